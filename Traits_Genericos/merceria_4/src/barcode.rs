@@ -0,0 +1,390 @@
+// Barcodes: GTINs on items, the barcode index and scanner input.
+//
+// A Gtin is an EAN-8, UPC-A or EAN-13 code with a valid check digit. UPC-A
+// codes are EAN-13 codes starting with 0, so both spellings of the same code
+// are equal and find the same lots. The shop keeps a barcode index from GTIN
+// to lots, next to the name index.
+//
+// `Scan::parse` reads raw scanner input. EAN-13 codes with a GS1 prefix from
+// 20 to 29 are in-store labels for variable-measure products, laid out as
+//
+//     PP IIIII VVVVV C
+//
+// with a 2 digit prefix, a 5 digit item reference, a 5 digit value and the
+// check digit. Prefixes 20 to 24 carry the price in cents, 25 to 29 the
+// weight in grams. The product is looked up under its label with the value
+// zeroed, which is the code the lots of that product are registered with.
+
+use crate::{GroceryShop, Item, Location, LotLocation, ShopError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BarcodeError {
+    /// The code is empty or holds something other than digits.
+    NotDigits(String),
+    /// GTINs have 8, 12 or 13 digits.
+    InvalidLength(usize),
+    /// The last digit does not match the rest of the code.
+    CheckDigit { expected: u8, found: u8 },
+}
+
+impl fmt::Display for BarcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BarcodeError::NotDigits(code) => write!(f, "'{}' is not a barcode", code),
+            BarcodeError::InvalidLength(length) => {
+                write!(f, "A barcode has 8, 12 or 13 digits, not {}", length)
+            }
+            BarcodeError::CheckDigit { expected, found } => {
+                write!(f, "Wrong check digit {} (expected {})", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BarcodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GtinKind {
+    Ean8,
+    UpcA,
+    Ean13,
+}
+
+/// A validated EAN-8, UPC-A or EAN-13 code.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Gtin {
+    /// 8 digits for EAN-8, 13 for UPC-A (padded with a 0) and EAN-13.
+    digits: String,
+}
+
+fn check_digit(payload: &str) -> u8 {
+    // GS1 check digit: weights 3 and 1 from the right, up to the next ten
+    let sum: u32 = payload
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| u32::from(digit - b'0') * if index % 2 == 0 { 3 } else { 1 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+impl Gtin {
+    pub fn kind(&self) -> GtinKind {
+        match self.digits.len() {
+            8 => GtinKind::Ean8,
+            _ if self.digits.starts_with('0') => GtinKind::UpcA,
+            _ => GtinKind::Ean13,
+        }
+    }
+
+    pub fn digits(&self) -> &str {
+        // The code as printed under the bars
+        match self.kind() {
+            GtinKind::UpcA => &self.digits[1..],
+            _ => &self.digits,
+        }
+    }
+
+    /// The GS1 prefix of an EAN-13 label from 20 to 29, if it is one.
+    pub fn variable_measure_prefix(&self) -> Option<u8> {
+        if self.kind() != GtinKind::Ean13 {
+            return None;
+        }
+        let prefix: u8 = self.digits[..2].parse().ok()?;
+        (20..=29).contains(&prefix).then_some(prefix)
+    }
+
+    fn with_payload(payload: &str) -> Gtin {
+        Gtin {
+            digits: format!("{}{}", payload, check_digit(payload)),
+        }
+    }
+}
+
+impl FromStr for Gtin {
+    type Err = BarcodeError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        // Digits only, of a GTIN length, ending in the right check digit
+        if text.is_empty() || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(BarcodeError::NotDigits(text.to_string()));
+        }
+        let digits = match text.len() {
+            8 | 13 => text.to_string(),
+            12 => format!("0{}", text),
+            length => return Err(BarcodeError::InvalidLength(length)),
+        };
+        let (payload, found) = digits.split_at(digits.len() - 1);
+        let found = found.as_bytes()[0] - b'0';
+        let expected = check_digit(payload);
+        if found != expected {
+            return Err(BarcodeError::CheckDigit { expected, found });
+        }
+        Ok(Gtin { digits })
+    }
+}
+
+impl TryFrom<String> for Gtin {
+    type Error = BarcodeError;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Gtin> for String {
+    fn from(gtin: Gtin) -> Self {
+        gtin.digits().to_string()
+    }
+}
+
+impl fmt::Display for Gtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.digits())
+    }
+}
+
+/// The price or weight printed in a variable-measure label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    /// Price in the minor units of the lot's currency.
+    Price {
+        minor_units: u32,
+    },
+    Weight {
+        grams: u32,
+    },
+}
+
+/// What a scanner read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scan {
+    /// A product sold by the unit.
+    Product(Gtin),
+    /// An in-store label: the product's code, with the value zeroed, and the value.
+    VariableMeasure { gtin: Gtin, measure: Measure },
+}
+
+impl Scan {
+    pub fn parse(input: &str) -> Result<Scan, BarcodeError> {
+        // Scanners add line endings, and may add an AIM symbology identifier
+        // (]E0 for EAN-13 and UPC-A, ]E4 for EAN-8) or send a 14 digit GTIN
+        let input = input.trim();
+        let input = input
+            .strip_prefix("]E0")
+            .or_else(|| input.strip_prefix("]E4"))
+            .unwrap_or(input);
+        let input = match input.strip_prefix('0') {
+            Some(rest) if input.len() == 14 => rest,
+            _ => input,
+        };
+        let gtin: Gtin = input.parse()?;
+
+        let Some(prefix) = gtin.variable_measure_prefix() else {
+            return Ok(Scan::Product(gtin));
+        };
+        let value: u32 = gtin.digits[7..12].parse().expect("digits");
+        let measure = if prefix < 25 {
+            Measure::Price { minor_units: value }
+        } else {
+            Measure::Weight { grams: value }
+        };
+        Ok(Scan::VariableMeasure {
+            gtin: Gtin::with_payload(&format!("{}00000", &gtin.digits[..7])),
+            measure,
+        })
+    }
+
+    /// The code the product is registered with.
+    pub fn gtin(&self) -> &Gtin {
+        match self {
+            Scan::Product(gtin) => gtin,
+            Scan::VariableMeasure { gtin, .. } => gtin,
+        }
+    }
+}
+
+/// A scan and the lots of the product it names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanMatch<'a, T: Item> {
+    pub scan: Scan,
+    pub lots: Vec<(Location, &'a T)>,
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn add_item_to_barcode_index(&mut self, item: &T, location: &Location) {
+        // Add a lot with a GTIN to the barcode index
+        let Some(gtin) = item.gtin() else {
+            return;
+        };
+        let lot = LotLocation {
+            location: location.clone(),
+            uuid: item.uuid(),
+        };
+        let lots = self.barcode_index.entry(gtin.clone()).or_default();
+        if !lots.contains(&lot) {
+            lots.push(lot);
+        }
+    }
+
+    pub fn remove_item_from_barcode_index(&mut self, item: &T, location: &Location) {
+        // Remove a lot from the barcode index
+        let Some(gtin) = item.gtin() else {
+            return;
+        };
+        if let Some(lots) = self.barcode_index.get_mut(gtin) {
+            lots.retain(|lot| !(lot.location == *location && lot.uuid == item.uuid()));
+            if lots.is_empty() {
+                self.barcode_index.remove(gtin);
+            }
+        }
+    }
+
+    pub(crate) fn collect_barcode_index(&self) -> HashMap<Gtin, Vec<LotLocation>> {
+        // Build the barcode index from the lots actually stored in the zones
+        let mut barcode_index: HashMap<Gtin, Vec<LotLocation>> = HashMap::new();
+        for (location, item) in self.iter() {
+            if let Some(gtin) = item.gtin() {
+                barcode_index
+                    .entry(gtin.clone())
+                    .or_default()
+                    .push(LotLocation {
+                        location,
+                        uuid: item.uuid(),
+                    });
+            }
+        }
+        barcode_index
+    }
+
+    pub fn get_items_by_gtin(&self, gtin: &Gtin) -> Vec<(Location, &T)> {
+        // Lots with that GTIN, through the barcode index
+        self.barcode_index
+            .get(gtin)
+            .into_iter()
+            .flatten()
+            .filter_map(|lot| {
+                self.get_item(&lot.location, &lot.uuid)
+                    .map(|item| (lot.location.clone(), item))
+            })
+            .collect()
+    }
+
+    pub fn scan(&self, input: &str) -> Result<ScanMatch<'_, T>, ShopError> {
+        // Read scanner input and find the lots of the product it names
+        let scan = Scan::parse(input)?;
+        let lots = self.get_items_by_gtin(scan.gtin());
+        Ok(ScanMatch { scan, lots })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::ExpirableItem;
+
+    #[test]
+    fn test_check_digits() {
+        let ean13: Gtin = "5601234567892".parse().unwrap();
+        assert_eq!(ean13.kind(), GtinKind::Ean13);
+        let ean8: Gtin = "96385074".parse().unwrap();
+        assert_eq!(ean8.kind(), GtinKind::Ean8);
+        let upc: Gtin = "036000291452".parse().unwrap();
+        assert_eq!(upc.kind(), GtinKind::UpcA);
+        assert_eq!(upc.to_string(), "036000291452");
+        // A UPC-A code written as an EAN-13 is the same code
+        assert_eq!("0036000291452".parse::<Gtin>().unwrap(), upc);
+
+        assert_eq!(
+            "5601234567891".parse::<Gtin>(),
+            Err(BarcodeError::CheckDigit {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!("12345".parse::<Gtin>(), Err(BarcodeError::InvalidLength(5)));
+        assert!(matches!(
+            "56012345678a0".parse::<Gtin>(),
+            Err(BarcodeError::NotDigits(_))
+        ));
+        assert_eq!(serde_json::to_string(&upc).unwrap(), "\"036000291452\"");
+        assert!(serde_json::from_str::<Gtin>("\"036000291453\"").is_err());
+    }
+
+    #[test]
+    fn test_scanner_input() {
+        assert_eq!(
+            Scan::parse("]E05601234567892\r\n").unwrap(),
+            Scan::Product("5601234567892".parse().unwrap())
+        );
+        assert_eq!(
+            Scan::parse("00036000291452").unwrap(),
+            Scan::Product("036000291452".parse().unwrap())
+        );
+
+        // Prefix 21: item 12345, 2,99 €
+        let label = Gtin::with_payload("211234500299");
+        assert_eq!(
+            Scan::parse(&label.to_string()).unwrap(),
+            Scan::VariableMeasure {
+                gtin: Gtin::with_payload("211234500000"),
+                measure: Measure::Price { minor_units: 299 },
+            }
+        );
+        // Prefix 28: item 00042, 1,250 kg
+        let label = Gtin::with_payload("280004201250");
+        assert_eq!(
+            Scan::parse(&label.to_string()).unwrap(),
+            Scan::VariableMeasure {
+                gtin: Gtin::with_payload("280004200000"),
+                measure: Measure::Weight { grams: 1250 },
+            }
+        );
+    }
+
+    #[test]
+    fn test_barcode_index_follows_the_lots() {
+        let milk = ExpirableItem {
+            gtin: Some("5601234567892".parse().unwrap()),
+            ..item("Leite", 4)
+        };
+        let cheese = ExpirableItem {
+            gtin: Some(Gtin::with_payload("280004200000")),
+            ..item("Queijo", 4)
+        };
+        let mut shop = shop_with(vec![
+            (milk.clone(), location(0, 0, 0)),
+            (cheese, location(0, 0, 1)),
+        ]);
+
+        let found = shop.scan("5601234567892").unwrap();
+        assert_eq!(found.lots, vec![(location(0, 0, 0), &milk)]);
+        let found = shop
+            .scan(&Gtin::with_payload("280004201250").to_string())
+            .unwrap();
+        assert!(matches!(found.scan, Scan::VariableMeasure { .. }));
+        assert_eq!(shop.name_of(found.lots[0].1), "Queijo");
+
+        shop.move_item(location(0, 0, 0), &milk.uuid, &location(1, 0, 0))
+            .unwrap();
+        let gtin = milk.gtin.clone().unwrap();
+        assert_eq!(shop.get_items_by_gtin(&gtin)[0].0, location(1, 0, 0));
+        if let Some(mut lot) = shop.get_item_mut(&location(1, 0, 0), &milk.uuid) {
+            lot.gtin = Some("96385074".parse().unwrap());
+            lot.commit().unwrap();
+        }
+        assert!(shop.get_items_by_gtin(&gtin).is_empty());
+        assert_eq!(shop.scan("96385074").unwrap().lots.len(), 1);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+
+        shop.remove_item(&location(1, 0, 0), &milk.uuid).unwrap();
+        assert!(shop.scan("96385074").unwrap().lots.is_empty());
+        assert!(shop.scan("123").is_err());
+    }
+}
@@ -0,0 +1,312 @@
+// Capacity limits (units per zone, weight and volume per rack) and the
+// utilisation report.
+//
+// Items that do not report a unit weight or volume count as zero towards the
+// rack limits.
+
+use crate::{CapacityLimit, GroceryShop, Item, Location, Rack, ShopError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RackUtilisation {
+    pub rack_id: u32,
+    pub occupied_zones: u32,
+    pub zones: u32,
+    pub units: u64,
+    /// `None` when at least one zone has no unit limit.
+    pub max_units: Option<u64>,
+    pub weight_grams: u64,
+    pub max_weight_grams: Option<u64>,
+    pub volume_ml: u64,
+    pub max_volume_ml: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowUtilisation {
+    pub row_id: u32,
+    pub occupied_zones: u32,
+    pub zones: u32,
+    pub units: u64,
+    pub weight_grams: u64,
+    pub volume_ml: u64,
+    pub racks: Vec<RackUtilisation>,
+}
+
+impl<T: Item> Rack<T> {
+    pub fn load(&self) -> (u64, u64) {
+        // Total weight (g) and volume (ml) of everything stored in the rack
+        self.zones
+            .values()
+            .flat_map(|zone| &zone.lots)
+            .fold((0, 0), |(weight, volume), item| {
+                let (item_weight, item_volume) = unit_load(item, u64::from(item.quantity()));
+                (weight + item_weight, volume + item_volume)
+            })
+    }
+}
+
+fn unit_load<T: Item>(item: &T, units: u64) -> (u64, u64) {
+    // Weight and volume of `units` units of the item
+    let weight = u64::from(item.unit_weight_grams().unwrap_or(0)) * units;
+    let volume = u64::from(item.unit_volume_ml().unwrap_or(0)) * units;
+    (weight, volume)
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub(crate) fn check_capacity(
+        &self,
+        location: &Location,
+        item: &T,
+        zone_units: u64,
+        added_units: u64,
+    ) -> Result<(), ShopError> {
+        // Check that the zone can hold `zone_units` units of the item and that
+        // its rack can take `added_units` more units of it
+        let rack = self
+            .rows
+            .get(&location.row_id)
+            .and_then(|row| row.racks.get(&location.rack_id))
+            .ok_or_else(|| ShopError::LocationNotFound(location.clone()))?;
+        let zone = rack
+            .zones
+            .get(&location.zone_id)
+            .ok_or_else(|| ShopError::LocationNotFound(location.clone()))?;
+
+        let exceeded = |limit, max, requested| ShopError::CapacityExceeded {
+            location: location.clone(),
+            limit,
+            max,
+            requested,
+        };
+
+        if let Some(max_units) = zone.max_units {
+            if zone_units > u64::from(max_units) {
+                return Err(exceeded(
+                    CapacityLimit::ZoneUnits,
+                    u64::from(max_units),
+                    zone_units,
+                ));
+            }
+        }
+
+        let (weight, volume) = rack.load();
+        let (added_weight, added_volume) = unit_load(item, added_units);
+        if let Some(max_weight) = rack.max_weight_grams {
+            if weight + added_weight > max_weight {
+                return Err(exceeded(
+                    CapacityLimit::RackWeight,
+                    max_weight,
+                    weight + added_weight,
+                ));
+            }
+        }
+        if let Some(max_volume) = rack.max_volume_ml {
+            if volume + added_volume > max_volume {
+                return Err(exceeded(
+                    CapacityLimit::RackVolume,
+                    max_volume,
+                    volume + added_volume,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn utilisation(&self) -> Vec<RowUtilisation> {
+        // Per row and per rack usage, in id order
+        self.rows
+            .iter()
+            .map(|(row_id, row)| {
+                let racks: Vec<RackUtilisation> = row
+                    .racks
+                    .iter()
+                    .map(|(rack_id, rack)| {
+                        let (weight_grams, volume_ml) = rack.load();
+                        RackUtilisation {
+                            rack_id: *rack_id,
+                            occupied_zones: rack
+                                .zones
+                                .values()
+                                .filter(|zone| !zone.lots.is_empty())
+                                .count() as u32,
+                            zones: rack.zones.len() as u32,
+                            units: rack.zones.values().map(|zone| zone.units()).sum(),
+                            max_units: rack
+                                .zones
+                                .values()
+                                .map(|zone| zone.max_units.map(u64::from))
+                                .sum(),
+                            weight_grams,
+                            max_weight_grams: rack.max_weight_grams,
+                            volume_ml,
+                            max_volume_ml: rack.max_volume_ml,
+                        }
+                    })
+                    .collect();
+
+                RowUtilisation {
+                    row_id: *row_id,
+                    occupied_zones: racks.iter().map(|rack| rack.occupied_zones).sum(),
+                    zones: racks.iter().map(|rack| rack.zones).sum(),
+                    units: racks.iter().map(|rack| rack.units).sum(),
+                    weight_grams: racks.iter().map(|rack| rack.weight_grams).sum(),
+                    volume_ml: racks.iter().map(|rack| rack.volume_ml).sum(),
+                    racks,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location};
+    use crate::{Dimensions, ExpirableItem, LayoutBuilder};
+
+    fn water(quantity: u32) -> ExpirableItem {
+        // 1.5 l bottles
+        ExpirableItem {
+            dimensions: Some(Dimensions {
+                weight_grams: 1500,
+                volume_ml: 1500,
+            }),
+            ..item("Water", quantity)
+        }
+    }
+
+    fn limited_shop() -> GroceryShop<ExpirableItem> {
+        LayoutBuilder::new()
+            .rows(1)
+            .racks_per_row(2)
+            .zones_per_rack(2)
+            .zone_unit_capacity(10)
+            .rack_weight_limit(20_000)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_zone_unit_capacity() {
+        let mut shop = limited_shop();
+
+        assert_eq!(
+            shop.add_item(water(11), &location(0, 0, 0)),
+            Err(ShopError::CapacityExceeded {
+                location: location(0, 0, 0),
+                limit: CapacityLimit::ZoneUnits,
+                max: 10,
+                requested: 11,
+            })
+        );
+        let item = water(8);
+        let uuid = item.uuid;
+        shop.add_item(item, &location(0, 0, 0)).unwrap();
+        assert!(shop.restock(&location(0, 0, 0), &uuid, 3).is_err());
+        assert_eq!(shop.restock(&location(0, 0, 0), &uuid, 2), Ok(10));
+    }
+
+    #[test]
+    fn test_rack_weight_limit() {
+        let mut shop = limited_shop();
+        let (heavy, light) = (water(10), water(3));
+        let (heavy_uuid, light_uuid) = (heavy.uuid, light.uuid);
+        shop.add_item(heavy, &location(0, 0, 0)).unwrap();
+        shop.add_item(light, &location(0, 1, 0)).unwrap();
+
+        // 15 kg already in rack 0, 4.5 kg more is fine but 6 kg is not
+        assert_eq!(
+            shop.add_item(water(4), &location(0, 0, 1)),
+            Err(ShopError::CapacityExceeded {
+                location: location(0, 0, 1),
+                limit: CapacityLimit::RackWeight,
+                max: 20_000,
+                requested: 21_000,
+            })
+        );
+        shop.move_item(location(0, 1, 0), &light_uuid, &location(0, 0, 1))
+            .unwrap();
+
+        // Moving inside the same rack does not add weight: 19.5 kg are stored
+        // in rack 0, so counting the light lot twice would break the limit
+        let zone = shop.get_zone_mut(&location(0, 0, 0)).unwrap();
+        zone.max_lots = 2;
+        zone.max_units = Some(20);
+        shop.move_item(location(0, 0, 1), &light_uuid, &location(0, 0, 0))
+            .unwrap();
+        assert_eq!(
+            shop.get_location_by_uuid(&light_uuid),
+            Some(&location(0, 0, 0))
+        );
+        assert!(shop
+            .move_item(location(0, 0, 0), &heavy_uuid, &location(0, 1, 1))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_guarded_edits_are_checked() {
+        let mut shop = limited_shop();
+        let item = water(8);
+        let uuid = item.uuid;
+        shop.add_item(item.clone(), &location(0, 0, 0)).unwrap();
+
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &uuid).unwrap();
+        lot.quantity = 11;
+        assert_eq!(
+            lot.commit(),
+            Err(ShopError::CapacityExceeded {
+                location: location(0, 0, 0),
+                limit: CapacityLimit::ZoneUnits,
+                max: 10,
+                requested: 11,
+            })
+        );
+
+        // 8 bottles of 3 kg weigh more than the rack takes
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &uuid).unwrap();
+        lot.dimensions = Some(Dimensions {
+            weight_grams: 3000,
+            volume_ml: 1500,
+        });
+        assert_eq!(
+            lot.commit(),
+            Err(ShopError::CapacityExceeded {
+                location: location(0, 0, 0),
+                limit: CapacityLimit::RackWeight,
+                max: 20_000,
+                requested: 24_000,
+            })
+        );
+        assert_eq!(shop.get_item(&location(0, 0, 0), &uuid), Some(&item));
+
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &uuid).unwrap();
+        lot.quantity = 10;
+        lot.commit().unwrap();
+        assert_eq!(shop.utilisation()[0].racks[0].units, 10);
+    }
+
+    #[test]
+    fn test_utilisation_report() {
+        let mut shop = limited_shop();
+        shop.add_item(water(4), &location(0, 1, 1)).unwrap();
+
+        let report = shop.utilisation();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].occupied_zones, 1);
+        assert_eq!(report[0].zones, 4);
+        assert_eq!(report[0].weight_grams, 6000);
+        assert_eq!(
+            report[0].racks[1],
+            RackUtilisation {
+                rack_id: 1,
+                occupied_zones: 1,
+                zones: 2,
+                units: 4,
+                max_units: Some(20),
+                weight_grams: 6000,
+                max_weight_grams: Some(20_000),
+                volume_ml: 6000,
+                max_volume_ml: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,523 @@
+// The product catalogue: master data kept apart from the stock lots.
+//
+// A Product holds what is true of every lot of it: SKU, name, category, unit
+// and regular price. A lot of a product only records the SKU next to its own
+// quantity, expiration date and uuid (`Article::Product`); a loose article
+// without a SKU still carries its own name and price (`Article::Loose`).
+//
+// The shop resolves the name and regular price of a lot through the catalogue
+// (`name_of`, `regular_price_of`), so the name index, stock levels, picking,
+// queries, reports and receipts all follow it. Renaming a product re-keys the
+// name index, and repricing it changes the one price every lot of it sells at
+// outside its own promotions.
+//
+// A ProductKey names a product for stock levels, reorder policies and
+// purchase orders: its SKU, or the name of a loose article. Keying those by
+// display name would mix up a product and a loose article that share a name,
+// and lose track of a product once it is renamed.
+
+use crate::{GroceryShop, Item, Location, Money, ShopError};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Stock keeping unit: the shop's own code for a product.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Sku(String);
+
+impl Sku {
+    pub fn new(code: &str) -> Self {
+        Sku(code.trim().to_uppercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Sku {
+    fn from(code: &str) -> Self {
+        Sku::new(code)
+    }
+}
+
+impl fmt::Display for Sku {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What a lot is stock of.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Article {
+    /// A catalogue product, named and priced by the catalogue.
+    Product { sku: Sku },
+    /// An article outside the catalogue, with its own name and regular price.
+    Loose { name: String, price: Money },
+}
+
+impl Article {
+    pub fn product(sku: &str) -> Self {
+        Article::Product { sku: Sku::new(sku) }
+    }
+
+    pub fn loose(name: &str, price: Money) -> Self {
+        Article::Loose {
+            name: name.to_string(),
+            price,
+        }
+    }
+
+    pub fn sku(&self) -> Option<&Sku> {
+        match self {
+            Article::Product { sku } => Some(sku),
+            Article::Loose { .. } => None,
+        }
+    }
+
+    /// Whether both are the same product, or loose articles of the same
+    /// name, whatever price a loose one was last listed at.
+    pub fn is_same_as(&self, other: &Article) -> bool {
+        self.key() == other.key()
+    }
+
+    pub fn key(&self) -> ProductKey {
+        match self {
+            Article::Product { sku } => ProductKey::Sku(sku.clone()),
+            Article::Loose { name, .. } => ProductKey::Loose(name.clone()),
+        }
+    }
+}
+
+/// A catalogue product, or a loose article by name. Written as `sku:CODE` or
+/// `loose:NAME` in snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum ProductKey {
+    Sku(Sku),
+    Loose(String),
+}
+
+impl ProductKey {
+    pub fn sku(code: &str) -> Self {
+        ProductKey::Sku(Sku::new(code))
+    }
+
+    pub fn loose(name: &str) -> Self {
+        ProductKey::Loose(name.to_string())
+    }
+}
+
+impl fmt::Display for ProductKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProductKey::Sku(sku) => write!(f, "sku:{}", sku),
+            ProductKey::Loose(name) => write!(f, "loose:{}", name),
+        }
+    }
+}
+
+impl From<ProductKey> for String {
+    fn from(key: ProductKey) -> Self {
+        key.to_string()
+    }
+}
+
+impl TryFrom<String> for ProductKey {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        if let Some(code) = text.strip_prefix("sku:") {
+            Ok(ProductKey::sku(code))
+        } else if let Some(name) = text.strip_prefix("loose:") {
+            Ok(ProductKey::loose(name))
+        } else {
+            Err(format!("Unknown product '{}'", text))
+        }
+    }
+}
+
+/// How a product is sold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Unit {
+    Piece,
+    Kilogram,
+    Litre,
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "piece" => Ok(Unit::Piece),
+            "kg" => Ok(Unit::Kilogram),
+            "l" => Ok(Unit::Litre),
+            _ => Err(format!("Unknown unit '{}'", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Product {
+    pub sku: Sku,
+    pub name: String,
+    pub category: String,
+    pub unit: Unit,
+    pub price: Money,
+}
+
+impl Product {
+    pub fn new(sku: &str, name: &str, price: Money) -> Self {
+        Product {
+            sku: Sku::new(sku),
+            name: name.to_string(),
+            category: String::new(),
+            unit: Unit::Piece,
+            price,
+        }
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.category = category.to_string();
+        self
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+}
+
+/// Every product the shop sells, by SKU.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Catalogue {
+    products: BTreeMap<Sku, Product>,
+}
+
+impl Catalogue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, sku: &Sku) -> Option<&Product> {
+        self.products.get(sku)
+    }
+
+    pub fn products(&self) -> impl Iterator<Item = &Product> {
+        self.products.values()
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Vec<&Product> {
+        self.products
+            .values()
+            .filter(|product| product.name == name)
+            .collect()
+    }
+
+    fn get_mut(&mut self, sku: &Sku) -> Result<&mut Product, ShopError> {
+        self.products
+            .get_mut(sku)
+            .ok_or_else(|| ShopError::UnknownSku(sku.clone()))
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn add_product(&mut self, product: Product) -> Result<(), ShopError> {
+        // Add a product to the catalogue, refusing a SKU already in use
+        if self.catalogue.products.contains_key(&product.sku) {
+            return Err(ShopError::DuplicateSku(product.sku));
+        }
+        self.catalogue.products.insert(product.sku.clone(), product);
+        Ok(())
+    }
+
+    pub fn product(&self, sku: &Sku) -> Option<&Product> {
+        self.catalogue.get(sku)
+    }
+
+    pub fn product_of(&self, item: &T) -> Option<&Product> {
+        item.sku().and_then(|sku| self.catalogue.get(sku))
+    }
+
+    pub fn name_of<'a>(&'a self, item: &'a T) -> &'a str {
+        // The product name of a lot, or the name of a loose article. A SKU
+        // missing from the catalogue names the lot itself.
+        match item.article() {
+            Article::Product { sku } => self
+                .catalogue
+                .get(sku)
+                .map_or(sku.as_str(), |product| product.name.as_str()),
+            Article::Loose { name, .. } => name,
+        }
+    }
+
+    pub fn product_name<'a>(&'a self, product: &'a ProductKey) -> &'a str {
+        // The name a product key stands for, like `name_of` for its lots
+        match product {
+            ProductKey::Sku(sku) => self
+                .catalogue
+                .get(sku)
+                .map_or(sku.as_str(), |product| product.name.as_str()),
+            ProductKey::Loose(name) => name,
+        }
+    }
+
+    pub fn units_of(&self, product: &ProductKey) -> u64 {
+        // Units in stock over every lot of a product, found through the name
+        // index and told apart from lots of another product of the same name
+        self.get_items_by_name(self.product_name(product))
+            .iter()
+            .filter(|item| item.article().key() == *product)
+            .map(|item| u64::from(item.quantity()))
+            .sum()
+    }
+
+    pub(crate) fn check_product(&self, product: &ProductKey) -> Result<(), ShopError> {
+        // Refuse a SKU missing from the catalogue
+        match product {
+            ProductKey::Sku(sku) if self.catalogue.get(sku).is_none() => {
+                Err(ShopError::UnknownSku(sku.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn regular_price_of(&self, item: &T) -> Result<Money, ShopError> {
+        // The product price of a lot, or the price of a loose article,
+        // including a scheduled change that has come due
+        match item.article() {
+            Article::Product { sku } => self
+                .catalogue
+                .get(sku)
+                .map(|product| product.price)
+                .ok_or_else(|| ShopError::UnknownSku(sku.clone())),
+            Article::Loose { price, .. } => Ok(self
+                .prices
+                .regular_price_at(&item.uuid(), Utc::now())
+                .unwrap_or(*price)),
+        }
+    }
+
+    pub fn lots_of(&self, sku: &Sku) -> Vec<(Location, &T)> {
+        // Every stored lot of a product
+        self.iter()
+            .filter(|(_, item)| item.sku() == Some(sku))
+            .collect()
+    }
+
+    pub fn rename_product(&mut self, sku: &Sku, name: &str) -> Result<(), ShopError> {
+        // Rename a product, moving its lots to the new name in the name index
+        self.catalogue.get_mut(sku)?;
+        let lots: Vec<(Location, T)> = self
+            .lots_of(sku)
+            .into_iter()
+            .map(|(location, item)| (location, item.clone()))
+            .collect();
+        for (location, item) in &lots {
+            self.remove_item_from_name_index(item, location);
+        }
+        self.catalogue.get_mut(sku)?.name = name.to_string();
+        for (location, item) in &lots {
+            self.add_item_to_name_index(item, location);
+        }
+        Ok(())
+    }
+
+    pub fn rename_item(&mut self, uuid: &Uuid, name: &str) -> Result<(), ShopError> {
+        // Rename a loose article; a lot of a product is named by the catalogue
+        let location = self
+            .get_location_by_uuid(uuid)
+            .cloned()
+            .ok_or(ShopError::UnknownUuid(*uuid))?;
+        let mut item = self
+            .get_item_mut(&location, uuid)
+            .expect("indexed lot exists");
+        match item.article_mut() {
+            Article::Product { sku } => {
+                return Err(ShopError::CatalogueProduct {
+                    uuid: *uuid,
+                    sku: sku.clone(),
+                })
+            }
+            Article::Loose { name: own, .. } => *own = name.to_string(),
+        }
+        item.commit()
+    }
+
+    pub fn reprice_product(&mut self, sku: &Sku, price: Money) -> Result<(), ShopError> {
+        // Change the regular price every lot of the product sells at
+        self.catalogue.get_mut(sku)?.price = price;
+        Ok(())
+    }
+
+    pub fn set_product_category(&mut self, sku: &Sku, category: &str) -> Result<(), ShopError> {
+        self.catalogue.get_mut(sku)?.category = category.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{location, product_lot, shop_with};
+    use crate::{AddMode, Checkout, ExpirableItem, Filter, Query};
+
+    fn shop() -> (GroceryShop<ExpirableItem>, ExpirableItem, ExpirableItem) {
+        // Two lots of one product; the product has to be listed first
+        let mut shop = shop_with(vec![]);
+        shop.add_product(
+            Product::new("lei-001", "Leite", Money::eur(95))
+                .category("Laticínios")
+                .unit(Unit::Litre),
+        )
+        .unwrap();
+        let first = product_lot("LEI-001", 6);
+        let second = product_lot("LEI-001", 4);
+        shop.add_item(first.clone(), &location(0, 0, 0)).unwrap();
+        shop.add_item(second.clone(), &location(1, 0, 0)).unwrap();
+        (shop, first, second)
+    }
+
+    #[test]
+    fn test_lots_take_name_and_price_from_the_catalogue() {
+        let (mut shop, first, _) = shop();
+        let sku = Sku::new("LEI-001");
+
+        let stored = shop.get_item_by_uuid(&first.uuid).unwrap();
+        assert_eq!(shop.name_of(stored), "Leite");
+        assert_eq!(shop.regular_price_of(stored), Ok(Money::eur(95)));
+        assert_eq!(shop.product_of(stored).unwrap().unit, Unit::Litre);
+        assert_eq!(shop.stock_of("Leite"), 10);
+        assert_eq!(shop.lots_of(&sku).len(), 2);
+
+        // The lot itself only records its SKU
+        let json = serde_json::to_value(stored).unwrap();
+        assert_eq!(json["sku"], "LEI-001");
+        assert!(json.get("name").is_none() && json.get("price").is_none());
+
+        assert_eq!(
+            shop.add_product(Product::new("LEI-001", "Outro", Money::eur(1))),
+            Err(ShopError::DuplicateSku(sku.clone()))
+        );
+        assert_eq!(
+            shop.add_item(product_lot("NONE", 1), &location(2, 0, 0)),
+            Err(ShopError::UnknownSku(Sku::new("NONE")))
+        );
+        assert_eq!(shop.catalogue.find_by_name("Leite").len(), 1);
+    }
+
+    #[test]
+    fn test_renaming_a_product_renames_every_lot() {
+        let (mut shop, first, _) = shop();
+        let sku = Sku::new("LEI-001");
+
+        shop.rename_product(&sku, "Leite Meio-Gordo").unwrap();
+        assert_eq!(shop.stock_of("Leite"), 0);
+        assert_eq!(shop.stock_of("Leite Meio-Gordo"), 10);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+        let query: Query = "name = \"Leite Meio-Gordo\"".parse().unwrap();
+        assert_eq!(shop.query(&query).count(), 2);
+        assert!(Filter::NameContains("meio".to_string()).matches(
+            &shop,
+            &location(0, 0, 0),
+            &first
+        ));
+        assert_eq!(shop.search("meio", 0).len(), 2);
+
+        let sale = Checkout::new().add("Leite Meio-Gordo", 1).commit(&mut shop);
+        let line = &sale.unwrap().lines[0];
+        assert_eq!(line.name, "Leite Meio-Gordo");
+        assert_eq!(line.sku, Some(sku.clone()));
+
+        // A lot of a product is named by the catalogue only
+        assert_eq!(
+            shop.rename_item(&first.uuid, "Milk"),
+            Err(ShopError::CatalogueProduct {
+                uuid: first.uuid,
+                sku: sku.clone()
+            })
+        );
+        assert_eq!(
+            shop.rename_product(&Sku::new("NONE"), "X"),
+            Err(ShopError::UnknownSku(Sku::new("NONE")))
+        );
+    }
+
+    #[test]
+    fn test_repricing_a_product_changes_one_price() {
+        let (mut shop, first, second) = shop();
+        let sku = Sku::new("LEI-001");
+        let now = chrono::Utc::now();
+        shop.add_promotion(
+            &second.uuid,
+            Money::eur(80),
+            now,
+            now + chrono::Duration::days(1),
+            "week deal",
+        )
+        .unwrap();
+
+        shop.reprice_product(&sku, Money::eur(105)).unwrap();
+        assert_eq!(shop.product(&sku).unwrap().price, Money::eur(105));
+        assert_eq!(shop.current_price(&first.uuid), Some(Money::eur(105)));
+        assert_eq!(shop.current_price(&second.uuid), Some(Money::eur(80)));
+        assert_eq!(
+            shop.price_at(&second.uuid, now + chrono::Duration::days(2)),
+            Some(Money::eur(105))
+        );
+        // 6 units at the new price and 4 still on promotion
+        assert_eq!(shop.total_value(), Ok(Money::eur(6 * 105 + 4 * 80)));
+
+        assert_eq!(
+            shop.set_price(&first.uuid, Money::eur(1), "typo"),
+            Err(ShopError::CatalogueProduct {
+                uuid: first.uuid,
+                sku
+            })
+        );
+    }
+
+    #[test]
+    fn test_lots_of_one_product_merge() {
+        let (mut shop, first, _) = shop();
+        let more = ExpirableItem {
+            quantity: 3,
+            uuid: uuid::Uuid::new_v4(),
+            ..first.clone()
+        };
+        shop.add_item_with_mode(more, &location(0, 0, 0), AddMode::Merge)
+            .unwrap();
+        assert_eq!(shop.get_item_by_uuid(&first.uuid).unwrap().quantity, 9);
+
+        // A loose article is a different lot, whatever its name
+        let loose = ExpirableItem {
+            article: Article::loose("Leite", Money::eur(95)),
+            uuid: uuid::Uuid::new_v4(),
+            ..first.clone()
+        };
+        assert!(shop
+            .add_item_with_mode(loose, &location(0, 0, 0), AddMode::Merge)
+            .is_err());
+    }
+
+    #[test]
+    fn test_changing_the_sku_of_a_lot_reindexes_it() {
+        let (mut shop, first, _) = shop();
+        shop.add_product(Product::new("NAT-002", "Natas", Money::eur(120)))
+            .unwrap();
+
+        if let Some(mut stored) = shop.get_item_mut(&location(0, 0, 0), &first.uuid) {
+            stored.article = Article::product("NAT-002");
+            stored.commit().unwrap();
+        }
+        assert_eq!(shop.stock_of("Leite"), 4);
+        assert_eq!(shop.stock_of("Natas"), 6);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+}
@@ -0,0 +1,592 @@
+// Selling from the shop.
+//
+// A Checkout holds the pricing rules (product categories, VAT rate per
+// category, quantity discounts per product) and a basket of products, asked
+// for by name or by lot uuid. Committing it takes the stock in one
+// transaction, names resolving to lots in first-expired-first-out order, and
+// returns the Sale with one line per lot taken. The shop keeps every sale it
+// made, so a sale is voided by its id and only ever restores what it took.
+//
+// Lines are priced at the lot's current price, promotions included. Prices
+// include VAT: the receipt shows how much of the total is VAT at each rate,
+// worked out once from everything sold at that rate so rounding per line
+// cannot add up.
+// A quantity discount applies when the basket holds at least `min_quantity`
+// units of the product over all its lines. Products without a category in
+// the checkout use their category in the shop's catalogue, if they have one.
+
+use crate::expiry::PickLine;
+use crate::{GroceryShop, Item, Location, Money, MovementReason, ShopError, Sku};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// VAT rate used for products without a category, in percent.
+pub const DEFAULT_VAT_RATE: u32 = 23;
+
+/// `percent` off every unit once `min_quantity` units are bought.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantityDiscount {
+    pub min_quantity: u32,
+    pub percent: u32,
+}
+
+/// What a basket line asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BasketProduct {
+    /// Any lots with that name, earliest-expiring first.
+    Name(String),
+    /// That very lot.
+    Lot(Uuid),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasketLine {
+    pub product: BasketProduct,
+    pub quantity: u32,
+}
+
+/// `quantity` units sold from the lot `uuid`, named as the shop knew it then.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaleLine {
+    pub name: String,
+    /// The catalogue product sold, if the lot is stock of one.
+    #[serde(default)]
+    pub sku: Option<Sku>,
+    pub uuid: Uuid,
+    pub location: Location,
+    pub quantity: u32,
+    pub unit_price: Money,
+    pub discount_percent: u32,
+    pub discount: Money,
+    /// What the line costs, discount taken off and VAT included.
+    pub total: Money,
+    pub vat_rate: u32,
+}
+
+/// VAT included in the lines taxed at `rate` percent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VatSummary {
+    pub rate: u32,
+    pub gross: Money,
+    pub vat: Money,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sale {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub lines: Vec<SaleLine>,
+    pub total: Money,
+    pub vat: Vec<VatSummary>,
+}
+
+impl Sale {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a sale always serializes")
+    }
+
+    pub fn to_receipt(&self) -> String {
+        // Plain-text receipt, one line per lot plus discounts, total and VAT
+        let row = |label: String, amount: String| format!("{:<30}{:>12}\n", label, amount);
+        let mut receipt = format!(
+            "Sale {}\n{}\n",
+            self.id,
+            self.timestamp.format("%Y-%m-%d %H:%M")
+        );
+        for line in &self.lines {
+            let gross = Money::new(
+                line.total.minor_units() + line.discount.minor_units(),
+                line.total.currency(),
+            );
+            receipt.push_str(&row(
+                format!("{} {} x {}", line.name, line.quantity, line.unit_price),
+                gross.to_string(),
+            ));
+            if line.discount_percent > 0 {
+                receipt.push_str(&row(
+                    format!("  {}% off", line.discount_percent),
+                    format!("-{}", line.discount),
+                ));
+            }
+        }
+        receipt.push_str(&format!("{}\n", "-".repeat(42)));
+        receipt.push_str(&row("Total".to_string(), self.total.to_string()));
+        for summary in &self.vat {
+            receipt.push_str(&row(
+                format!("VAT {}% of {}", summary.rate, summary.gross),
+                summary.vat.to_string(),
+            ));
+        }
+        receipt
+    }
+}
+
+/// A lot taken for a sale, before pricing.
+struct Taken {
+    pick: PickLine,
+    name: String,
+    sku: Option<Sku>,
+    category: Option<String>,
+    unit_price: Money,
+}
+
+#[derive(Debug, Clone)]
+pub struct Checkout {
+    basket: Vec<BasketLine>,
+    categories: HashMap<String, String>,
+    vat_rates: HashMap<String, u32>,
+    default_vat_rate: u32,
+    discounts: HashMap<String, Vec<QuantityDiscount>>,
+}
+
+impl Default for Checkout {
+    fn default() -> Self {
+        Checkout {
+            basket: Vec::new(),
+            categories: HashMap::new(),
+            vat_rates: HashMap::new(),
+            default_vat_rate: DEFAULT_VAT_RATE,
+            discounts: HashMap::new(),
+        }
+    }
+}
+
+impl Checkout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put the product `name` in a VAT category.
+    pub fn category(mut self, name: &str, category: &str) -> Self {
+        self.categories
+            .insert(name.to_string(), category.to_string());
+        self
+    }
+
+    /// VAT rate, in percent, of a category.
+    pub fn vat_rate(mut self, category: &str, percent: u32) -> Self {
+        self.vat_rates.insert(category.to_string(), percent);
+        self
+    }
+
+    /// VAT rate, in percent, of products without a category. Defaults to 23.
+    pub fn default_vat_rate(mut self, percent: u32) -> Self {
+        self.default_vat_rate = percent;
+        self
+    }
+
+    /// Take `percent` (at most 100) off the product `name` from `min_quantity` units on.
+    pub fn quantity_discount(mut self, name: &str, min_quantity: u32, percent: u32) -> Self {
+        self.discounts
+            .entry(name.to_string())
+            .or_default()
+            .push(QuantityDiscount {
+                min_quantity,
+                percent: percent.min(100),
+            });
+        self
+    }
+
+    pub fn add(&mut self, name: &str, quantity: u32) -> &mut Self {
+        self.basket.push(BasketLine {
+            product: BasketProduct::Name(name.to_string()),
+            quantity,
+        });
+        self
+    }
+
+    pub fn add_lot(&mut self, uuid: Uuid, quantity: u32) -> &mut Self {
+        self.basket.push(BasketLine {
+            product: BasketProduct::Lot(uuid),
+            quantity,
+        });
+        self
+    }
+
+    pub fn basket(&self) -> &[BasketLine] {
+        &self.basket
+    }
+
+    pub fn clear(&mut self) {
+        self.basket.clear();
+    }
+
+    fn vat_rate_of(&self, name: &str, category: Option<&String>) -> u32 {
+        // The checkout's category for the product wins over the catalogue's
+        self.categories
+            .get(name)
+            .or(category)
+            .and_then(|category| self.vat_rates.get(category))
+            .copied()
+            .unwrap_or(self.default_vat_rate)
+    }
+
+    fn discount_of(&self, name: &str, quantity: u64) -> u32 {
+        // The best discount the quantity qualifies for
+        self.discounts
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|discount| u64::from(discount.min_quantity) <= quantity)
+            .map(|discount| discount.percent)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn price(&self, id: Uuid, taken: Vec<Taken>) -> Result<Sale, ShopError> {
+        // Turn the lots taken into priced sale lines and totals
+        let mut units: HashMap<&str, u64> = HashMap::new();
+        for lot in &taken {
+            *units.entry(&lot.name).or_default() += u64::from(lot.pick.quantity);
+        }
+
+        let mut lines = Vec::new();
+        for lot in &taken {
+            let discount_percent = self.discount_of(&lot.name, units[lot.name.as_str()]);
+            let vat_rate = self.vat_rate_of(&lot.name, lot.category.as_ref());
+            let gross = lot.unit_price.checked_mul(i64::from(lot.pick.quantity))?;
+            let discount = gross.checked_mul_ratio(i64::from(discount_percent), 100)?;
+            let total = gross.checked_sub(discount)?;
+            lines.push(SaleLine {
+                name: lot.name.clone(),
+                sku: lot.sku.clone(),
+                uuid: lot.pick.uuid,
+                location: lot.pick.location.clone(),
+                quantity: lot.pick.quantity,
+                unit_price: lot.unit_price,
+                discount_percent,
+                discount,
+                total,
+                vat_rate,
+            });
+        }
+
+        // A basket of zero quantities takes nothing
+        let currency = lines
+            .first()
+            .map(|line: &SaleLine| line.total.currency())
+            .ok_or(ShopError::EmptyBasket)?;
+        let mut by_rate: BTreeMap<u32, Money> = BTreeMap::new();
+        for line in &lines {
+            let gross = by_rate
+                .entry(line.vat_rate)
+                .or_insert(Money::zero(currency));
+            *gross = gross.checked_add(line.total)?;
+        }
+        let mut vat = Vec::new();
+        for (rate, gross) in by_rate {
+            vat.push(VatSummary {
+                rate,
+                gross,
+                vat: gross.checked_mul_ratio(i64::from(rate), 100 + i64::from(rate))?,
+            });
+        }
+        Ok(Sale {
+            id,
+            timestamp: Utc::now(),
+            total: Money::sum(currency, lines.iter().map(|line| line.total))?,
+            vat,
+            lines,
+        })
+    }
+
+    pub fn commit<T: Item>(&mut self, shop: &mut GroceryShop<T>) -> Result<Sale, ShopError> {
+        // Take every basket line from stock and price the sale, or change nothing
+        // if any line cannot be served. The basket is emptied on success.
+        if self.basket.is_empty() {
+            return Err(ShopError::EmptyBasket);
+        }
+        let id = Uuid::new_v4();
+        let sale = shop.transaction(|tx| {
+            let mut taken = Vec::new();
+            for line in &self.basket {
+                let picks = match &line.product {
+                    BasketProduct::Name(name) => tx.shop().plan_pick(name, line.quantity)?,
+                    BasketProduct::Lot(uuid) => vec![PickLine {
+                        location: tx
+                            .shop()
+                            .get_location_by_uuid(uuid)
+                            .cloned()
+                            .ok_or(ShopError::UnknownUuid(*uuid))?,
+                        uuid: *uuid,
+                        quantity: line.quantity,
+                    }],
+                };
+                for pick in picks {
+                    let item = tx
+                        .shop()
+                        .get_item(&pick.location, &pick.uuid)
+                        .ok_or_else(|| ShopError::ItemNotFound {
+                            location: pick.location.clone(),
+                            uuid: pick.uuid,
+                        })?;
+                    let name = tx.shop().name_of(item).to_string();
+                    let sku = item.sku().cloned();
+                    let category = tx
+                        .shop()
+                        .product_of(item)
+                        .map(|product| product.category.clone())
+                        .filter(|category| !category.is_empty());
+                    let unit_price = tx.shop().current_price_of(item)?;
+                    tx.adjust(
+                        &pick.location,
+                        &pick.uuid,
+                        -i64::from(pick.quantity),
+                        MovementReason::Sale(id),
+                    )?;
+                    taken.push(Taken {
+                        pick,
+                        name,
+                        sku,
+                        category,
+                        unit_price,
+                    });
+                }
+            }
+            self.price(id, taken)
+        })?;
+        shop.sales.insert(id, sale.clone());
+        self.basket.clear();
+        Ok(sale)
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn sale(&self, id: &Uuid) -> Option<&Sale> {
+        self.sales.get(id)
+    }
+
+    pub fn void_sale(&mut self, id: &Uuid) -> Result<(), ShopError> {
+        // Put the stock of a sale made here back into its lots, wherever they are now
+        let sale = self
+            .sales
+            .get(id)
+            .cloned()
+            .ok_or(ShopError::UnknownSale(*id))?;
+        if self.voided_sales.contains(&sale.id) {
+            return Err(ShopError::SaleAlreadyVoided(sale.id));
+        }
+        self.transaction(|tx| {
+            for line in &sale.lines {
+                let location = tx
+                    .shop()
+                    .get_location_by_uuid(&line.uuid)
+                    .cloned()
+                    .ok_or(ShopError::UnknownUuid(line.uuid))?;
+                tx.adjust(
+                    &location,
+                    &line.uuid,
+                    i64::from(line.quantity),
+                    MovementReason::Void(sale.id),
+                )?;
+            }
+            Ok(())
+        })?;
+        self.voided_sales.insert(sale.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        expiring_item, in_days, location, priced_item, product_lot, shop_with,
+    };
+    use crate::{Catalogue, ExpirableItem, Product, Sku};
+    use chrono::Duration;
+
+    fn shop() -> (GroceryShop<ExpirableItem>, Vec<ExpirableItem>) {
+        // Two lots of milk at 1,00 €, the second expiring first, and wine
+        let lots = vec![
+            expiring_item("Leite", 2, 20),
+            expiring_item("Leite", 5, 3),
+            ExpirableItem {
+                expiration_date: in_days(400),
+                ..priced_item("Vinho", 4, Money::eur(599))
+            },
+        ];
+        let shop = shop_with(vec![
+            (lots[0].clone(), location(0, 0, 0)),
+            (lots[1].clone(), location(1, 0, 0)),
+            (lots[2].clone(), location(2, 0, 0)),
+        ]);
+        (shop, lots)
+    }
+
+    fn checkout() -> Checkout {
+        Checkout::new()
+            .category("Leite", "food")
+            .vat_rate("food", 6)
+            .quantity_discount("Leite", 6, 10)
+            .quantity_discount("Leite", 3, 5)
+    }
+
+    #[test]
+    fn test_commit_takes_stock_fefo_and_prices_lines() {
+        let (mut shop, lots) = shop();
+        let mut checkout = checkout();
+        checkout.add("Leite", 6).add_lot(lots[2].uuid, 1);
+
+        let sale = checkout.commit(&mut shop).unwrap();
+
+        // The milk expiring first goes first, and 6 units earn 10% off
+        let sold: Vec<(Uuid, u32, Money)> = sale
+            .lines
+            .iter()
+            .map(|line| (line.uuid, line.quantity, line.total))
+            .collect();
+        assert_eq!(
+            sold,
+            vec![
+                (lots[1].uuid, 5, Money::eur(450)),
+                (lots[0].uuid, 1, Money::eur(90)),
+                (lots[2].uuid, 1, Money::eur(599)),
+            ]
+        );
+        assert_eq!(sale.total, Money::eur(1139));
+        // 5,40 € at 6% holds 5,40 × 6 / 106 = 0,3057 € of VAT, and
+        // 5,99 € at 23% holds 5,99 × 23 / 123 = 1,1201 €. Rounding the two milk
+        // lines apart (0,25 € + 0,05 €) would lose a cent.
+        assert_eq!(
+            sale.vat,
+            vec![
+                VatSummary {
+                    rate: 6,
+                    gross: Money::eur(540),
+                    vat: Money::eur(31),
+                },
+                VatSummary {
+                    rate: 23,
+                    gross: Money::eur(599),
+                    vat: Money::eur(112),
+                },
+            ]
+        );
+        assert_eq!(shop.stock_of("Leite"), 1);
+        assert_eq!(shop.stock_of("Vinho"), 3);
+        assert_eq!(
+            shop.ledger.for_item(&lots[1].uuid)[0].reason,
+            MovementReason::Sale(sale.id)
+        );
+        assert!(checkout.basket().is_empty());
+    }
+
+    #[test]
+    fn test_commit_is_all_or_nothing() {
+        let (mut shop, lots) = shop();
+        let mut checkout = checkout();
+
+        assert_eq!(checkout.commit(&mut shop), Err(ShopError::EmptyBasket));
+        checkout.add("Leite", 2).add("Vinho", 5);
+        assert!(matches!(
+            checkout.commit(&mut shop),
+            Err(ShopError::InsufficientStock { .. })
+        ));
+        assert_eq!(shop.stock_of("Leite"), 7);
+        assert_eq!(checkout.basket().len(), 2);
+
+        checkout.clear();
+        checkout.add("Leite", 0);
+        assert_eq!(checkout.commit(&mut shop), Err(ShopError::EmptyBasket));
+        checkout.clear();
+        checkout.add_lot(Uuid::nil(), 1);
+        assert_eq!(
+            checkout.commit(&mut shop),
+            Err(ShopError::UnknownUuid(Uuid::nil()))
+        );
+        checkout.clear();
+        checkout.add_lot(lots[0].uuid, 3);
+        assert!(matches!(
+            checkout.commit(&mut shop),
+            Err(ShopError::QuantityUnderflow { .. })
+        ));
+        assert!(shop.ledger.movements().is_empty());
+    }
+
+    #[test]
+    fn test_commit_refuses_lots_without_a_price() {
+        let (mut shop, _) = shop();
+        shop.add_product(Product::new("NAT-002", "Natas", Money::eur(120)))
+            .unwrap();
+        let cream = product_lot("NAT-002", 2);
+        shop.add_item(cream.clone(), &location(0, 1, 0)).unwrap();
+        // A product that has left the catalogue has no price to sell at
+        shop.catalogue = Catalogue::new();
+
+        let mut checkout = checkout();
+        checkout.add_lot(cream.uuid, 1);
+        assert_eq!(
+            checkout.commit(&mut shop),
+            Err(ShopError::UnknownSku(Sku::new("NAT-002")))
+        );
+        assert_eq!(shop.get_item(&location(0, 1, 0), &cream.uuid), Some(&cream));
+    }
+
+    #[test]
+    fn test_promotions_and_receipts() {
+        let (mut shop, lots) = shop();
+        let now = Utc::now();
+        shop.add_promotion(
+            &lots[2].uuid,
+            Money::eur(499),
+            now,
+            now + Duration::days(1),
+            "promo",
+        )
+        .unwrap();
+        let mut checkout = checkout();
+        checkout.add("Leite", 3).add("Vinho", 1);
+
+        let sale = checkout.commit(&mut shop).unwrap();
+        let receipt = sale.to_receipt();
+        let body: Vec<&str> = receipt.lines().skip(2).collect();
+        assert_eq!(
+            body,
+            vec![
+                "Leite 3 x 1,00 €                    3,00 €",
+                "  5% off                           -0,15 €",
+                "Vinho 1 x 4,99 €                    4,99 €",
+                "------------------------------------------",
+                "Total                               7,84 €",
+                "VAT 6% of 2,85 €                    0,16 €",
+                "VAT 23% of 4,99 €                   0,93 €",
+            ]
+        );
+
+        let json = sale.to_json();
+        assert!(json.contains(r#""discount_percent": 5"#));
+        assert_eq!(serde_json::from_str::<Sale>(&json).unwrap(), sale);
+    }
+
+    #[test]
+    fn test_void_restores_stock_once() {
+        let (mut shop, lots) = shop();
+        let mut checkout = checkout();
+        checkout.add("Leite", 6);
+        let sale = checkout.commit(&mut shop).unwrap();
+
+        // The lot may have moved since the sale
+        shop.move_item(location(1, 0, 0), &lots[1].uuid, &location(1, 1, 0))
+            .unwrap();
+        assert_eq!(shop.sale(&sale.id), Some(&sale));
+        shop.void_sale(&sale.id).unwrap();
+        assert_eq!(shop.stock_of("Leite"), 7);
+        assert_eq!(shop.get_item_by_uuid(&lots[1].uuid).unwrap().quantity, 5);
+        assert_eq!(
+            shop.void_sale(&sale.id),
+            Err(ShopError::SaleAlreadyVoided(sale.id))
+        );
+        assert_eq!(shop.stock_of("Leite"), 7);
+
+        // Only sales the shop made can be voided
+        let unknown = Uuid::new_v4();
+        assert_eq!(
+            shop.void_sale(&unknown),
+            Err(ShopError::UnknownSale(unknown))
+        );
+        assert_eq!(shop.stock_of("Leite"), 7);
+    }
+}
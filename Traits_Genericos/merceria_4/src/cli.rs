@@ -0,0 +1,1281 @@
+// Terminal front-end for the grocery shop.
+//
+// Without arguments the program shows an interactive menu. With arguments it
+// runs a single command against a snapshot file, e.g.
+//
+//     merceria add --row 1 --rack 0 --zone 1 --name Milk --quantity 5 --price "2,50 €" --expires 2025-01-31
+//
+// Every command accepts `--store <path>` (default `merceria.json`) anywhere
+// among its arguments, and refuses options it does not know. The snapshot is
+// created with the default layout the first time it is used.
+
+use crate::checkout::DEFAULT_VAT_RATE;
+use crate::{
+    Article, BarcodeError, Checkout, CountScope, DeliveredLot, Dimensions, Discrepancy,
+    ExpirableItem, GroceryShop, LayoutBuilder, Location, Money, MoneyError, MovementReason,
+    OrderLine, PlacementStrategy, Product, ProductKey, PurchaseOrder, Query, Scan, ShopError, Sku,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+pub const DEFAULT_STORE: &str = "merceria.json";
+
+/// Options that take no value.
+const FLAGS: &[&str] = &["force"];
+
+/// The options each command takes, besides `--store`.
+const COMMAND_OPTIONS: &[(&str, &[&str])] = &[
+    (
+        "layout",
+        &[
+            "rows",
+            "racks",
+            "zones",
+            "row-capacity",
+            "rack-capacity",
+            "lots-per-zone",
+            "force",
+        ],
+    ),
+    (
+        "add",
+        &[
+            "row",
+            "rack",
+            "zone",
+            "name",
+            "sku",
+            "quantity",
+            "price",
+            "expires",
+            "weight-grams",
+            "volume-ml",
+            "gtin",
+            "strategy",
+        ],
+    ),
+    ("remove", &["row", "rack", "zone", "uuid"]),
+    (
+        "move",
+        &[
+            "row", "rack", "zone", "uuid", "to-row", "to-rack", "to-zone",
+        ],
+    ),
+    (
+        "set-price",
+        &["row", "rack", "zone", "uuid", "price", "from", "reason"],
+    ),
+    (
+        "promote",
+        &[
+            "row", "rack", "zone", "uuid", "price", "from", "until", "reason",
+        ],
+    ),
+    ("prices", &["row", "rack", "zone", "uuid"]),
+    ("product", &["sku", "name", "price", "category", "unit"]),
+    ("rename-product", &["sku", "name"]),
+    ("reprice", &["sku", "price"]),
+    ("catalogue", &[]),
+    ("rename", &["row", "rack", "zone", "uuid", "name"]),
+    ("restock", &["row", "rack", "zone", "uuid", "quantity"]),
+    ("consume", &["row", "rack", "zone", "uuid", "quantity"]),
+    ("search", &["name", "max-distance"]),
+    ("scan", &["code"]),
+    ("query", &["where"]),
+    ("list", &[]),
+    ("utilisation", &[]),
+    ("report", &["by", "days", "format"]),
+    ("reorder", &["name", "sku", "point", "target"]),
+    ("low-stock", &[]),
+    ("supplier", &["name"]),
+    (
+        "order",
+        &[
+            "supplier",
+            "name",
+            "sku",
+            "quantity",
+            "cost",
+            "min-expiry",
+            "expected",
+        ],
+    ),
+    ("orders", &[]),
+    (
+        "receive",
+        &[
+            "order", "row", "rack", "zone", "name", "sku", "quantity", "price", "expires",
+        ],
+    ),
+    ("cancel-order", &["order"]),
+    ("count-start", &["row", "rack"]),
+    (
+        "count",
+        &["session", "row", "rack", "zone", "name", "quantity"],
+    ),
+    ("count-report", &["session"]),
+    ("count-approve", &["session", "code"]),
+    ("count-history", &["row", "rack", "zone"]),
+    ("sell", &["items", "vat", "receipt"]),
+    ("void", &["sale"]),
+];
+
+const USAGE: &str = "\
+Usage: merceria [--store <path>] <command> [options]
+
+Commands:
+  layout    --rows N --racks N --zones N [--row-capacity N] [--rack-capacity N]
+            [--lots-per-zone N] [--force]
+            (--force replaces an existing store and everything in it)
+  add       --row N --rack N --zone N --name NAME --quantity N --price P --expires YYYY-MM-DD
+            [--weight-grams N --volume-ml N] [--gtin CODE]
+  add       --row N --rack N --zone N --sku SKU --quantity N --expires YYYY-MM-DD
+            (name and price come from the catalogue)
+  add       --strategy S --name NAME --quantity N --price P --expires YYYY-MM-DD
+            (S: first-free, near-same-name, balance-rows, expiring-front)
+  remove    --row N --rack N --zone N [--uuid UUID]
+  move      --row N --rack N --zone N [--uuid UUID] --to-row N --to-rack N --to-zone N
+  set-price --row N --rack N --zone N [--uuid UUID] --price P [--from YYYY-MM-DD]
+            [--reason TEXT]
+  promote   --row N --rack N --zone N [--uuid UUID] --price P --from YYYY-MM-DD
+            --until YYYY-MM-DD [--reason TEXT]
+  prices    --row N --rack N --zone N [--uuid UUID]
+  product   --sku SKU --name NAME --price P [--category TEXT] [--unit piece|kg|l]
+  rename-product --sku SKU --name NAME
+  reprice   --sku SKU --price P
+  catalogue
+  rename    --row N --rack N --zone N [--uuid UUID] --name NAME
+  restock   --row N --rack N --zone N [--uuid UUID] --quantity N
+  consume   --row N --rack N --zone N [--uuid UUID] --quantity N
+  search    --name NAME [--max-distance N]
+  scan      --code CODE
+  query     --where \"price < 2 and row = 1 sort quantity desc limit 5\"
+  list
+  utilisation
+  report    --by total|row|rack|name|at-risk [--days N] [--format table|csv]
+  reorder   (--name NAME | --sku CODE) --point N --target N
+  low-stock
+  supplier  --name NAME
+  order     --supplier UUID (--name NAME | --sku CODE) --quantity N --cost P
+            [--min-expiry YYYY-MM-DD] [--expected YYYY-MM-DD]
+  orders
+  receive   --order UUID --row N --rack N --zone N --name NAME --quantity N --price P
+            --expires YYYY-MM-DD (or --sku SKU instead of --name and --price)
+  cancel-order --order UUID
+  count-start   --row N [--rack N]
+  count         --session UUID --row N --rack N --zone N --name NAME --quantity N
+  count-report  --session UUID
+  count-approve --session UUID --code CODE
+  count-history --row N --rack N --zone N
+  sell      --items \"Leite:2,Pão:1\" [--vat N] [--receipt PATH]
+  void      --sale UUID
+
+--uuid can be left out when the zone holds a single lot.
+Run without a command for the interactive menu.";
+
+// Helper functions for the terminal interface
+fn prompt_input(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    input.trim().to_string()
+}
+
+fn prompt_numeric<T: std::str::FromStr>(prompt: &str) -> T {
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+
+        match input.trim().parse::<T>() {
+            Ok(value) => return value,
+            Err(_) => println!("Invalid input. Please enter a valid number."),
+        }
+    }
+}
+
+fn prompt_yes_no(prompt: &str) -> bool {
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).unwrap();
+
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => println!("Invalid input. Please enter 'y' or 'n'."),
+        }
+    }
+}
+
+fn prompt_date(prompt: &str) -> DateTime<Utc> {
+    loop {
+        match parse_date(&prompt_input(prompt)) {
+            Ok(date) => return date,
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+fn prompt_location(prompt: &str) -> Location {
+    println!("{}", prompt);
+    Location {
+        row_id: prompt_numeric("  Row: "),
+        rack_id: prompt_numeric("  Rack: "),
+        zone_id: prompt_numeric("  Zone: "),
+    }
+}
+
+fn prompt_lot(shop: &GroceryShop<ExpirableItem>, prompt: &str) -> Option<(Location, Uuid)> {
+    // Ask for a zone and, when it holds several lots, for one of them
+    let location = prompt_location(prompt);
+    let lots = shop.get_items(&location);
+    match lots {
+        [] => {
+            println!("No item at {}.", location);
+            None
+        }
+        [item] => Some((location, item.uuid)),
+        _ => {
+            for (number, item) in lots.iter().enumerate() {
+                print!("  {}. ", number + 1);
+                print_item(shop, &location, item);
+            }
+            loop {
+                let number: usize = prompt_numeric("  Lot: ");
+                if let Some(item) = number.checked_sub(1).and_then(|index| lots.get(index)) {
+                    return Some((location, item.uuid));
+                }
+                println!("Invalid lot. Please pick one of the numbers above.");
+            }
+        }
+    }
+}
+
+fn select_lot(
+    shop: &GroceryShop<ExpirableItem>,
+    location: &Location,
+    uuid: Option<Uuid>,
+) -> Result<Uuid, String> {
+    // Use the given uuid, or the only lot in the zone when none is given
+    if let Some(uuid) = uuid {
+        return Ok(uuid);
+    }
+    match shop.get_items(location) {
+        [] => Err(format!("No item at {}", location)),
+        [item] => Ok(item.uuid),
+        lots => Err(format!(
+            "{} holds {} lots, pick one with --uuid",
+            location,
+            lots.len()
+        )),
+    }
+}
+
+fn parse_date(input: &str) -> Result<DateTime<Utc>, String> {
+    // Dates are entered as YYYY-MM-DD and stored at midnight UTC
+    NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("Invalid date '{}'. Use YYYY-MM-DD.", input))
+}
+
+fn default_shop() -> GroceryShop<ExpirableItem> {
+    let mut shop = GroceryShop::new();
+    shop.initialize();
+    shop
+}
+
+fn load_or_create(path: &str) -> Result<GroceryShop<ExpirableItem>, String> {
+    // Load the snapshot, or start from the default layout if it does not exist yet
+    if Path::new(path).exists() {
+        GroceryShop::load_from(path).map_err(|err| err.to_string())
+    } else {
+        Ok(default_shop())
+    }
+}
+
+fn print_item(shop: &GroceryShop<ExpirableItem>, location: &Location, item: &ExpirableItem) {
+    // Show the price the lot sells at today, and its regular price during a promotion
+    let price = match (shop.current_price(&item.uuid), shop.regular_price_of(item)) {
+        (Some(price), Ok(regular)) if price != regular => {
+            format!("{} (regular {})", price, regular)
+        }
+        (Some(price), _) => price.to_string(),
+        (None, _) => "no price".to_string(),
+    };
+    println!(
+        "[{}] {} x{} @ {} (expires {}) {}",
+        location,
+        shop.name_of(item),
+        item.quantity,
+        price,
+        item.expiration_date.format("%Y-%m-%d"),
+        item.uuid
+    );
+}
+
+fn print_all_items(shop: &GroceryShop<ExpirableItem>) {
+    if shop.uuid_index.is_empty() {
+        println!("The shop is empty.");
+    }
+    for (location, item) in shop.iter() {
+        print_item(shop, &location, item);
+    }
+}
+
+fn print_search(shop: &GroceryShop<ExpirableItem>, name: &str, max_distance: usize) {
+    let hits = shop.search(name, max_distance);
+    if hits.is_empty() {
+        println!("No items found matching '{}'.", name);
+    }
+    for hit in hits {
+        print_item(shop, &hit.location, hit.item);
+    }
+}
+
+fn print_utilisation(shop: &GroceryShop<ExpirableItem>) {
+    let limit = |max: Option<u64>| max.map_or("-".to_string(), |max| max.to_string());
+    for row in shop.utilisation() {
+        println!(
+            "Row {}: {}/{} zones used, {} units, {} g, {} ml",
+            row.row_id, row.occupied_zones, row.zones, row.units, row.weight_grams, row.volume_ml
+        );
+        for rack in row.racks {
+            println!(
+                "  Rack {}: {}/{} zones, {}/{} units, {}/{} g, {}/{} ml",
+                rack.rack_id,
+                rack.occupied_zones,
+                rack.zones,
+                rack.units,
+                limit(rack.max_units),
+                rack.weight_grams,
+                limit(rack.max_weight_grams),
+                rack.volume_ml,
+                limit(rack.max_volume_ml)
+            );
+        }
+    }
+}
+
+fn build_layout(
+    rows: u32,
+    racks: u32,
+    zones: u32,
+    row_capacity: u32,
+    rack_capacity: u32,
+    lots_per_zone: u32,
+) -> Result<GroceryShop<ExpirableItem>, String> {
+    LayoutBuilder::new()
+        .rows(rows)
+        .racks_per_row(racks)
+        .zones_per_rack(zones)
+        .row_capacity(row_capacity)
+        .rack_capacity(rack_capacity)
+        .lots_per_zone(lots_per_zone)
+        .build()
+        .map_err(|violations| {
+            violations
+                .iter()
+                .map(|violation| violation.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+}
+
+/// Options of a command, parsed from `--key value` pairs.
+struct Options {
+    values: HashMap<String, String>,
+}
+
+impl Options {
+    fn parse(command: &str, args: &[String], known: &[&str]) -> Result<Self, String> {
+        // Take `--key value` pairs, refusing a key the command does not know
+        let mut values = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let key = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("Unexpected argument '{}'", arg))?;
+            if key != "store" && !known.contains(&key) {
+                return Err(format!("Unknown option --{} for {}", key, command));
+            }
+            if FLAGS.contains(&key) {
+                values.insert(key.to_string(), String::new());
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for --{}", key))?;
+            values.insert(key.to_string(), value.clone());
+        }
+        Ok(Options { values })
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn text(&self, key: &str) -> Result<String, String> {
+        self.values
+            .get(key)
+            .cloned()
+            .ok_or_else(|| format!("Missing --{}", key))
+    }
+
+    fn text_or(&self, key: &str, default: &str) -> String {
+        self.values
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    fn number<T: std::str::FromStr>(&self, key: &str) -> Result<T, String> {
+        self.text(key)?
+            .parse()
+            .map_err(|_| format!("Invalid number for --{}", key))
+    }
+
+    fn money(&self, key: &str) -> Result<Money, String> {
+        self.text(key)?
+            .parse()
+            .map_err(|err: MoneyError| format!("Invalid price for --{}: {}", key, err))
+    }
+
+    fn number_or<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        if self.values.contains_key(key) {
+            self.number(key)
+        } else {
+            Ok(default)
+        }
+    }
+
+    fn uuid(&self, key: &str) -> Result<Uuid, String> {
+        let text = self.text(key)?;
+        Uuid::parse_str(&text).map_err(|_| format!("Invalid uuid '{}'", text))
+    }
+
+    fn article(&self) -> Result<Article, String> {
+        // A catalogue product given by --sku, or a loose article given by --name and --price
+        match self.values.get("sku") {
+            Some(sku) => Ok(Article::product(sku)),
+            None => Ok(Article::loose(&self.text("name")?, self.money("price")?)),
+        }
+    }
+
+    fn product(&self) -> Result<ProductKey, String> {
+        // A catalogue product given by --sku, or a loose article given by --name
+        match self.values.get("sku") {
+            Some(sku) => Ok(ProductKey::sku(sku)),
+            None => Ok(ProductKey::loose(&self.text("name")?)),
+        }
+    }
+
+    fn location(&self, prefix: &str) -> Result<Location, String> {
+        Ok(Location {
+            row_id: self.number(&format!("{}row", prefix))?,
+            rack_id: self.number(&format!("{}rack", prefix))?,
+            zone_id: self.number(&format!("{}zone", prefix))?,
+        })
+    }
+
+    fn lot(&self, shop: &GroceryShop<ExpirableItem>) -> Result<(Location, Uuid), String> {
+        // The zone given by --row/--rack/--zone and the lot picked by --uuid
+        let location = self.location("")?;
+        let uuid = match self.values.get("uuid") {
+            Some(uuid) => {
+                Some(Uuid::parse_str(uuid).map_err(|_| format!("Invalid uuid '{}'", uuid))?)
+            }
+            None => None,
+        };
+        let uuid = select_lot(shop, &location, uuid)?;
+        Ok((location, uuid))
+    }
+}
+
+pub fn run_command(args: &[String]) -> Result<(), String> {
+    // Run a single non-interactive command
+    // The command is the first argument that is not an option or its value
+    let mut command = None;
+    let mut rest = Vec::new();
+    let mut remaining = args.iter();
+    while let Some(arg) = remaining.next() {
+        if let Some(key) = arg.strip_prefix("--") {
+            rest.push(arg.clone());
+            if !FLAGS.contains(&key) {
+                rest.extend(remaining.next().cloned());
+            }
+        } else if command.is_none() {
+            command = Some(arg.as_str());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    let command = command.ok_or_else(|| USAGE.to_string())?;
+    let known = COMMAND_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, known)| *known)
+        .ok_or_else(|| format!("Unknown command '{}'\n\n{}", command, USAGE))?;
+    let options = Options::parse(command, &rest, known)?;
+    let store = options.text_or("store", DEFAULT_STORE);
+
+    if command == "layout" {
+        // A new layout starts an empty shop, so it must not silently replace
+        // the stock, catalogue and history kept in an existing store
+        if Path::new(&store).exists() && !options.flag("force") {
+            return Err(format!(
+                "{} already exists and a new layout discards everything in it; \
+                 use --force to replace it",
+                store
+            ));
+        }
+        let racks = options.number("racks")?;
+        let zones = options.number("zones")?;
+        let shop = build_layout(
+            options.number("rows")?,
+            racks,
+            zones,
+            options.number_or("row-capacity", racks)?,
+            options.number_or("rack-capacity", zones)?,
+            options.number_or("lots-per-zone", 1)?,
+        )?;
+        return shop.save_to(&store).map_err(|err| err.to_string());
+    }
+
+    let mut shop = load_or_create(&store)?;
+    shop.subscribe_low_stock(|alert| {
+        println!(
+            "Low stock: {} is down to {} (reorder point {})",
+            alert.name, alert.on_hand, alert.reorder_point
+        )
+    });
+    match command {
+        "add" => {
+            let item = ExpirableItem {
+                article: options.article()?,
+                quantity: options.number("quantity")?,
+                uuid: Uuid::new_v4(),
+                expiration_date: parse_date(&options.text("expires")?)?,
+                dimensions: if options.values.contains_key("weight-grams")
+                    || options.values.contains_key("volume-ml")
+                {
+                    Some(Dimensions {
+                        weight_grams: options.number_or("weight-grams", 0)?,
+                        volume_ml: options.number_or("volume-ml", 0)?,
+                    })
+                } else {
+                    None
+                },
+                gtin: match options.values.get("gtin") {
+                    Some(code) => Some(code.parse().map_err(|err: BarcodeError| err.to_string())?),
+                    None => None,
+                },
+            };
+            println!("Generated UUID: {}", item.uuid);
+            if options.values.contains_key("strategy") {
+                let strategy: PlacementStrategy = options.text("strategy")?.parse()?;
+                let location = shop.place(item, strategy).map_err(|err| err.to_string())?;
+                println!("Placed at {}", location);
+            } else {
+                shop.add_item(item, &options.location("")?)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        "remove" => {
+            let (location, uuid) = options.lot(&shop)?;
+            shop.remove_item(&location, &uuid)
+                .map_err(|err| err.to_string())?;
+        }
+        "move" => {
+            let (location, uuid) = options.lot(&shop)?;
+            shop.move_item(location, &uuid, &options.location("to-")?)
+                .map_err(|err| err.to_string())?
+        }
+        "set-price" => {
+            let (_, uuid) = options.lot(&shop)?;
+            let price = options.money("price")?;
+            let reason = options.text_or("reason", "manual change");
+            match options.values.get("from") {
+                Some(from) => shop.schedule_price(&uuid, price, parse_date(from)?, &reason),
+                None => shop.set_price(&uuid, price, &reason),
+            }
+            .map_err(|err| err.to_string())?;
+        }
+        "promote" => {
+            let (_, uuid) = options.lot(&shop)?;
+            shop.add_promotion(
+                &uuid,
+                options.money("price")?,
+                parse_date(&options.text("from")?)?,
+                parse_date(&options.text("until")?)?,
+                &options.text_or("reason", "promotion"),
+            )
+            .map_err(|err| err.to_string())?;
+        }
+        "prices" => {
+            let (_, uuid) = options.lot(&shop)?;
+            for change in shop.price_history(&uuid) {
+                let until = change
+                    .until
+                    .map(|until| format!(" until {}", until.format("%Y-%m-%d")))
+                    .unwrap_or_default();
+                println!(
+                    "{} {} from {}{} ({})",
+                    change.recorded_at.format("%Y-%m-%d %H:%M"),
+                    change.price,
+                    change.from.format("%Y-%m-%d"),
+                    until,
+                    change.reason
+                );
+            }
+            return Ok(());
+        }
+        "product" => {
+            let mut product = Product::new(
+                &options.text("sku")?,
+                &options.text("name")?,
+                options.money("price")?,
+            )
+            .category(&options.text_or("category", ""));
+            if options.values.contains_key("unit") {
+                product = product.unit(options.text("unit")?.parse()?);
+            }
+            shop.add_product(product).map_err(|err| err.to_string())?;
+        }
+        "rename-product" => {
+            shop.rename_product(&Sku::new(&options.text("sku")?), &options.text("name")?)
+                .map_err(|err| err.to_string())?;
+        }
+        "reprice" => {
+            shop.reprice_product(&Sku::new(&options.text("sku")?), options.money("price")?)
+                .map_err(|err| err.to_string())?;
+        }
+        "catalogue" => {
+            for product in shop.catalogue.products() {
+                println!(
+                    "{} {} ({:?}, {}) {} - {} in stock",
+                    product.sku,
+                    product.name,
+                    product.unit,
+                    product.category,
+                    product.price,
+                    shop.stock_of(&product.name)
+                );
+            }
+            return Ok(());
+        }
+        "rename" => {
+            let (_, uuid) = options.lot(&shop)?;
+            shop.rename_item(&uuid, &options.text("name")?)
+                .map_err(|err| err.to_string())?;
+        }
+        "restock" => {
+            let (location, uuid) = options.lot(&shop)?;
+            let quantity = shop
+                .restock(&location, &uuid, options.number("quantity")?)
+                .map_err(|err| err.to_string())?;
+            println!("New quantity: {}", quantity);
+        }
+        "consume" => {
+            let (location, uuid) = options.lot(&shop)?;
+            let quantity = shop
+                .consume(&location, &uuid, options.number("quantity")?)
+                .map_err(|err| err.to_string())?;
+            println!("New quantity: {}", quantity);
+        }
+        "search" => {
+            print_search(
+                &shop,
+                &options.text("name")?,
+                options.number_or("max-distance", 2)?,
+            );
+            return Ok(());
+        }
+        "scan" => {
+            let found = shop
+                .scan(&options.text("code")?)
+                .map_err(|err| err.to_string())?;
+            match found.scan {
+                Scan::Product(gtin) => println!("Product {}", gtin),
+                Scan::VariableMeasure { gtin, measure } => {
+                    println!("Variable-measure product {}: {:?}", gtin, measure)
+                }
+            }
+            if found.lots.is_empty() {
+                println!("No lot has that barcode.");
+            }
+            for (location, item) in found.lots {
+                print_item(&shop, &location, item);
+            }
+            return Ok(());
+        }
+        "query" => {
+            let query: Query = options
+                .text("where")?
+                .parse()
+                .map_err(|err: ShopError| err.to_string())?;
+            for (location, item) in shop.query(&query) {
+                print_item(&shop, &location, item);
+            }
+            return Ok(());
+        }
+        "list" => {
+            print_all_items(&shop);
+            return Ok(());
+        }
+        "utilisation" => {
+            print_utilisation(&shop);
+            return Ok(());
+        }
+        "reorder" => {
+            shop.set_reorder_policy(
+                options.product()?,
+                options.number("point")?,
+                options.number("target")?,
+            )
+            .map_err(|err| err.to_string())?;
+        }
+        "low-stock" => {
+            let low = shop.low_stock();
+            if low.is_empty() {
+                println!("No product is at or below its reorder point.");
+            }
+            for low in &low {
+                println!(
+                    "{}: {} in stock, {} on order (reorder point {}), order {} to reach {}",
+                    low.name,
+                    low.on_hand,
+                    low.on_order,
+                    low.policy.reorder_point,
+                    low.to_order(),
+                    low.policy.target_level
+                );
+            }
+            return Ok(());
+        }
+        "supplier" => {
+            let id = shop.add_supplier(&options.text("name")?);
+            println!("Supplier id: {}", id);
+        }
+        "order" => {
+            let mut line = OrderLine::new(
+                options.product()?,
+                options.number("quantity")?,
+                options.money("cost")?,
+            );
+            if let Some(date) = options.values.get("min-expiry") {
+                line = line.min_expiration(parse_date(date)?);
+            }
+            let mut order = PurchaseOrder::new(options.uuid("supplier")?).line(line);
+            if let Some(date) = options.values.get("expected") {
+                order = order.expected_at(parse_date(date)?);
+            }
+            let id = shop.place_order(order).map_err(|err| err.to_string())?;
+            println!("Purchase order id: {}", id);
+        }
+        "orders" => {
+            for order in shop.open_orders() {
+                let supplier = shop
+                    .supplier(&order.supplier_id)
+                    .map_or("unknown supplier", |supplier| supplier.name.as_str());
+                println!("{} from {} ({:?})", order.id, supplier, order.status);
+                for line in &order.lines {
+                    println!(
+                        "  {} {}/{} received @ {}",
+                        shop.product_name(&line.product),
+                        line.received,
+                        line.quantity,
+                        line.unit_cost
+                    );
+                }
+            }
+            return Ok(());
+        }
+        "receive" => {
+            let lot = DeliveredLot {
+                item: ExpirableItem {
+                    article: options.article()?,
+                    quantity: options.number("quantity")?,
+                    uuid: Uuid::new_v4(),
+                    expiration_date: parse_date(&options.text("expires")?)?,
+                    dimensions: None,
+                    gtin: None,
+                },
+                location: options.location("")?,
+            };
+            let report = shop
+                .receive(&options.uuid("order")?, vec![lot])
+                .map_err(|err| err.to_string())?;
+            for lot in &report.accepted {
+                println!(
+                    "Put away {} x{} at {}",
+                    shop.name_of(&lot.item),
+                    lot.item.quantity,
+                    lot.location
+                );
+            }
+            for discrepancy in &report.discrepancies {
+                print_discrepancy(discrepancy);
+            }
+        }
+        "cancel-order" => {
+            shop.cancel_order(&options.uuid("order")?)
+                .map_err(|err| err.to_string())?;
+        }
+        "count-start" => {
+            let row_id = options.number("row")?;
+            let scope = match options.values.get("rack") {
+                Some(_) => CountScope::Rack {
+                    row_id,
+                    rack_id: options.number("rack")?,
+                },
+                None => CountScope::Row(row_id),
+            };
+            let id = shop.start_count(scope).map_err(|err| err.to_string())?;
+            println!("Count session id: {}", id);
+        }
+        "count" => {
+            shop.record_count(
+                &options.uuid("session")?,
+                &options.location("")?,
+                &options.text("name")?,
+                options.number("quantity")?,
+            )
+            .map_err(|err| err.to_string())?;
+        }
+        "count-report" | "count-approve" => {
+            let session_id = options.uuid("session")?;
+            let report = if command == "count-approve" {
+                shop.approve_count(&session_id, &options.text("code")?)
+            } else {
+                shop.variance_report(&session_id)
+            }
+            .map_err(|err| err.to_string())?;
+            if report.is_clean() {
+                println!("The counts match the system.");
+            }
+            for variance in &report.variances {
+                println!(
+                    "[{}] {}: expected {}, counted {} ({:?})",
+                    variance.location,
+                    variance.name,
+                    variance.expected,
+                    variance.counted,
+                    variance.kind
+                );
+            }
+            for location in &report.uncounted {
+                println!("[{}] not counted", location);
+            }
+            if command == "count-report" {
+                return Ok(());
+            }
+        }
+        "count-history" => {
+            for record in shop.count_history(&options.location("")?) {
+                println!(
+                    "{} {}: expected {}, counted {} ({})",
+                    record.counted_at.format("%Y-%m-%d %H:%M"),
+                    record.name,
+                    record.expected,
+                    record.counted,
+                    record.code
+                );
+            }
+            return Ok(());
+        }
+        "sell" => {
+            let mut checkout =
+                Checkout::new().default_vat_rate(options.number_or("vat", DEFAULT_VAT_RATE)?);
+            for entry in options.text("items")?.split(',') {
+                let (name, quantity) = entry
+                    .rsplit_once(':')
+                    .ok_or_else(|| format!("Expected NAME:QUANTITY, got '{}'", entry))?;
+                let quantity = quantity
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid quantity in '{}'", entry))?;
+                checkout.add(name.trim(), quantity);
+            }
+            let sale = checkout.commit(&mut shop).map_err(|err| err.to_string())?;
+            print!("{}", sale.to_receipt());
+            if let Some(path) = options.values.get("receipt") {
+                std::fs::write(path, sale.to_json()).map_err(|err| err.to_string())?;
+            }
+        }
+        "void" => {
+            let id = options.uuid("sale")?;
+            shop.void_sale(&id).map_err(|err| err.to_string())?;
+            let total = shop.sale(&id).expect("voided sales are kept").total;
+            println!("Voided sale {} ({})", id, total);
+        }
+        "report" => {
+            let report = match options.text("by")?.as_str() {
+                "total" => {
+                    let total = shop.total_value().map_err(|err| err.to_string())?;
+                    println!("Total stock value: {}", total);
+                    return Ok(());
+                }
+                "row" => shop.value_by_row(),
+                "rack" => shop.value_by_rack(),
+                "name" => shop.value_by_name(),
+                "at-risk" => shop.value_at_risk(options.number_or("days", 7)?),
+                other => return Err(format!("Unknown report '{}'", other)),
+            }
+            .map_err(|err| err.to_string())?;
+            match options.values.get("format").map(String::as_str) {
+                None | Some("table") => print!("{}", report.to_table()),
+                Some("csv") => print!("{}", report.to_csv()),
+                Some(other) => return Err(format!("Unknown format '{}'", other)),
+            }
+            return Ok(());
+        }
+        _ => return Err(format!("Unknown command '{}'\n\n{}", command, USAGE)),
+    }
+    shop.save_to(&store).map_err(|err| err.to_string())
+}
+
+fn print_discrepancy(discrepancy: &Discrepancy) {
+    match discrepancy {
+        Discrepancy::Short {
+            name,
+            outstanding,
+            delivered,
+        } => println!(
+            "Short: {} {} of {} outstanding",
+            name, delivered, outstanding
+        ),
+        Discrepancy::Over {
+            name,
+            outstanding,
+            delivered,
+        } => println!(
+            "Over: {} {} of {} outstanding, excess rejected",
+            name, delivered, outstanding
+        ),
+        Discrepancy::NotOrdered { name, .. } => println!("Not ordered: {}, rejected", name),
+        Discrepancy::ShortDated {
+            name,
+            expires,
+            min_expiration,
+            ..
+        } => println!(
+            "Short-dated: {} expires {}, needs {}, rejected",
+            name,
+            expires.map_or("never".to_string(), |date| date
+                .format("%Y-%m-%d")
+                .to_string()),
+            min_expiration.map_or("any date".to_string(), |date| date
+                .format("%Y-%m-%d")
+                .to_string())
+        ),
+    }
+}
+
+fn add_item_terminal_interface(shop: &mut GroceryShop<ExpirableItem>) {
+    println!("=== Add New Item ===");
+    let article = Article::loose(&prompt_input("Name: "), prompt_numeric("Price: "));
+    let item = ExpirableItem {
+        article,
+        quantity: prompt_numeric("Quantity: "),
+        uuid: Uuid::new_v4(),
+        expiration_date: prompt_date("Expiration date (YYYY-MM-DD): "),
+        dimensions: None,
+        gtin: None,
+    };
+    println!("Generated UUID: {}", item.uuid);
+    if prompt_yes_no("Place it next to the same product automatically? (y/n): ") {
+        match shop.place(item, PlacementStrategy::NearSameName) {
+            Ok(location) => println!("Item added at {}.", location),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+    let location = prompt_location("Where should it go?");
+    match shop.add_item(item, &location) {
+        Ok(()) => println!("Item added at {}.", location),
+        Err(err) => println!("{}", err),
+    }
+}
+
+fn layout_terminal_interface(shop: &mut GroceryShop<ExpirableItem>) {
+    println!("=== Shop Layout ===");
+    if !prompt_yes_no(
+        "A new layout discards everything in the shop: items, catalogue, prices, \
+         orders, sales and counts. Continue? (y/n): ",
+    ) {
+        return;
+    }
+    let rows = prompt_numeric("Rows: ");
+    let racks = prompt_numeric("Racks per row: ");
+    let zones = prompt_numeric("Zones per rack: ");
+    let row_capacity = prompt_numeric("Max racks per row: ");
+    let rack_capacity = prompt_numeric("Max zones per rack: ");
+    let lots_per_zone = prompt_numeric("Max lots per zone: ");
+    match build_layout(
+        rows,
+        racks,
+        zones,
+        row_capacity,
+        rack_capacity,
+        lots_per_zone,
+    ) {
+        Ok(new_shop) => {
+            *shop = new_shop;
+            println!("Layout created.");
+        }
+        Err(violations) => println!("The layout is not valid:\n{}", violations),
+    }
+}
+
+pub fn run_interactive(store: &str) {
+    let mut shop = match load_or_create(store) {
+        Ok(shop) => shop,
+        Err(err) => {
+            println!("{} - starting with the default layout.", err);
+            default_shop()
+        }
+    };
+
+    loop {
+        print_main_menu();
+        let choice = prompt_input("Enter your choice: ");
+
+        match choice.as_str() {
+            "1" => layout_terminal_interface(&mut shop),
+            "2" => add_item_terminal_interface(&mut shop),
+            "3" => {
+                if let Some((location, uuid)) = prompt_lot(&shop, "Item to remove:") {
+                    match shop.remove_item(&location, &uuid) {
+                        Ok(_) => println!("Item removed."),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "4" => {
+                if let Some((from_location, uuid)) = prompt_lot(&shop, "Item to move:") {
+                    let to_location = prompt_location("New location:");
+                    match shop.move_item(from_location, &uuid, &to_location) {
+                        Ok(()) => println!("Item moved to {}.", to_location),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "5" => {
+                if let Some((_, uuid)) = prompt_lot(&shop, "Item to reprice:") {
+                    match shop.set_price(&uuid, prompt_numeric("New price: "), "manual change") {
+                        Ok(()) => println!("Price changed."),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "6" => {
+                if let Some((_, uuid)) = prompt_lot(&shop, "Item to rename:") {
+                    match shop.rename_item(&uuid, &prompt_input("New name: ")) {
+                        Ok(()) => println!("Name changed."),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "7" => {
+                if let Some((location, uuid)) = prompt_lot(&shop, "Item to restock:") {
+                    let quantity: i64 = prompt_numeric("Quantity to add (negative to remove): ");
+                    let reason = if quantity >= 0 {
+                        MovementReason::Restock
+                    } else {
+                        MovementReason::Consumption
+                    };
+                    match shop.adjust(&location, &uuid, quantity, reason) {
+                        Ok(quantity) => println!("New quantity: {}", quantity),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "8" => print_search(&shop, &prompt_input("Name to search: "), 2),
+            "9" => print_all_items(&shop),
+            "10" => match shop.save_to(store) {
+                Ok(()) => println!("Saved to {}.", store),
+                Err(err) => println!("{}", err),
+            },
+            "0" => {
+                if prompt_yes_no("Save before exiting? (y/n): ") {
+                    if let Err(err) = shop.save_to(store) {
+                        println!("{}", err);
+                    }
+                }
+                println!("Exiting Grocery Management System...");
+                break;
+            }
+            _ => println!("Invalid option. Please try again."),
+        }
+
+        println!("\nPress Enter to continue...");
+        io::stdin().read_line(&mut String::new()).unwrap();
+    }
+}
+
+fn print_main_menu() {
+    println!("╔════════════════════════════════════════╗");
+    println!("║  MAIN MENU GROCERY MANAGEMENT SYSTEM   ║");
+    println!("╠════════════════════════════════════════╣");
+    println!("║                                        ║");
+    println!("║ SHOP MANAGEMENT:                       ║");
+    println!("║  1. Lay out the store                  ║");
+    println!("║                                        ║");
+    println!("║ ITEM MANAGEMENT:                       ║");
+    println!("║  2. Add an Item                        ║");
+    println!("║  3. Remove an Item                     ║");
+    println!("║  4. Move an Item                       ║");
+    println!("║  5. Change price                       ║");
+    println!("║  6. Change name                        ║");
+    println!("║  7. Restock / remove stock             ║");
+    println!("║  8. Find Items (name)                  ║");
+    println!("║  9. Print all items                    ║");
+    println!("║                                        ║");
+    println!("║ 10. Save                               ║");
+    println!("║  0. Exit                               ║");
+    println!("╚════════════════════════════════════════╝");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_scripted_commands() {
+        let store = std::env::temp_dir().join(format!("merceria_4_cli_{}.json", Uuid::new_v4()));
+        let store = store.to_str().unwrap();
+
+        run_command(&args(&format!(
+            "--store {} layout --rows 2 --racks 1 --zones 2 --lots-per-zone 2",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} add --row 1 --rack 0 --zone 1 --name Milk --quantity 5 --price 2.5 --expires 2030-01-31",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} move --row 1 --rack 0 --zone 1 --to-row 0 --to-rack 0 --to-zone 0",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} restock --row 0 --rack 0 --zone 0 --quantity 3",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} rename --row 0 --rack 0 --zone 0 --name Leite",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} set-price --row 0 --rack 0 --zone 0 --price 2,70 --reason supplier",
+            store
+        )))
+        .unwrap();
+        run_command(&args(&format!(
+            "--store {} promote --row 0 --rack 0 --zone 0 --price 1.99 --from 2020-01-01 --until 2099-01-01",
+            store
+        )))
+        .unwrap();
+        let result = run_command(&args(&format!(
+            "--store {} remove --row 5 --rack 0 --zone 0",
+            store
+        )));
+
+        // A second lot in the same zone has to be picked by uuid
+        run_command(&args(&format!(
+            "--store {} add --row 0 --rack 0 --zone 0 --name Leite --quantity 1 --price 2.5 --expires 2030-02-28",
+            store
+        )))
+        .unwrap();
+        let ambiguous = run_command(&args(&format!(
+            "--store {} consume --row 0 --rack 0 --zone 0 --quantity 1",
+            store
+        )));
+
+        run_command(&args(&format!(
+            "--store {} add --strategy near-same-name --name Pão --quantity 2 --price 1.2 --expires 2030-01-20",
+            store
+        )))
+        .unwrap();
+
+        let shop = GroceryShop::<ExpirableItem>::load_from(store).unwrap();
+        std::fs::remove_file(store).unwrap();
+
+        assert!(result.is_err());
+        assert!(ambiguous.is_err());
+        assert_eq!(shop.rows.len(), 2);
+        let items = shop.get_items_by_name("Leite");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].quantity, 8);
+        assert_eq!(items[0].expiration_date, parse_date("2030-01-31").unwrap());
+        assert_eq!(shop.regular_price_of(items[0]), Ok(Money::eur(270)));
+        assert_eq!(shop.current_price(&items[0].uuid), Some(Money::eur(199)));
+        assert_eq!(shop.price_history(&items[0].uuid).len(), 3);
+        let bread = shop.get_items_by_name("Pão")[0];
+        assert_eq!(
+            shop.get_location_by_uuid(&bread.uuid),
+            Some(&Location {
+                row_id: 0,
+                rack_id: 0,
+                zone_id: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_store_can_come_after_the_command() {
+        let store = std::env::temp_dir().join(format!("merceria_4_cli_{}.json", Uuid::new_v4()));
+        let store = store.to_str().unwrap();
+
+        run_command(&args(&format!(
+            "layout --rows 1 --racks 1 --zones 1 --store {}",
+            store
+        )))
+        .unwrap();
+        // A second layout would wipe the store unless forced
+        let again = format!("layout --rows 2 --racks 1 --zones 1 --store {}", store);
+        assert!(run_command(&args(&again)).is_err());
+        assert_eq!(
+            GroceryShop::<ExpirableItem>::load_from(store)
+                .unwrap()
+                .zones()
+                .count(),
+            1
+        );
+        run_command(&args(&format!("{} --force", again))).unwrap();
+        let shop = GroceryShop::<ExpirableItem>::load_from(store).unwrap();
+        std::fs::remove_file(store).unwrap();
+        assert_eq!(shop.zones().count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(run_command(&args("add --row")).is_err());
+        assert!(run_command(&args("add row 1")).is_err());
+        assert_eq!(
+            run_command(&args("remove --row 0 --rack 0 --zone 0 --uid 1")),
+            Err("Unknown option --uid for remove".to_string())
+        );
+        assert!(run_command(&args("frobnicate")).is_err());
+        assert!("nearest".parse::<PlacementStrategy>().is_err());
+        assert!(parse_date("31/01/2030").is_err());
+    }
+}
@@ -0,0 +1,552 @@
+// Cycle counts: counting the stock of a row or rack and reconciling it.
+//
+// `start_count` opens a CountSession for a row or a rack and snapshots how
+// many units of each product the system expects in every zone. Staff then
+// record what they find with `record_count`, product by product; a zone is
+// counted once anything was recorded for it, and products of the snapshot
+// that were not recorded in a counted zone count as zero.
+//
+// The variance report compares the counts with the snapshot: units missing,
+// surplus units, and products found in a zone that should hold none of them.
+// Approving a session adjusts the lots of counted zones by the variances,
+// with a reason code in the ledger, and files every count in the history of
+// its Location. Products in the wrong zone are reported but never adjusted:
+// the shop cannot tell which lot they belong to.
+
+use crate::{GroceryShop, Item, Location, MovementReason, ShopError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// The part of the shop a count session covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CountScope {
+    Row(u32),
+    Rack { row_id: u32, rack_id: u32 },
+}
+
+impl CountScope {
+    pub fn contains(&self, location: &Location) -> bool {
+        match *self {
+            CountScope::Row(row_id) => location.row_id == row_id,
+            CountScope::Rack { row_id, rack_id } => {
+                location.row_id == row_id && location.rack_id == rack_id
+            }
+        }
+    }
+}
+
+impl fmt::Display for CountScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CountScope::Row(row_id) => write!(f, "row {}", row_id),
+            CountScope::Rack { row_id, rack_id } => write!(f, "row {} / rack {}", row_id, rack_id),
+        }
+    }
+}
+
+/// `quantity` units of the product `name` in the zone at `location`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountLine {
+    pub location: Location,
+    pub name: String,
+    pub quantity: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountSession {
+    pub id: Uuid,
+    pub scope: CountScope,
+    pub started_at: DateTime<Utc>,
+    /// Every zone in scope when the session started.
+    pub zones: Vec<Location>,
+    /// What the system held when the session started.
+    pub expected: Vec<CountLine>,
+    /// What the staff recorded so far.
+    pub counted: Vec<CountLine>,
+}
+
+impl CountSession {
+    fn expected_units(&self, location: &Location, name: &str) -> u64 {
+        find_line(&self.expected, location, name).map_or(0, |line| line.quantity)
+    }
+
+    fn is_counted(&self, location: &Location) -> bool {
+        self.counted.iter().any(|line| &line.location == location)
+    }
+}
+
+fn find_line<'a>(lines: &'a [CountLine], location: &Location, name: &str) -> Option<&'a CountLine> {
+    lines
+        .iter()
+        .find(|line| &line.location == location && line.name == name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarianceKind {
+    /// Fewer units were counted than expected.
+    Missing,
+    /// More units were counted than expected.
+    Surplus,
+    /// The product was found in a zone that should hold none of it.
+    WrongProduct,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variance {
+    pub location: Location,
+    pub name: String,
+    pub expected: u64,
+    pub counted: u64,
+    pub kind: VarianceKind,
+}
+
+impl Variance {
+    /// Units to add to the stock, negative when units are missing.
+    pub fn delta(&self) -> i64 {
+        self.counted as i64 - self.expected as i64
+    }
+}
+
+/// The differences between the counts of a session and its snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarianceReport {
+    pub session_id: Uuid,
+    pub variances: Vec<Variance>,
+    /// Zones in scope nothing was recorded for; they are left alone.
+    pub uncounted: Vec<Location>,
+}
+
+impl VarianceReport {
+    pub fn is_clean(&self) -> bool {
+        self.variances.is_empty()
+    }
+}
+
+/// A product counted in a zone by an approved session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountRecord {
+    pub session_id: Uuid,
+    pub counted_at: DateTime<Utc>,
+    pub location: Location,
+    pub name: String,
+    pub expected: u64,
+    pub counted: u64,
+    pub code: String,
+}
+
+/// Approved counts, oldest first, kept per zone.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<CountRecord>", into = "Vec<CountRecord>")]
+pub struct CountHistory {
+    by_location: BTreeMap<Location, Vec<CountRecord>>,
+}
+
+impl CountHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, record: CountRecord) {
+        self.by_location
+            .entry(record.location.clone())
+            .or_default()
+            .push(record);
+    }
+
+    pub fn at_location(&self, location: &Location) -> &[CountRecord] {
+        match self.by_location.get(location) {
+            Some(records) => records,
+            None => &[],
+        }
+    }
+}
+
+impl From<Vec<CountRecord>> for CountHistory {
+    fn from(records: Vec<CountRecord>) -> Self {
+        let mut history = CountHistory::new();
+        for record in records {
+            history.record(record);
+        }
+        history
+    }
+}
+
+impl From<CountHistory> for Vec<CountRecord> {
+    fn from(history: CountHistory) -> Self {
+        history.by_location.into_values().flatten().collect()
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn start_count(&mut self, scope: CountScope) -> Result<Uuid, ShopError> {
+        // Open a session for the zones in scope, snapshotting what they should hold
+        let mut zones = Vec::new();
+        let mut expected: Vec<CountLine> = Vec::new();
+        for (location, zone) in self
+            .zones()
+            .filter(|(location, _)| scope.contains(location))
+        {
+            for lot in &zone.lots {
+                let units = u64::from(lot.quantity());
+                let name = self.name_of(lot);
+                match expected
+                    .iter_mut()
+                    .find(|line| line.location == location && line.name == name)
+                {
+                    Some(line) => line.quantity += units,
+                    None => expected.push(CountLine {
+                        location: location.clone(),
+                        name: name.to_string(),
+                        quantity: units,
+                    }),
+                }
+            }
+            zones.push(location);
+        }
+        if zones.is_empty() {
+            return Err(ShopError::EmptyCountScope(scope));
+        }
+
+        let session = CountSession {
+            id: Uuid::new_v4(),
+            scope,
+            started_at: Utc::now(),
+            zones,
+            expected,
+            counted: Vec::new(),
+        };
+        let id = session.id;
+        self.count_sessions.insert(id, session);
+        Ok(id)
+    }
+
+    pub fn count_session(&self, id: &Uuid) -> Option<&CountSession> {
+        self.count_sessions.get(id)
+    }
+
+    pub fn record_count(
+        &mut self,
+        session_id: &Uuid,
+        location: &Location,
+        name: &str,
+        quantity: u32,
+    ) -> Result<(), ShopError> {
+        // Record how many units of a product were found in a zone, replacing
+        // an earlier count of the same product there. A count is bounded like
+        // a lot quantity, so its variance always fits an i64.
+        let session = self
+            .count_sessions
+            .get_mut(session_id)
+            .ok_or(ShopError::UnknownCountSession(*session_id))?;
+        if !session.zones.contains(location) {
+            return Err(ShopError::OutOfCountScope {
+                session_id: *session_id,
+                location: location.clone(),
+            });
+        }
+        match session
+            .counted
+            .iter_mut()
+            .find(|line| &line.location == location && line.name == name)
+        {
+            Some(line) => line.quantity = u64::from(quantity),
+            None => session.counted.push(CountLine {
+                location: location.clone(),
+                name: name.to_string(),
+                quantity: u64::from(quantity),
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn cancel_count(&mut self, session_id: &Uuid) -> Result<(), ShopError> {
+        self.count_sessions
+            .remove(session_id)
+            .map(|_| ())
+            .ok_or(ShopError::UnknownCountSession(*session_id))
+    }
+
+    fn counted_lines(session: &CountSession) -> Vec<CountLine> {
+        // Every product of a counted zone with its count, zero when the staff
+        // did not record a product the snapshot expects there
+        let mut lines = Vec::new();
+        for location in session.zones.iter().filter(|zone| session.is_counted(zone)) {
+            let names = session
+                .expected
+                .iter()
+                .chain(&session.counted)
+                .filter(|line| &line.location == location)
+                .map(|line| line.name.as_str());
+            for name in names {
+                if find_line(&lines, location, name).is_none() {
+                    lines.push(CountLine {
+                        location: location.clone(),
+                        name: name.to_string(),
+                        quantity: find_line(&session.counted, location, name)
+                            .map_or(0, |line| line.quantity),
+                    });
+                }
+            }
+        }
+        lines
+    }
+
+    pub fn variance_report(&self, session_id: &Uuid) -> Result<VarianceReport, ShopError> {
+        // Compare the counts recorded so far with the snapshot
+        let session = self
+            .count_sessions
+            .get(session_id)
+            .ok_or(ShopError::UnknownCountSession(*session_id))?;
+        let variances = Self::counted_lines(session)
+            .into_iter()
+            .filter_map(|line| {
+                let expected = session.expected_units(&line.location, &line.name);
+                let kind = if expected == 0 && line.quantity > 0 {
+                    VarianceKind::WrongProduct
+                } else if line.quantity < expected {
+                    VarianceKind::Missing
+                } else if line.quantity > expected {
+                    VarianceKind::Surplus
+                } else {
+                    return None;
+                };
+                Some(Variance {
+                    location: line.location,
+                    name: line.name,
+                    expected,
+                    counted: line.quantity,
+                    kind,
+                })
+            })
+            .collect();
+        Ok(VarianceReport {
+            session_id: *session_id,
+            variances,
+            uncounted: session
+                .zones
+                .iter()
+                .filter(|zone| !session.is_counted(zone))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    pub fn approve_count(
+        &mut self,
+        session_id: &Uuid,
+        code: &str,
+    ) -> Result<VarianceReport, ShopError> {
+        // Adjust the stock of the counted zones to the counts, record them in
+        // the count history and close the session; nothing changes if an
+        // adjustment fails
+        let report = self.variance_report(session_id)?;
+        let reason = MovementReason::Count {
+            session_id: *session_id,
+            code: code.to_string(),
+        };
+        self.transaction(|tx| {
+            for variance in &report.variances {
+                if variance.kind == VarianceKind::WrongProduct {
+                    continue;
+                }
+                let mut delta = variance.delta();
+                let lots: Vec<(Uuid, u32)> = tx
+                    .shop()
+                    .get_items(&variance.location)
+                    .iter()
+                    .filter(|lot| tx.shop().name_of(lot) == variance.name)
+                    .map(|lot| (lot.uuid(), lot.quantity()))
+                    .collect();
+                // Surplus goes to the first lot, missing units come off the
+                // lots in order
+                for (uuid, quantity) in lots {
+                    if delta == 0 {
+                        break;
+                    }
+                    let change = delta.max(-i64::from(quantity));
+                    tx.adjust(&variance.location, &uuid, change, reason.clone())?;
+                    delta -= change;
+                }
+                // The lots were removed or emptied since the count started
+                if delta != 0 {
+                    return Err(ShopError::CountMismatch {
+                        location: variance.location.clone(),
+                        name: variance.name.clone(),
+                    });
+                }
+            }
+            Ok(())
+        })?;
+
+        let session = self
+            .count_sessions
+            .remove(session_id)
+            .expect("the session was just reported on");
+        let counted_at = Utc::now();
+        for line in Self::counted_lines(&session) {
+            self.count_history.record(CountRecord {
+                session_id: *session_id,
+                counted_at,
+                expected: session.expected_units(&line.location, &line.name),
+                counted: line.quantity,
+                location: line.location,
+                name: line.name,
+                code: code.to_string(),
+            });
+        }
+        Ok(report)
+    }
+
+    pub fn count_history(&self, location: &Location) -> &[CountRecord] {
+        self.count_history.at_location(location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::ExpirableItem;
+
+    fn shop() -> (GroceryShop<ExpirableItem>, ExpirableItem) {
+        let milk = item("Leite", 10);
+        let shop = shop_with(vec![
+            (milk.clone(), location(0, 0, 0)),
+            (item("Pão", 5), location(0, 0, 1)),
+            (item("Ovos", 12), location(0, 1, 0)),
+            (item("Arroz", 3), location(1, 0, 0)),
+        ]);
+        (shop, milk)
+    }
+
+    #[test]
+    fn test_sessions_snapshot_their_scope() {
+        let (mut shop, _) = shop();
+
+        let id = shop
+            .start_count(CountScope::Rack {
+                row_id: 0,
+                rack_id: 0,
+            })
+            .unwrap();
+        let session = shop.count_session(&id).unwrap();
+        assert_eq!(session.zones, vec![location(0, 0, 0), location(0, 0, 1)]);
+        assert_eq!(session.expected.len(), 2);
+        assert_eq!(
+            shop.start_count(CountScope::Row(9)),
+            Err(ShopError::EmptyCountScope(CountScope::Row(9)))
+        );
+        assert_eq!(
+            shop.record_count(&id, &location(1, 0, 0), "Arroz", 3),
+            Err(ShopError::OutOfCountScope {
+                session_id: id,
+                location: location(1, 0, 0)
+            })
+        );
+        shop.cancel_count(&id).unwrap();
+        assert_eq!(
+            shop.record_count(&id, &location(0, 0, 0), "Leite", 3),
+            Err(ShopError::UnknownCountSession(id))
+        );
+    }
+
+    #[test]
+    fn test_variance_report() {
+        let (mut shop, _) = shop();
+        let id = shop.start_count(CountScope::Row(0)).unwrap();
+
+        shop.record_count(&id, &location(0, 0, 0), "Leite", 8)
+            .unwrap();
+        shop.record_count(&id, &location(0, 0, 0), "Iogurte", 2)
+            .unwrap();
+        shop.record_count(&id, &location(0, 1, 0), "Ovos", 7)
+            .unwrap();
+        // A later count of the same product replaces the earlier one
+        shop.record_count(&id, &location(0, 1, 0), "Ovos", 13)
+            .unwrap();
+
+        let report = shop.variance_report(&id).unwrap();
+        let variances: Vec<(&str, u64, u64, VarianceKind)> = report
+            .variances
+            .iter()
+            .map(|v| (v.name.as_str(), v.expected, v.counted, v.kind))
+            .collect();
+        assert_eq!(
+            variances,
+            vec![
+                ("Leite", 10, 8, VarianceKind::Missing),
+                ("Iogurte", 0, 2, VarianceKind::WrongProduct),
+                ("Ovos", 12, 13, VarianceKind::Surplus),
+            ]
+        );
+        assert_eq!(report.variances[0].delta(), -2);
+        assert_eq!(report.uncounted, vec![location(0, 0, 1), location(0, 1, 1)]);
+    }
+
+    #[test]
+    fn test_approval_adjusts_and_keeps_history() {
+        let (mut shop, milk) = shop();
+        let second = item("Leite", 4);
+        let milk_zone = location(0, 0, 0);
+        shop.get_zone_mut(&milk_zone).unwrap().max_lots = 2;
+        shop.add_item(second.clone(), &milk_zone).unwrap();
+        let id = shop.start_count(CountScope::Row(0)).unwrap();
+
+        shop.record_count(&id, &milk_zone, "Leite", 3).unwrap();
+        shop.record_count(&id, &milk_zone, "Iogurte", 2).unwrap();
+        shop.record_count(&id, &location(0, 0, 1), "Pão", 5)
+            .unwrap();
+        shop.record_count(&id, &location(0, 1, 0), "Ovos", 13)
+            .unwrap();
+        let report = shop.approve_count(&id, "SHRINKAGE").unwrap();
+        assert_eq!(report.variances.len(), 3);
+
+        // 11 units missing: the whole first lot and one unit of the second
+        assert_eq!(shop.get_item(&milk_zone, &milk.uuid).unwrap().quantity, 0);
+        assert_eq!(shop.get_item(&milk_zone, &second.uuid).unwrap().quantity, 3);
+        assert_eq!(shop.stock_of("Ovos"), 13);
+        assert_eq!(shop.stock_of("Iogurte"), 0);
+        assert_eq!(
+            shop.ledger.for_item(&milk.uuid)[0].reason,
+            MovementReason::Count {
+                session_id: id,
+                code: "SHRINKAGE".to_string()
+            }
+        );
+
+        let history = shop.count_history(&milk_zone);
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].expected, history[0].counted), (14, 3));
+        assert_eq!(history[1].name, "Iogurte");
+        assert_eq!(shop.count_history(&location(0, 0, 1))[0].counted, 5);
+        assert!(shop.count_history(&location(0, 1, 1)).is_empty());
+        assert!(shop.count_session(&id).is_none());
+        assert_eq!(
+            shop.approve_count(&id, "SHRINKAGE"),
+            Err(ShopError::UnknownCountSession(id))
+        );
+    }
+
+    #[test]
+    fn test_approval_fails_when_the_counted_lot_is_gone() {
+        let (mut shop, milk) = shop();
+        let milk_zone = location(0, 0, 0);
+        let id = shop.start_count(CountScope::Row(0)).unwrap();
+        shop.record_count(&id, &milk_zone, "Leite", 12).unwrap();
+        shop.remove_item(&milk_zone, &milk.uuid).unwrap();
+
+        // The surplus has no lot to go to, so nothing is approved
+        assert_eq!(
+            shop.approve_count(&id, "FOUND"),
+            Err(ShopError::CountMismatch {
+                location: milk_zone.clone(),
+                name: "Leite".to_string()
+            })
+        );
+        assert!(shop.count_session(&id).is_some());
+        assert!(shop.count_history(&milk_zone).is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+// Errors returned by the GroceryShop, Row and Rack operations.
+
+use crate::{BarcodeError, CountScope, Location, MoneyError, Sku};
+use std::fmt;
+use uuid::Uuid;
+
+/// The kind of limit reported by `ShopError::CapacityExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapacityLimit {
+    /// Units of product a zone can hold.
+    ZoneUnits,
+    /// Total weight, in grams, a rack can hold.
+    RackWeight,
+    /// Total volume, in millilitres, a rack can hold.
+    RackVolume,
+}
+
+impl fmt::Display for CapacityLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapacityLimit::ZoneUnits => write!(f, "Zone units"),
+            CapacityLimit::RackWeight => write!(f, "Rack weight (g)"),
+            CapacityLimit::RackVolume => write!(f, "Rack volume (ml)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShopError {
+    /// The row, rack or zone addressed by the location does not exist.
+    LocationNotFound(Location),
+    /// The zone exists but holds no lot with that uuid.
+    ItemNotFound { location: Location, uuid: Uuid },
+    /// The zone already holds `max_lots` lots.
+    ZoneFull { location: Location, max_lots: u32 },
+    /// Another item with the same uuid is already stored at `location`.
+    DuplicateUuid { uuid: Uuid, location: Location },
+    /// Merging into the zone would overflow the item quantity.
+    QuantityOverflow(Location),
+    /// Taking `requested` units would leave a negative quantity at `location`.
+    QuantityUnderflow {
+        location: Location,
+        available: u32,
+        requested: u64,
+    },
+    /// Fewer than `requested` units of the product are in stock.
+    InsufficientStock {
+        name: String,
+        requested: u32,
+        available: u64,
+    },
+    /// Storing `requested` would go over the `max` allowed by `limit` at `location`.
+    CapacityExceeded {
+        location: Location,
+        limit: CapacityLimit,
+        max: u64,
+        requested: u64,
+    },
+    /// No zone in the shop can take the item.
+    ShopFull,
+    /// Row `row_id` already holds `max_capacity` racks, so rack `rack_id`
+    /// was not added.
+    RowFull {
+        row_id: u32,
+        rack_id: u32,
+        max_capacity: u32,
+    },
+    /// Rack `rack_id` of row `row_id` already holds `max_capacity` zones, so
+    /// zone `zone_id` was not added.
+    RackFull {
+        row_id: u32,
+        rack_id: u32,
+        zone_id: u32,
+        max_capacity: u32,
+    },
+    /// Reading or writing a snapshot file failed.
+    Io(String),
+    /// The snapshot could not be parsed or breaks a layout rule.
+    InvalidSnapshot(String),
+    /// The snapshot was written by a newer (or unknown) format version.
+    UnsupportedSnapshotVersion { found: u64, supported: u32 },
+    /// No lot with that uuid is stored in the shop.
+    UnknownUuid(Uuid),
+    /// A scheduled price change has an empty or inverted date range.
+    InvalidPriceChange(String),
+    /// A reorder policy must aim above its reorder point.
+    InvalidReorderPolicy {
+        reorder_point: u32,
+        target_level: u32,
+    },
+    /// A checkout was committed with nothing in the basket.
+    EmptyBasket,
+    /// No sale with that id was committed in the shop.
+    UnknownSale(Uuid),
+    /// The sale with that id has already been voided.
+    SaleAlreadyVoided(Uuid),
+    /// No supplier with that id is known to the shop.
+    UnknownSupplier(Uuid),
+    /// No purchase order with that id was placed.
+    UnknownPurchaseOrder(Uuid),
+    /// The purchase order was already received in full or cancelled.
+    PurchaseOrderClosed(Uuid),
+    /// A purchase order must order at least one unit of every line.
+    InvalidPurchaseOrder(String),
+    /// The row or rack to count holds no zones.
+    EmptyCountScope(CountScope),
+    /// No open count session with that id.
+    UnknownCountSession(Uuid),
+    /// The zone is not part of the count session.
+    OutOfCountScope {
+        session_id: Uuid,
+        location: Location,
+    },
+    /// The zone no longer holds the lots of `name` a count variance adjusts.
+    CountMismatch { location: Location, name: String },
+    /// No product with that SKU is in the catalogue.
+    UnknownSku(Sku),
+    /// The catalogue already holds a product with that SKU.
+    DuplicateSku(Sku),
+    /// The name and regular price of a lot of a catalogue product are set on the product.
+    CatalogueProduct { uuid: Uuid, sku: Sku },
+    /// A text query could not be parsed.
+    InvalidQuery(String),
+    /// Adding up prices failed: mixed currencies or overflow.
+    Money(MoneyError),
+    /// Scanner input that is not a valid barcode.
+    Barcode(BarcodeError),
+}
+
+impl fmt::Display for ShopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShopError::LocationNotFound(location) => {
+                write!(f, "Location {} does not exist", location)
+            }
+            ShopError::ItemNotFound { location, uuid } => {
+                write!(f, "No item {} at {}", uuid, location)
+            }
+            ShopError::ZoneFull { location, max_lots } => {
+                write!(f, "{} is full (max {} lots)", location, max_lots)
+            }
+            ShopError::DuplicateUuid { uuid, location } => {
+                write!(f, "Item {} is already stored at {}", uuid, location)
+            }
+            ShopError::QuantityOverflow(location) => write!(f, "Quantity overflow at {}", location),
+            ShopError::QuantityUnderflow {
+                location,
+                available,
+                requested,
+            } => write!(
+                f,
+                "Not enough stock at {}. Available: {}, Requested: {}",
+                location, available, requested
+            ),
+            ShopError::InsufficientStock {
+                name,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Not enough '{}' in stock. Available: {}, Requested: {}",
+                name, available, requested
+            ),
+            ShopError::CapacityExceeded {
+                location,
+                limit,
+                max,
+                requested,
+            } => write!(
+                f,
+                "{} limit exceeded at {}. Max: {}, Requested: {}",
+                limit, location, max, requested
+            ),
+            ShopError::ShopFull => write!(f, "No zone in the shop can take the item"),
+            ShopError::RowFull {
+                row_id,
+                rack_id,
+                max_capacity,
+            } => write!(
+                f,
+                "Row {} is full (max capacity {}): cannot add rack {}",
+                row_id, max_capacity, rack_id
+            ),
+            ShopError::RackFull {
+                row_id,
+                rack_id,
+                zone_id,
+                max_capacity,
+            } => write!(
+                f,
+                "Rack {} in row {} is full (max capacity {}): cannot add zone {}",
+                rack_id, row_id, max_capacity, zone_id
+            ),
+            ShopError::Io(message) => write!(f, "I/O error: {}", message),
+            ShopError::InvalidSnapshot(message) => write!(f, "Invalid snapshot: {}", message),
+            ShopError::UnsupportedSnapshotVersion { found, supported } => write!(
+                f,
+                "Unsupported snapshot version {} (latest supported is {})",
+                found, supported
+            ),
+            ShopError::UnknownUuid(uuid) => write!(f, "No item {} in the shop", uuid),
+            ShopError::InvalidPriceChange(message) => {
+                write!(f, "Invalid price change: {}", message)
+            }
+            ShopError::InvalidReorderPolicy {
+                reorder_point,
+                target_level,
+            } => write!(
+                f,
+                "Target level {} must be above the reorder point {}",
+                target_level, reorder_point
+            ),
+            ShopError::EmptyBasket => write!(f, "The basket is empty"),
+            ShopError::UnknownSale(id) => write!(f, "No sale {} was made in this shop", id),
+            ShopError::SaleAlreadyVoided(id) => write!(f, "Sale {} has already been voided", id),
+            ShopError::UnknownSupplier(id) => write!(f, "No supplier {}", id),
+            ShopError::UnknownPurchaseOrder(id) => write!(f, "No purchase order {}", id),
+            ShopError::PurchaseOrderClosed(id) => {
+                write!(f, "Purchase order {} is no longer open", id)
+            }
+            ShopError::InvalidPurchaseOrder(message) => {
+                write!(f, "Invalid purchase order: {}", message)
+            }
+            ShopError::EmptyCountScope(scope) => write!(f, "There are no zones in {}", scope),
+            ShopError::UnknownCountSession(id) => write!(f, "No open count session {}", id),
+            ShopError::OutOfCountScope {
+                session_id,
+                location,
+            } => write!(f, "{} is not counted by session {}", location, session_id),
+            ShopError::CountMismatch { location, name } => write!(
+                f,
+                "Cannot adjust {} at {} to the count: its lots changed since the count started",
+                name, location
+            ),
+            ShopError::UnknownSku(sku) => write!(f, "No product {} in the catalogue", sku),
+            ShopError::DuplicateSku(sku) => {
+                write!(f, "Product {} is already in the catalogue", sku)
+            }
+            ShopError::CatalogueProduct { uuid, sku } => write!(
+                f,
+                "Lot {} is stock of product {}: change the product in the catalogue",
+                uuid, sku
+            ),
+            ShopError::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            ShopError::Money(err) => write!(f, "{}", err),
+            ShopError::Barcode(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl ShopError {
+    /// Names the row a `RowFull` error happened in; other errors are kept.
+    pub fn in_row(self, row_id: u32) -> Self {
+        match self {
+            ShopError::RowFull {
+                rack_id,
+                max_capacity,
+                ..
+            } => ShopError::RowFull {
+                row_id,
+                rack_id,
+                max_capacity,
+            },
+            other => other,
+        }
+    }
+
+    /// Names the rack a `RackFull` error happened in; other errors are kept.
+    pub fn in_rack(self, row_id: u32, rack_id: u32) -> Self {
+        match self {
+            ShopError::RackFull {
+                zone_id,
+                max_capacity,
+                ..
+            } => ShopError::RackFull {
+                row_id,
+                rack_id,
+                zone_id,
+                max_capacity,
+            },
+            other => other,
+        }
+    }
+}
+
+impl std::error::Error for ShopError {}
+
+impl From<MoneyError> for ShopError {
+    fn from(err: MoneyError) -> Self {
+        ShopError::Money(err)
+    }
+}
+
+impl From<BarcodeError> for ShopError {
+    fn from(err: BarcodeError) -> Self {
+        ShopError::Barcode(err)
+    }
+}
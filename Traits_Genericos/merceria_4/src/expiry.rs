@@ -0,0 +1,231 @@
+// Expiry queries and first-expired-first-out (FEFO) picking.
+
+use crate::{GroceryShop, Item, Location, MovementReason, ShopError};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+/// One line of a pick list: take `quantity` units of lot `uuid` from `location`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickLine {
+    pub location: Location,
+    pub uuid: Uuid,
+    pub quantity: u32,
+}
+
+impl<T: Item> GroceryShop<T> {
+    fn items_expiring_in(
+        &self,
+        after: Option<DateTime<Utc>>,
+        until: DateTime<Utc>,
+    ) -> Vec<(Location, &T)> {
+        // Items whose expiration date falls in (after, until], earliest first
+        let mut items: Vec<(Location, &T)> = self
+            .uuid_index
+            .iter()
+            .filter_map(|(uuid, location)| Some((location.clone(), self.get_item(location, uuid)?)))
+            .filter(|(_, item)| match item.expiration_date() {
+                Some(date) => date <= until && after.is_none_or(|after| date > after),
+                None => false,
+            })
+            .collect();
+        items.sort_by_key(|(location, item)| (item.expiration_date(), location.clone()));
+        items
+    }
+
+    pub fn expired_as_of(&self, date: DateTime<Utc>) -> Vec<(Location, &T)> {
+        // Items that have expired on or before the given date
+        self.items_expiring_in(None, date)
+    }
+
+    pub fn expiring_within(&self, duration: Duration) -> Vec<(Location, &T)> {
+        // Items that are still good now but expire within the given duration
+        let now = Utc::now();
+        self.items_expiring_in(Some(now), now + duration)
+    }
+
+    pub fn pick(&mut self, name: &str, quantity: u32) -> Result<Vec<PickLine>, ShopError> {
+        // Take stock of a product from its earliest-expiring lots first.
+        // Nothing is changed if there is not enough stock.
+        let pick_list = self.plan_pick(name, quantity)?;
+        for line in &pick_list {
+            self.adjust(
+                &line.location,
+                &line.uuid,
+                -i64::from(line.quantity),
+                MovementReason::Pick,
+            )
+            .expect("picked lots hold at least the picked quantity");
+        }
+        Ok(pick_list)
+    }
+
+    pub fn plan_pick(&self, name: &str, quantity: u32) -> Result<Vec<PickLine>, ShopError> {
+        // The lots `pick` would take stock from, without taking it.
+        // Expired lots are never picked.
+        let now = Utc::now();
+        let mut lots: Vec<(Location, &T)> = self
+            .name_index
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|lot| {
+                Some((
+                    lot.location.clone(),
+                    self.get_item(&lot.location, &lot.uuid)?,
+                ))
+            })
+            .filter(|(_, item)| item.quantity() > 0)
+            .filter(|(_, item)| item.expiration_date().is_none_or(|date| date > now))
+            .collect();
+        // Lots without an expiration date go last
+        lots.sort_by_key(|(location, item)| {
+            (
+                item.expiration_date().is_none(),
+                item.expiration_date(),
+                location.clone(),
+            )
+        });
+
+        let available: u64 = lots
+            .iter()
+            .map(|(_, item)| u64::from(item.quantity()))
+            .sum();
+        if available < u64::from(quantity) {
+            return Err(ShopError::InsufficientStock {
+                name: name.to_string(),
+                requested: quantity,
+                available,
+            });
+        }
+
+        let mut pick_list = Vec::new();
+        let mut remaining = quantity;
+        for (location, item) in lots {
+            if remaining == 0 {
+                break;
+            }
+            let taken = remaining.min(item.quantity());
+            remaining -= taken;
+            pick_list.push(PickLine {
+                location,
+                uuid: item.uuid(),
+                quantity: taken,
+            });
+        }
+        Ok(pick_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expiring_item, location, shop_with};
+
+    #[test]
+    fn test_expiry_queries() {
+        let expired = expiring_item("Milk", 1, -1);
+        let soon = expiring_item("Milk", 2, 2);
+        let later = expiring_item("Milk", 3, 30);
+        let shop = shop_with(vec![
+            (later.clone(), location(0, 0, 0)),
+            (soon.clone(), location(0, 0, 1)),
+            (expired.clone(), location(1, 0, 0)),
+        ]);
+
+        let expired_now = shop.expired_as_of(Utc::now());
+        assert_eq!(expired_now, vec![(location(1, 0, 0), &expired)]);
+
+        let expiring = shop.expiring_within(Duration::days(7));
+        assert_eq!(expiring, vec![(location(0, 0, 1), &soon)]);
+
+        let by_next_year = shop.expired_as_of(Utc::now() + Duration::days(365));
+        assert_eq!(by_next_year.len(), 3);
+        assert_eq!(by_next_year[2].1, &later);
+    }
+
+    #[test]
+    fn test_pick_uses_earliest_expiring_lots_first() {
+        let expired = expiring_item("Milk", 10, -1);
+        let soon = expiring_item("Milk", 2, 2);
+        let later = expiring_item("Milk", 5, 10);
+        let mut shop = shop_with(vec![
+            (later.clone(), location(0, 0, 0)),
+            (soon.clone(), location(2, 1, 1)),
+            (expired.clone(), location(1, 0, 0)),
+        ]);
+
+        let pick_list = shop.pick("Milk", 4).unwrap();
+        assert_eq!(
+            pick_list,
+            vec![
+                PickLine {
+                    location: location(2, 1, 1),
+                    uuid: soon.uuid,
+                    quantity: 2,
+                },
+                PickLine {
+                    location: location(0, 0, 0),
+                    uuid: later.uuid,
+                    quantity: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            shop.get_item(&location(2, 1, 1), &soon.uuid)
+                .unwrap()
+                .quantity,
+            0
+        );
+        assert_eq!(
+            shop.get_item(&location(0, 0, 0), &later.uuid)
+                .unwrap()
+                .quantity,
+            3
+        );
+        assert_eq!(
+            shop.get_item(&location(1, 0, 0), &expired.uuid)
+                .unwrap()
+                .quantity,
+            10
+        );
+        assert_eq!(
+            shop.ledger.for_item(&soon.uuid)[0].reason,
+            MovementReason::Pick
+        );
+    }
+
+    #[test]
+    fn test_pick_without_enough_stock_changes_nothing() {
+        let lot = expiring_item("Milk", 3, 5);
+        let mut shop = shop_with(vec![(lot.clone(), location(0, 1, 0))]);
+
+        assert_eq!(
+            shop.pick("Milk", 4),
+            Err(ShopError::InsufficientStock {
+                name: "Milk".to_string(),
+                requested: 4,
+                available: 3,
+            })
+        );
+        assert_eq!(
+            shop.get_item(&location(0, 1, 0), &lot.uuid)
+                .unwrap()
+                .quantity,
+            3
+        );
+    }
+
+    #[test]
+    fn test_pick_counts_stock_beyond_u32() {
+        let first = expiring_item("Milk", u32::MAX, 5);
+        let mut shop = shop_with(vec![
+            (first.clone(), location(0, 0, 0)),
+            (expiring_item("Milk", u32::MAX, 6), location(0, 0, 1)),
+        ]);
+
+        let pick_list = shop.pick("Milk", u32::MAX).unwrap();
+        assert_eq!(pick_list.len(), 1);
+        assert_eq!(pick_list[0].uuid, first.uuid);
+        assert_eq!(shop.stock_of("Milk"), u64::from(u32::MAX));
+    }
+}
@@ -0,0 +1,217 @@
+// Mutable access to an item that keeps the shop indexes in sync.
+//
+// GroceryShop::get_item_mut hands out an ItemGuard instead of a plain
+// `&mut T`. Edits through the guard change a copy of the lot; nothing is
+// stored until `commit`, and a guard dropped without it leaves the lot as it
+// was. `commit` refuses an edit the shop could not hold (a uuid already used
+// by another lot, a SKU missing from the catalogue, more units, weight or
+// volume than the zone and its rack take), then stores the lot,
+// moves the name, uuid and barcode index entries if they changed, records a
+// price edit of a loose article in the shop's price book and a quantity edit
+// in its ledger, and reports the edit to the shop's observers.
+
+use crate::{
+    Article, GroceryShop, Item, Location, MovementReason, PriceChange, ShopError, ShopEvent,
+    StockMovement,
+};
+use chrono::Utc;
+use std::ops::{Deref, DerefMut};
+
+pub struct ItemGuard<'a, T: Item> {
+    shop: &'a mut GroceryShop<T>,
+    location: Location,
+    index: usize,
+    before: T,
+    item: T,
+}
+
+impl<'a, T: Item> ItemGuard<'a, T> {
+    pub(crate) fn new(shop: &'a mut GroceryShop<T>, location: Location, index: usize) -> Self {
+        // The caller makes sure a lot is stored at that index of the zone
+        let before = shop.get_zone(&location).expect("guarded zone exists").lots[index].clone();
+        ItemGuard {
+            shop,
+            location,
+            index,
+            item: before.clone(),
+            before,
+        }
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.location
+    }
+
+    pub fn commit(self) -> Result<(), ShopError> {
+        // Store the edited lot, record a price or quantity edit, re-index the
+        // lot if its name, uuid or GTIN was edited and tell the observers
+        let ItemGuard {
+            shop,
+            location,
+            index,
+            before,
+            item,
+        } = self;
+        if item == before {
+            return Ok(());
+        }
+        let uuid = before.uuid();
+
+        if item.uuid() != uuid {
+            if let Some(existing) = shop.uuid_index.get(&item.uuid()) {
+                return Err(ShopError::DuplicateUuid {
+                    uuid: item.uuid(),
+                    location: existing.clone(),
+                });
+            }
+        }
+        if let Some(sku) = item.sku() {
+            if shop.catalogue.get(sku).is_none() {
+                return Err(ShopError::UnknownSku(sku.clone()));
+            }
+        }
+        shop.get_zone_mut(&location)
+            .expect("guarded zone exists")
+            .lots[index] = item.clone();
+        if item.quantity() > before.quantity()
+            || item.unit_weight_grams() > before.unit_weight_grams()
+            || item.unit_volume_ml() > before.unit_volume_ml()
+        {
+            // The edited lot is already stored, so nothing more is added
+            let zone_units = shop
+                .get_zone(&location)
+                .expect("guarded zone exists")
+                .units();
+            if let Err(err) = shop.check_capacity(&location, &item, zone_units, 0) {
+                shop.get_zone_mut(&location)
+                    .expect("guarded zone exists")
+                    .lots[index] = before;
+                return Err(err);
+            }
+        }
+
+        if let (Article::Loose { price: listed, .. }, Article::Loose { price, .. }) =
+            (before.article(), item.article())
+        {
+            if price != listed {
+                let now = Utc::now();
+                shop.prices.record(
+                    item.uuid(),
+                    Some(*listed),
+                    PriceChange {
+                        recorded_at: now,
+                        from: now,
+                        until: None,
+                        price: *price,
+                        reason: "edited".to_string(),
+                    },
+                );
+            }
+        }
+
+        if item.quantity() != before.quantity() {
+            shop.ledger.record(StockMovement {
+                timestamp: Utc::now(),
+                uuid: item.uuid(),
+                location: location.clone(),
+                delta: i64::from(item.quantity()) - i64::from(before.quantity()),
+                quantity_after: item.quantity(),
+                reason: MovementReason::Edit,
+            });
+        }
+
+        if shop.name_of(&item) != shop.name_of(&before) || item.uuid() != uuid {
+            let before = before.clone();
+            shop.remove_item_from_name_index(&before, &location);
+            shop.add_item_to_name_index(&item, &location);
+
+            if item.uuid() != uuid {
+                shop.uuid_index.remove(&uuid);
+                shop.uuid_index.insert(item.uuid(), location.clone());
+            }
+        }
+
+        if item.gtin() != before.gtin() || item.uuid() != uuid {
+            let before = before.clone();
+            shop.remove_item_from_barcode_index(&before, &location);
+            shop.add_item_to_barcode_index(&item, &location);
+        }
+
+        shop.observers.notify(ShopEvent::Edited {
+            before,
+            after: item,
+            location,
+        });
+        Ok(())
+    }
+}
+
+impl<T: Item> Deref for ItemGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.item
+    }
+}
+
+impl<T: Item> DerefMut for ItemGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{item, location, product_lot, shop_with};
+    use crate::{ShopError, Sku};
+
+    #[test]
+    fn test_edits_are_stored_only_on_commit() {
+        let milk = item("Leite", 3);
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+
+        if let Some(mut lot) = shop.get_item_mut(&location(0, 0, 0), &milk.uuid) {
+            lot.quantity = 7;
+        }
+        assert_eq!(shop.get_item(&location(0, 0, 0), &milk.uuid), Some(&milk));
+
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &milk.uuid).unwrap();
+        lot.quantity = 7;
+        lot.commit().unwrap();
+        assert_eq!(
+            shop.get_item(&location(0, 0, 0), &milk.uuid)
+                .unwrap()
+                .quantity,
+            7
+        );
+    }
+
+    #[test]
+    fn test_commit_rejects_duplicate_uuids_and_unknown_skus() {
+        let (milk, bread) = (item("Leite", 3), item("Pao", 2));
+        let mut shop = shop_with(vec![
+            (milk.clone(), location(0, 0, 0)),
+            (bread.clone(), location(1, 0, 0)),
+        ]);
+
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &milk.uuid).unwrap();
+        lot.uuid = bread.uuid;
+        assert_eq!(
+            lot.commit(),
+            Err(ShopError::DuplicateUuid {
+                uuid: bread.uuid,
+                location: location(1, 0, 0),
+            })
+        );
+
+        let mut lot = shop.get_item_mut(&location(0, 0, 0), &milk.uuid).unwrap();
+        lot.article = product_lot("NAT-002", 1).article;
+        assert_eq!(
+            lot.commit(),
+            Err(ShopError::UnknownSku(Sku::new("NAT-002")))
+        );
+
+        assert_eq!(shop.get_item(&location(0, 0, 0), &milk.uuid), Some(&milk));
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+}
@@ -0,0 +1,189 @@
+// Walking the shop hierarchy in location order.
+//
+// Rows, racks and zones are kept in BTreeMaps, so every iterator here yields
+// its results sorted by location (row, then rack, then zone). Lots sharing a
+// zone come out in the order they were added to it.
+//
+// `for_each_mut` is the mutable walk. It hands every lot out through an
+// ItemGuard and commits it, so the indexes, the price book and the observers
+// follow the edits as they do for `get_item_mut`.
+
+use crate::{GroceryShop, Item, ItemGuard, Location, Rack, Row, ShopError, Zone};
+use uuid::Uuid;
+
+fn rack_zones<T: Item>(
+    row_id: u32,
+    rack_id: u32,
+    rack: &Rack<T>,
+) -> impl Iterator<Item = (Location, &Zone<T>)> {
+    rack.zones.iter().map(move |(zone_id, zone)| {
+        let location = Location {
+            row_id,
+            rack_id,
+            zone_id: *zone_id,
+        };
+        (location, zone)
+    })
+}
+
+fn row_zones<T: Item>(row_id: u32, row: &Row<T>) -> impl Iterator<Item = (Location, &Zone<T>)> {
+    row.racks
+        .iter()
+        .flat_map(move |(rack_id, rack)| rack_zones(row_id, *rack_id, rack))
+}
+
+fn lots<'a, T: Item + 'a>(
+    zones: impl Iterator<Item = (Location, &'a Zone<T>)>,
+) -> impl Iterator<Item = (Location, &'a T)> {
+    zones.flat_map(|(location, zone)| zone.lots.iter().map(move |item| (location.clone(), item)))
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn iter(&self) -> impl Iterator<Item = (Location, &T)> {
+        // Every lot in the shop
+        lots(self.zones())
+    }
+
+    pub fn iter_row(&self, row_id: u32) -> impl Iterator<Item = (Location, &T)> {
+        // Every lot in one row, nothing if the row does not exist
+        lots(
+            self.rows
+                .get(&row_id)
+                .into_iter()
+                .flat_map(move |row| row_zones(row_id, row)),
+        )
+    }
+
+    pub fn iter_rack(&self, row_id: u32, rack_id: u32) -> impl Iterator<Item = (Location, &T)> {
+        // Every lot in one rack, nothing if the rack does not exist
+        lots(
+            self.rows
+                .get(&row_id)
+                .and_then(|row| row.racks.get(&rack_id))
+                .into_iter()
+                .flat_map(move |rack| rack_zones(row_id, rack_id, rack)),
+        )
+    }
+
+    pub fn for_each_mut<F>(&mut self, mut edit: F) -> Result<(), ShopError>
+    where
+        F: FnMut(&Location, &mut ItemGuard<'_, T>),
+    {
+        // Edit every lot in location order, committing each one; stops at the
+        // first edit the shop refuses, keeping the ones committed before it
+        let lots: Vec<(Location, Uuid)> = self
+            .iter()
+            .map(|(location, item)| (location, item.uuid()))
+            .collect();
+        for (location, uuid) in lots {
+            let mut guard = self
+                .get_item_mut(&location, &uuid)
+                .expect("lots listed above are stored");
+            edit(&location, &mut guard);
+            guard.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = (Location, &Zone<T>)> {
+        // Every zone in the shop, occupied or not
+        self.rows
+            .iter()
+            .flat_map(|(row_id, row)| row_zones(*row_id, row))
+    }
+
+    pub fn occupied_zones(&self) -> impl Iterator<Item = (Location, &Zone<T>)> {
+        // Zones holding at least one lot
+        self.zones().filter(|(_, zone)| !zone.lots.is_empty())
+    }
+
+    pub fn empty_zones(&self) -> impl Iterator<Item = Location> + '_ {
+        // Zones holding no lot at all
+        self.zones()
+            .filter(|(_, zone)| zone.lots.is_empty())
+            .map(|(location, _)| location)
+    }
+
+    pub fn zones_with_room(&self) -> impl Iterator<Item = Location> + '_ {
+        // Zones that can still take another lot
+        self.zones()
+            .filter(|(_, zone)| (zone.lots.len() as u32) < zone.max_lots)
+            .map(|(location, _)| location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{item, location};
+    use crate::{ExpirableItem, GroceryShop, LayoutBuilder, Location};
+
+    fn shop() -> GroceryShop<ExpirableItem> {
+        let mut shop = LayoutBuilder::new()
+            .rows(12)
+            .racks_per_row(11)
+            .zones_per_rack(2)
+            .lots_per_zone(2)
+            .build()
+            .unwrap();
+        shop.add_item(item("Queijo", 1), &location(11, 10, 1))
+            .unwrap();
+        shop.add_item(item("Leite", 1), &location(2, 1, 0)).unwrap();
+        shop.add_item(item("Pão", 1), &location(2, 10, 0)).unwrap();
+        shop.add_item(item("Manteiga", 1), &location(2, 1, 0))
+            .unwrap();
+        shop.add_item(item("Ovos", 1), &location(0, 0, 1)).unwrap();
+        shop
+    }
+
+    #[test]
+    fn test_iter_in_location_order() {
+        let shop = shop();
+
+        let names: Vec<&str> = shop.iter().map(|(_, item)| shop.name_of(item)).collect();
+        assert_eq!(names, vec!["Ovos", "Leite", "Manteiga", "Pão", "Queijo"]);
+
+        let locations: Vec<Location> = shop.iter_row(2).map(|(location, _)| location).collect();
+        assert_eq!(
+            locations,
+            vec![location(2, 1, 0), location(2, 1, 0), location(2, 10, 0)]
+        );
+        assert_eq!(shop.iter_rack(2, 10).count(), 1);
+        assert_eq!(shop.iter_rack(2, 99).count(), 0);
+        assert_eq!(shop.iter_row(99).count(), 0);
+    }
+
+    #[test]
+    fn test_occupied_and_empty_zones() {
+        let shop = shop();
+
+        assert_eq!(shop.zones().count(), 12 * 11 * 2);
+        assert_eq!(shop.occupied_zones().count(), 4);
+        assert_eq!(shop.empty_zones().count(), 12 * 11 * 2 - 4);
+        assert_eq!(shop.empty_zones().next(), Some(location(0, 0, 0)));
+        // Row 2 rack 1 zone 0 holds two lots and has no room left
+        assert_eq!(shop.zones_with_room().count(), 12 * 11 * 2 - 1);
+    }
+
+    #[test]
+    fn test_for_each_mut_in_location_order() {
+        let mut shop = shop();
+
+        let mut visited = Vec::new();
+        shop.for_each_mut(|location, lot| {
+            visited.push(location.clone());
+            lot.quantity += visited.len() as u32;
+        })
+        .unwrap();
+        assert_eq!(
+            visited,
+            shop.iter()
+                .map(|(location, _)| location)
+                .collect::<Vec<_>>()
+        );
+
+        let quantities: Vec<u32> = shop.iter().map(|(_, item)| item.quantity).collect();
+        assert_eq!(quantities, vec![2, 3, 4, 5, 6]);
+        assert_eq!(shop.ledger.movements().len(), 5);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+}
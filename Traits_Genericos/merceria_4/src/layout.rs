@@ -0,0 +1,252 @@
+// Declarative description of a shop layout (rows, racks and zones).
+//
+// The builder goes through Row::add_rack and Rack::add_zone so the usual
+// capacity rules apply, but instead of stopping at the first failure it
+// collects every violation and reports them together.
+
+use crate::{GroceryShop, Item, Rack, Row, ShopError};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Layout of a single row, overriding the builder defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowLayout {
+    pub racks: u32,
+    pub max_capacity: u32,
+    pub zones_per_rack: u32,
+    pub rack_capacity: u32,
+}
+
+/// A capacity rule broken while building a layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutViolation {
+    pub row_id: u32,
+    /// `None` when the violation is about adding a rack to the row.
+    pub rack_id: Option<u32>,
+    pub error: ShopError,
+}
+
+impl fmt::Display for LayoutViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rack_id {
+            Some(rack_id) => write!(f, "row {} / rack {}: {}", self.row_id, rack_id, self.error),
+            None => write!(f, "row {}: {}", self.row_id, self.error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LayoutBuilder {
+    rows: u32,
+    racks_per_row: u32,
+    zones_per_rack: u32,
+    row_capacity: Option<u32>,
+    rack_capacity: Option<u32>,
+    zone_unit_capacity: Option<u32>,
+    lots_per_zone: Option<u32>,
+    rack_weight_limit: Option<u64>,
+    rack_volume_limit: Option<u64>,
+    overrides: BTreeMap<u32, RowLayout>,
+}
+
+impl LayoutBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(mut self, rows: u32) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    pub fn racks_per_row(mut self, racks: u32) -> Self {
+        self.racks_per_row = racks;
+        self
+    }
+
+    pub fn zones_per_rack(mut self, zones: u32) -> Self {
+        self.zones_per_rack = zones;
+        self
+    }
+
+    /// Max number of racks per row. Defaults to `racks_per_row`.
+    pub fn row_capacity(mut self, max_capacity: u32) -> Self {
+        self.row_capacity = Some(max_capacity);
+        self
+    }
+
+    /// Max number of zones per rack. Defaults to `zones_per_rack`.
+    pub fn rack_capacity(mut self, max_capacity: u32) -> Self {
+        self.rack_capacity = Some(max_capacity);
+        self
+    }
+
+    /// Max units of product in every zone. Unlimited by default.
+    pub fn zone_unit_capacity(mut self, max_units: u32) -> Self {
+        self.zone_unit_capacity = Some(max_units);
+        self
+    }
+
+    /// Max number of lots every zone can hold. Defaults to one.
+    pub fn lots_per_zone(mut self, max_lots: u32) -> Self {
+        self.lots_per_zone = Some(max_lots);
+        self
+    }
+
+    /// Max weight, in grams, of every rack. Unlimited by default.
+    pub fn rack_weight_limit(mut self, max_weight_grams: u64) -> Self {
+        self.rack_weight_limit = Some(max_weight_grams);
+        self
+    }
+
+    /// Max volume, in millilitres, of every rack. Unlimited by default.
+    pub fn rack_volume_limit(mut self, max_volume_ml: u64) -> Self {
+        self.rack_volume_limit = Some(max_volume_ml);
+        self
+    }
+
+    /// Use a different layout for one row. Rows past `rows` are added as well.
+    pub fn row(mut self, row_id: u32, layout: RowLayout) -> Self {
+        self.overrides.insert(row_id, layout);
+        self
+    }
+
+    fn row_layout(&self, row_id: u32) -> RowLayout {
+        // Layout of a row, taking the overrides into account
+        self.overrides
+            .get(&row_id)
+            .cloned()
+            .unwrap_or_else(|| RowLayout {
+                racks: self.racks_per_row,
+                max_capacity: self.row_capacity.unwrap_or(self.racks_per_row),
+                zones_per_rack: self.zones_per_rack,
+                rack_capacity: self.rack_capacity.unwrap_or(self.zones_per_rack),
+            })
+    }
+
+    pub fn build<T: Item>(&self) -> Result<GroceryShop<T>, Vec<LayoutViolation>> {
+        // Build every row, collecting all the capacity violations on the way
+        let mut shop = GroceryShop::new();
+        let mut violations = Vec::new();
+
+        let row_ids =
+            (0..self.rows).chain(self.overrides.keys().copied().filter(|id| *id >= self.rows));
+        for row_id in row_ids {
+            let layout = self.row_layout(row_id);
+            let mut row = Row::new(layout.max_capacity);
+
+            for rack_id in 0..layout.racks {
+                if let Err(error) = row
+                    .add_rack(rack_id, layout.rack_capacity)
+                    .map_err(|error| error.in_row(row_id))
+                {
+                    violations.push(LayoutViolation {
+                        row_id,
+                        rack_id: None,
+                        error,
+                    });
+                    continue;
+                }
+
+                let rack: &mut Rack<T> = row.racks.get_mut(&rack_id).unwrap();
+                rack.max_weight_grams = self.rack_weight_limit;
+                rack.max_volume_ml = self.rack_volume_limit;
+                for zone_id in 0..layout.zones_per_rack {
+                    if let Err(error) = rack
+                        .add_zone(zone_id)
+                        .map_err(|error| error.in_rack(row_id, rack_id))
+                    {
+                        violations.push(LayoutViolation {
+                            row_id,
+                            rack_id: Some(rack_id),
+                            error,
+                        });
+                        continue;
+                    }
+                    let zone = rack.zones.get_mut(&zone_id).unwrap();
+                    zone.max_units = self.zone_unit_capacity;
+                    zone.max_lots = self.lots_per_zone.unwrap_or(1);
+                }
+            }
+            shop.rows.insert(row_id, row);
+        }
+
+        if violations.is_empty() {
+            Ok(shop)
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExpirableItem;
+
+    #[test]
+    fn test_build_uniform_layout() {
+        let shop = LayoutBuilder::new()
+            .rows(40)
+            .racks_per_row(6)
+            .zones_per_rack(4)
+            .rack_capacity(8)
+            .build::<ExpirableItem>()
+            .unwrap();
+
+        assert_eq!(shop.rows.len(), 40);
+        assert_eq!(shop.rows[&39].racks.len(), 6);
+        assert_eq!(shop.rows[&39].max_capacity, 6);
+        assert_eq!(shop.rows[&0].racks[&5].zones.len(), 4);
+        assert_eq!(shop.rows[&0].racks[&5].max_capacity, 8);
+    }
+
+    #[test]
+    fn test_build_reports_every_violation() {
+        let result = LayoutBuilder::new()
+            .rows(2)
+            .racks_per_row(2)
+            .zones_per_rack(3)
+            .rack_capacity(2)
+            .row(
+                1,
+                RowLayout {
+                    racks: 3,
+                    max_capacity: 1,
+                    zones_per_rack: 1,
+                    rack_capacity: 1,
+                },
+            )
+            .build::<ExpirableItem>();
+
+        let violations = result.unwrap_err();
+        // Row 0: one zone too many in each of its two racks
+        // Row 1: two racks too many
+        assert_eq!(violations.len(), 4);
+        assert_eq!(
+            violations[0],
+            LayoutViolation {
+                row_id: 0,
+                rack_id: Some(0),
+                error: ShopError::RackFull {
+                    row_id: 0,
+                    rack_id: 0,
+                    zone_id: 2,
+                    max_capacity: 2
+                },
+            }
+        );
+        assert_eq!(
+            violations[3],
+            LayoutViolation {
+                row_id: 1,
+                rack_id: None,
+                error: ShopError::RowFull {
+                    row_id: 1,
+                    rack_id: 2,
+                    max_capacity: 1
+                },
+            }
+        );
+    }
+}
@@ -24,99 +24,301 @@ A nossa merceria deve ser capaz de ser utilizada para um tipo de item genérico.
 Devemos manter todas as capacidades anteriores.
 */
 
-use ordered_float::OrderedFloat;
-use std::collections::HashMap;
+pub mod barcode;
+pub mod capacity;
+pub mod catalogue;
+pub mod checkout;
+pub mod cli;
+pub mod counting;
+pub mod error;
+pub mod expiry;
+pub mod item_guard;
+pub mod iter;
+pub mod layout;
+pub mod money;
+pub mod observer;
+pub mod persistence;
+pub mod placement;
+pub mod pricing;
+pub mod purchasing;
+pub mod query;
+pub mod reorder;
+pub mod search;
+pub mod stock;
+#[cfg(test)]
+mod test_support;
+pub mod transaction;
+pub mod valuation;
+
+pub use barcode::{BarcodeError, Gtin, GtinKind, Measure, Scan, ScanMatch};
+pub use catalogue::{Article, Catalogue, Product, ProductKey, Sku, Unit};
+pub use checkout::{Checkout, Sale, SaleLine};
+pub use counting::{
+    CountHistory, CountLine, CountRecord, CountScope, CountSession, Variance, VarianceKind,
+    VarianceReport,
+};
+pub use error::{CapacityLimit, ShopError};
+pub use item_guard::ItemGuard;
+pub use layout::LayoutBuilder;
+pub use money::{Currency, Money, MoneyError};
+pub use observer::{Observers, ShopEvent, ShopObserver};
+pub use placement::PlacementStrategy;
+pub use pricing::{PriceBook, PriceChange};
+pub use purchasing::{
+    DeliveredLot, Discrepancy, OrderLine, OrderStatus, PurchaseOrder, ReceivingReport, Supplier,
+};
+pub use query::{Comparison, Filter, Query, SortKey};
+pub use reorder::{AlertSubscribers, LowStock, LowStockAlert, PurchaseSuggestion, ReorderPolicy};
+pub use search::{MatchKind, SearchHit};
+pub use stock::{Ledger, MovementReason, StockMovement};
+pub use transaction::Transaction;
+pub use valuation::{ValuationLine, ValuationReport};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Location {
     pub row_id: u32,
     pub rack_id: u32,
     pub zone_id: u32,
 }
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "row {} / rack {} / zone {}",
+            self.row_id, self.rack_id, self.zone_id
+        )
+    }
+}
+
+/// Where a single lot is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LotLocation {
+    pub location: Location,
+    pub uuid: Uuid,
+}
 
 #[derive(Debug, Clone)]
 pub struct GroceryShop<T: Item> {
-    pub rows: HashMap<u32, Row<T>>,
-    pub name_index: HashMap<String, Vec<Location>>,
+    pub rows: BTreeMap<u32, Row<T>>,
+    pub name_index: HashMap<String, Vec<LotLocation>>,
+    pub uuid_index: HashMap<Uuid, Location>,
+    pub barcode_index: HashMap<Gtin, Vec<LotLocation>>,
+    pub catalogue: Catalogue,
+    pub ledger: Ledger,
+    pub prices: PriceBook,
+    pub reorder_policies: BTreeMap<ProductKey, ReorderPolicy>,
+    pub low_stock_subscribers: AlertSubscribers,
+    pub observers: Observers<T>,
+    pub sales: BTreeMap<Uuid, Sale>,
+    pub voided_sales: BTreeSet<Uuid>,
+    pub suppliers: BTreeMap<Uuid, Supplier>,
+    pub purchase_orders: BTreeMap<Uuid, PurchaseOrder>,
+    pub count_sessions: BTreeMap<Uuid, CountSession>,
+    pub count_history: CountHistory,
+}
+impl<T: Item> Default for GroceryShop<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl<T: Item> GroceryShop<T> {
     pub fn new() -> Self {
         GroceryShop {
-            rows: HashMap::new(),
+            rows: BTreeMap::new(),
             name_index: HashMap::new(),
+            uuid_index: HashMap::new(),
+            barcode_index: HashMap::new(),
+            catalogue: Catalogue::new(),
+            ledger: Ledger::new(),
+            prices: PriceBook::new(),
+            reorder_policies: BTreeMap::new(),
+            low_stock_subscribers: AlertSubscribers::default(),
+            observers: Observers::default(),
+            sales: BTreeMap::new(),
+            voided_sales: BTreeSet::new(),
+            suppliers: BTreeMap::new(),
+            purchase_orders: BTreeMap::new(),
+            count_sessions: BTreeMap::new(),
+            count_history: CountHistory::new(),
         }
     }
 
     pub fn initialize(&mut self) {
         // GroceryShop with 3 rows, each with 2 racks and 2 zones
         // All the Racks and Rows have max capacity of 2
-        for row in 0..3 {
-            let mut racks = HashMap::new();
-            for rack in 0..2 {
-                let mut zones = HashMap::new();
-                for zone in 0..2 {
-                    zones.insert(zone, Zone { item: Option::None });
-                }
-                racks.insert(
-                    rack,
-                    Rack {
-                        zones,
-                        max_capacity: 2,
-                    },
-                );
+        let shop = LayoutBuilder::new()
+            .rows(3)
+            .racks_per_row(2)
+            .zones_per_rack(2)
+            .build::<T>()
+            .expect("the default layout is valid");
+        self.rows.extend(shop.rows);
+    }
+
+    pub fn add_item(&mut self, item: T, location: &Location) -> Result<(), ShopError> {
+        // Add item as a new lot in the specified zone, refusing zones that are full
+        self.add_item_with_mode(item, location, AddMode::Reject)
+    }
+
+    pub fn add_item_with_mode(
+        &mut self,
+        item: T,
+        location: &Location,
+        mode: AddMode,
+    ) -> Result<(), ShopError> {
+        // Add item to the specified zone, merging it into a matching lot if allowed.
+        // A lot of a product needs the product in the catalogue.
+        if let Some(sku) = item.sku() {
+            if self.catalogue.get(sku).is_none() {
+                return Err(ShopError::UnknownSku(sku.clone()));
+            }
+        }
+        let zone = self
+            .get_zone(location)
+            .ok_or_else(|| ShopError::LocationNotFound(location.clone()))?;
+        let zone_units = zone.units() + u64::from(item.quantity());
+
+        if mode == AddMode::Merge {
+            let price = self.current_price_of(&item)?;
+            if let Some(index) = zone.lots.iter().position(|lot| {
+                lot.can_merge_with(&item) && self.current_price_of(lot) == Ok(price)
+            }) {
+                // The existing lot keeps its uuid and its place in the indexes
+                let quantity = zone.lots[index]
+                    .quantity()
+                    .checked_add(item.quantity())
+                    .ok_or_else(|| ShopError::QuantityOverflow(location.clone()))?;
+                self.check_capacity(location, &item, zone_units, u64::from(item.quantity()))?;
+                let lot = &mut self.get_zone_mut(location).unwrap().lots[index];
+                let before = lot.clone();
+                lot.set_quantity(quantity);
+                let after = lot.clone();
+                self.ledger.record(StockMovement {
+                    timestamp: chrono::Utc::now(),
+                    uuid: after.uuid(),
+                    location: location.clone(),
+                    delta: i64::from(item.quantity()),
+                    quantity_after: quantity,
+                    reason: MovementReason::Merge(item.uuid()),
+                });
+                self.observers.notify(ShopEvent::Edited {
+                    before,
+                    after,
+                    location: location.clone(),
+                });
+                return Ok(());
             }
-            self.rows.insert(
-                row,
-                Row {
-                    racks,
-                    max_capacity: 2,
-                },
-            );
         }
-    }
 
-    pub fn add_item(&mut self, item: T, location: &Location) -> Result<(), String> {
-        // Add item to the specified location in the warehouse
-        if let Some(zone) = self.get_zone_mut(location) {
-            zone.item = Some(item.clone());
-            self.add_item_to_name_index(&item, location);
-            Ok(())
-        } else {
-            Err("Could not add item".to_string())
+        if zone.lots.len() as u32 >= zone.max_lots {
+            return Err(ShopError::ZoneFull {
+                location: location.clone(),
+                max_lots: zone.max_lots,
+            });
+        }
+        if let Some(existing) = self.uuid_index.get(&item.uuid()) {
+            return Err(ShopError::DuplicateUuid {
+                uuid: item.uuid(),
+                location: existing.clone(),
+            });
         }
+        self.check_capacity(location, &item, zone_units, u64::from(item.quantity()))?;
+
+        self.observers.notify(ShopEvent::Added {
+            item: item.clone(),
+            location: location.clone(),
+        });
+        self.insert_item(item, location);
+        Ok(())
     }
 
-    pub fn remove_item(&mut self, location: &Location) -> Result<(), String> {
-        // Remove item from the specified location in the warehouse
+    fn insert_item(&mut self, item: T, location: &Location) {
+        // Store a lot the zone has room for and index it, without any notification
+        self.add_item_to_name_index(&item, location);
+        self.add_item_to_barcode_index(&item, location);
+        self.uuid_index.insert(item.uuid(), location.clone());
+        self.get_zone_mut(location).unwrap().lots.push(item);
+    }
 
-        if let Some(zone) = self.get_zone_mut(location) {
-            // zone.item = None;
-            // Remove item from the name index
-            if let Some(item) = zone.item.take() {
-                self.remove_item_from_name_index(&item, location);
-            }
-            Ok(())
-        } else {
-            Err("Could not remove item".to_string())
+    pub fn remove_item(&mut self, location: &Location, uuid: &Uuid) -> Result<T, ShopError> {
+        // Remove a lot from the specified zone and return it
+        let product = self
+            .get_item(location, uuid)
+            .map(|item| item.article().key());
+        let stock_before = product.as_ref().map_or(0, |product| self.units_of(product));
+        let item = self.take_item(location, uuid)?;
+        self.prices.forget(uuid);
+        self.observers.notify(ShopEvent::Removed {
+            item: item.clone(),
+            location: location.clone(),
+        });
+        if let Some(product) = product {
+            self.check_reorder_point(&product, stock_before);
         }
+        Ok(item)
     }
 
-    pub fn get_item(&self, location: &Location) -> Option<&T> {
-        // Get item from the specified location in the warehouse
-        if let Some(zone) = self.get_zone(location) {
-            return zone.item.as_ref();
-        }
-        None
+    fn take_item(&mut self, location: &Location, uuid: &Uuid) -> Result<T, ShopError> {
+        // Remove a lot from its zone and the indexes, without any notification
+        let zone = self
+            .get_zone_mut(location)
+            .ok_or_else(|| ShopError::LocationNotFound(location.clone()))?;
+        let index = zone
+            .lots
+            .iter()
+            .position(|lot| lot.uuid() == *uuid)
+            .ok_or_else(|| ShopError::ItemNotFound {
+                location: location.clone(),
+                uuid: *uuid,
+            })?;
+        let item = zone.lots.remove(index);
+
+        // Remove item from the name, barcode and uuid indexes
+        self.remove_item_from_name_index(&item, location);
+        self.remove_item_from_barcode_index(&item, location);
+        self.uuid_index.remove(&item.uuid());
+        Ok(item)
     }
 
-    pub fn get_item_mut(&mut self, location: &Location) -> Option<&mut T> {
-        // Get mutable reference to item from the specified location in the warehouse
-        if let Some(zone) = self.get_zone_mut(location) {
-            return zone.item.as_mut();
+    pub fn get_item(&self, location: &Location, uuid: &Uuid) -> Option<&T> {
+        // Get a single lot from the specified zone
+        self.get_zone(location)?
+            .lots
+            .iter()
+            .find(|lot| lot.uuid() == *uuid)
+    }
+
+    pub fn get_items(&self, location: &Location) -> &[T] {
+        // Get every lot in the specified zone, in the order they were added
+        match self.get_zone(location) {
+            Some(zone) => &zone.lots,
+            None => &[],
         }
+    }
 
-        None
+    pub fn get_item_mut(&mut self, location: &Location, uuid: &Uuid) -> Option<ItemGuard<'_, T>> {
+        // Get a guarded mutable copy of a lot, stored and re-indexed by its commit
+        let index = self
+            .get_zone(location)?
+            .lots
+            .iter()
+            .position(|lot| lot.uuid() == *uuid)?;
+        Some(ItemGuard::new(self, location.clone(), index))
+    }
+
+    pub fn get_location_by_uuid(&self, uuid: &Uuid) -> Option<&Location> {
+        // Get location of the lot with the given uuid from the uuid index
+        self.uuid_index.get(uuid)
+    }
+
+    pub fn get_item_by_uuid(&self, uuid: &Uuid) -> Option<&T> {
+        // Get the lot with the given uuid through the uuid index
+        self.get_item(self.uuid_index.get(uuid)?, uuid)
     }
 
     pub fn get_zone(&self, location: &Location) -> Option<&Zone<T>> {
@@ -140,63 +342,73 @@ impl<T: Item> GroceryShop<T> {
     }
 
     pub fn get_location_of_item_linear_time(&self, item: &T) -> Option<Location> {
-        // Get location of the specified item in the warehouse
-        for (row_id, row) in &self.rows {
-            for (rack_id, rack) in &row.racks {
-                for (zone_id, zone) in &rack.zones {
-                    if let Some(ref zone_item) = zone.item {
-                        if zone_item == item {
-                            return Some(Location {
-                                row_id: *row_id,
-                                rack_id: *rack_id,
-                                zone_id: *zone_id,
-                            });
-                        }
-                    }
-                }
-            }
-        }
-        None
+        // Get location of the specified item in the warehouse, the first one in location order
+        self.iter()
+            .find(|(_, stored)| *stored == item)
+            .map(|(location, _)| location)
     }
 
     pub fn move_item(
         &mut self,
         from_location: Location,
+        uuid: &Uuid,
         to_location: &Location,
-    ) -> Result<(), String> {
-        // First check if the item exists at the location
-        let item_exists = self.get_item(&from_location).is_some();
-
-        if item_exists {
-            // If item exists, get a clone of it before removing
-            let item = self.get_item(&from_location).unwrap().clone();
-
-            // Then remove it and add it to the new location
-            if let Err(err) = self.remove_item(&from_location) {
-                println!("Failed to remove item from the original location: {}", err);
-                return Err("Failed to move item due to removal error".to_string());
-            }
-            if let Ok(_) = self.add_item(item.clone(), &to_location) {
-                // Successfully moved the item
-                Ok(())
-            } else {
-                // If adding to the new location fails, add it back to the original location
-                self.add_item(item.clone(), &from_location)?;
-                println!("Failed to move item to the new location");
-                Err("Failed to move item to the new location".to_string())
-            }
-        } else {
-            println!("Item not found at the specified location");
-            Err("Item not found at the specified location".to_string())
+    ) -> Result<(), ShopError> {
+        // First check if the lot exists at the location
+        if self.get_zone(&from_location).is_none() {
+            return Err(ShopError::LocationNotFound(from_location));
+        }
+        let item = self
+            .get_item(&from_location, uuid)
+            .ok_or_else(|| ShopError::ItemNotFound {
+                location: from_location.clone(),
+                uuid: *uuid,
+            })?
+            .clone();
+        // Moving a lot to where it already is changes nothing
+        if from_location == *to_location {
+            return Ok(());
         }
+
+        // Then check the destination can take it, so nothing has to be undone later
+        let zone = self
+            .get_zone(to_location)
+            .ok_or_else(|| ShopError::LocationNotFound(to_location.clone()))?;
+        if zone.lots.len() as u32 >= zone.max_lots {
+            return Err(ShopError::ZoneFull {
+                location: to_location.clone(),
+                max_lots: zone.max_lots,
+            });
+        }
+
+        // Weight and volume only change when the item leaves its rack
+        let same_rack = from_location.row_id == to_location.row_id
+            && from_location.rack_id == to_location.rack_id;
+        let units = u64::from(item.quantity());
+        self.check_capacity(
+            to_location,
+            &item,
+            zone.units() + units,
+            if same_rack { 0 } else { units },
+        )?;
+
+        // Finally remove it and add it to the new location
+        self.take_item(&from_location, uuid)?;
+        self.observers.notify(ShopEvent::Moved {
+            item: item.clone(),
+            from: from_location,
+            to: to_location.clone(),
+        });
+        self.insert_item(item, to_location);
+        Ok(())
     }
 
     pub fn get_items_by_name(&self, name: &str) -> Vec<&T> {
         // Get items by name from the name index
         let mut items = Vec::new();
-        if let Some(locations) = self.name_index.get(name) {
-            for location in locations {
-                if let Some(item) = self.get_item(location) {
+        if let Some(lots) = self.name_index.get(name) {
+            for lot in lots {
+                if let Some(item) = self.get_item(&lot.location, &lot.uuid) {
                     items.push(item);
                 }
             }
@@ -205,116 +417,363 @@ impl<T: Item> GroceryShop<T> {
     }
 
     pub fn add_item_to_name_index(&mut self, item: &T, location: &Location) {
-        // Add item to the name index
-        let name = item.name().to_string();
-        let locations = self.name_index.entry(name).or_insert_with(Vec::new);
-        locations.push(location.clone());
+        // Add item to the name index, under its product name
+        let name = self.name_of(item).to_string();
+        let lot = LotLocation {
+            location: location.clone(),
+            uuid: item.uuid(),
+        };
+        let lots = self.name_index.entry(name).or_default();
+        if !lots.contains(&lot) {
+            lots.push(lot);
+        }
     }
 
     pub fn remove_item_from_name_index(&mut self, item: &T, location: &Location) {
         // Remove item from the name index
-        let name = item.name().to_string();
-        if let Some(locations) = self.name_index.get_mut(&name) {
-            locations.retain(|loc| loc != location);
-            if locations.is_empty() {
+        let name = self.name_of(item).to_string();
+        if let Some(lots) = self.name_index.get_mut(&name) {
+            lots.retain(|lot| !(lot.location == *location && lot.uuid == item.uuid()));
+            if lots.is_empty() {
                 self.name_index.remove(&name);
             }
         }
     }
+
+    fn collect_indexes(&self) -> (HashMap<String, Vec<LotLocation>>, HashMap<Uuid, Location>) {
+        // Build the name and uuid indexes from the lots actually stored in the zones
+        let mut name_index: HashMap<String, Vec<LotLocation>> = HashMap::new();
+        let mut uuid_index = HashMap::new();
+        for (location, item) in self.iter() {
+            name_index
+                .entry(self.name_of(item).to_string())
+                .or_default()
+                .push(LotLocation {
+                    location: location.clone(),
+                    uuid: item.uuid(),
+                });
+            uuid_index.insert(item.uuid(), location);
+        }
+        (name_index, uuid_index)
+    }
+
+    pub fn rebuild_indexes(&mut self) {
+        // Rebuild the name, uuid and barcode indexes from scratch
+        let (name_index, uuid_index) = self.collect_indexes();
+        self.name_index = name_index;
+        self.uuid_index = uuid_index;
+        self.barcode_index = self.collect_barcode_index();
+    }
+
+    pub fn verify_indexes(&self) -> Result<(), Vec<String>> {
+        // Cross-check both indexes against the zones, listing every mismatch found
+        let (expected_names, expected_uuids) = self.collect_indexes();
+        let mut problems = Vec::new();
+
+        for (name, expected) in &expected_names {
+            let indexed = self.name_index.get(name).cloned().unwrap_or_default();
+            for lot in expected {
+                if !indexed.contains(lot) {
+                    problems.push(format!(
+                        "'{}' ({}) at {} is missing from the name index",
+                        name, lot.uuid, lot.location
+                    ));
+                }
+            }
+        }
+        for (name, indexed) in &self.name_index {
+            let expected = expected_names.get(name).cloned().unwrap_or_default();
+            for lot in indexed {
+                if !expected.contains(lot) {
+                    problems.push(format!(
+                        "Name index points '{}' ({}) to {} but it is not there",
+                        name, lot.uuid, lot.location
+                    ));
+                }
+            }
+        }
+
+        // The uuid index keeps one location per uuid, so a lot stored twice
+        // only shows up when walking the zones
+        let mut stored: HashMap<Uuid, Location> = HashMap::new();
+        for (location, item) in self.iter() {
+            if let Some(first) = stored.insert(item.uuid(), location.clone()) {
+                problems.push(format!(
+                    "Lot {} is stored both at {} and at {}",
+                    item.uuid(),
+                    first,
+                    location
+                ));
+            }
+        }
+
+        for (uuid, location) in &expected_uuids {
+            match self.uuid_index.get(uuid) {
+                Some(indexed) if indexed == location => {}
+                Some(indexed) => problems.push(format!(
+                    "Uuid index points {} to {} but it is at {}",
+                    uuid, indexed, location
+                )),
+                None => problems.push(format!(
+                    "{} at {} is missing from the uuid index",
+                    uuid, location
+                )),
+            }
+        }
+        for (uuid, location) in &self.uuid_index {
+            if !expected_uuids.contains_key(uuid) {
+                problems.push(format!(
+                    "Uuid index points {} to {} but it is not there",
+                    uuid, location
+                ));
+            }
+        }
+
+        let expected_barcodes = self.collect_barcode_index();
+        for (gtin, expected) in &expected_barcodes {
+            let indexed = self.barcode_index.get(gtin).cloned().unwrap_or_default();
+            for lot in expected {
+                if !indexed.contains(lot) {
+                    problems.push(format!(
+                        "{} ({}) at {} is missing from the barcode index",
+                        gtin, lot.uuid, lot.location
+                    ));
+                }
+            }
+        }
+        for (gtin, indexed) in &self.barcode_index {
+            let expected = expected_barcodes.get(gtin).cloned().unwrap_or_default();
+            for lot in indexed {
+                if !expected.contains(lot) {
+                    problems.push(format!(
+                        "Barcode index points {} ({}) to {} but it is not there",
+                        gtin, lot.uuid, lot.location
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Row<T: Item> {
-    pub racks: HashMap<u32, Rack<T>>,
+    pub racks: BTreeMap<u32, Rack<T>>,
     pub max_capacity: u32,
 }
 impl<T: Item> Row<T> {
     pub fn new(max_capacity: u32) -> Self {
         Row {
-            racks: HashMap::new(),
+            racks: BTreeMap::new(),
             max_capacity,
         }
     }
 
-    pub fn add_rack(&mut self, rack_id: u32, max_capacity: u32) -> Result<(), String> {
-        // Check if the row has space for a new rack
+    pub fn add_rack(&mut self, rack_id: u32, max_capacity: u32) -> Result<(), ShopError> {
+        // Check if the row has space for a new rack. A row does not know its
+        // own id, so the error names row 0 until the caller sets it with
+        // `ShopError::in_row`.
         if self.racks.len() as u32 >= self.max_capacity {
-            return Err("Row is full".to_string());
+            return Err(ShopError::RowFull {
+                row_id: 0,
+                rack_id,
+                max_capacity: self.max_capacity,
+            });
         }
         self.racks.insert(rack_id, Rack::new(max_capacity));
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Rack<T: Item> {
-    pub zones: HashMap<u32, Zone<T>>,
+    pub zones: BTreeMap<u32, Zone<T>>,
     pub max_capacity: u32,
+    #[serde(default)]
+    pub max_weight_grams: Option<u64>,
+    #[serde(default)]
+    pub max_volume_ml: Option<u64>,
 }
 impl<T: Item> Rack<T> {
     pub fn new(max_capacity: u32) -> Self {
         Rack {
-            zones: HashMap::new(),
+            zones: BTreeMap::new(),
             max_capacity,
+            max_weight_grams: None,
+            max_volume_ml: None,
         }
     }
 
-    pub fn add_zone(&mut self, zone_id: u32) -> Result<(), String> {
-        // Check if the rack has space for a new zone
+    pub fn add_zone(&mut self, zone_id: u32) -> Result<(), ShopError> {
+        // Check if the rack has space for a new zone. The error names rack 0
+        // of row 0 until the caller sets them with `ShopError::in_rack`.
         if self.zones.len() as u32 >= self.max_capacity {
-            return Err("Rack is full".to_string());
+            return Err(ShopError::RackFull {
+                row_id: 0,
+                rack_id: 0,
+                zone_id,
+                max_capacity: self.max_capacity,
+            });
         }
-        self.zones.insert(zone_id, Zone { item: None });
+        self.zones.insert(zone_id, Zone::new(1));
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Zone<T: Item> {
-    pub item: Option<T>,
+    pub lots: Vec<T>,
+    pub max_lots: u32,
+    #[serde(default)]
+    pub max_units: Option<u32>,
+}
+impl<T: Item> Zone<T> {
+    pub fn new(max_lots: u32) -> Self {
+        Zone {
+            lots: Vec::new(),
+            max_lots,
+            max_units: None,
+        }
+    }
+
+    pub fn units(&self) -> u64 {
+        // Units of product stored across all the lots of the zone
+        self.lots.iter().map(|lot| u64::from(lot.quantity())).sum()
+    }
+}
+
+/// How `GroceryShop::add_item_with_mode` treats lots already in the zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddMode {
+    /// Always store the item as a new lot, failing with `ShopError::ZoneFull`
+    /// when the zone has no room for another lot.
+    #[default]
+    Reject,
+    /// Add the quantities together when the zone holds the same lot
+    /// (same article at the same current price, same expiration date and
+    /// GTIN), otherwise behave like `Reject`.
+    Merge,
 }
 
 pub trait Item: PartialEq + Eq + std::fmt::Debug + Clone {
-    fn name(&self) -> &str;
+    /// What the lot is stock of. The shop resolves its name and regular
+    /// price, through the catalogue for a product.
+    fn article(&self) -> &Article;
+    fn article_mut(&mut self) -> &mut Article;
     fn quantity(&self) -> u32;
+    fn set_quantity(&mut self, quantity: u32);
     fn uuid(&self) -> Uuid;
-    fn price(&self) -> OrderedFloat<f32>;
+
+    fn expiration_date(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        None
+    }
+
+    fn gtin(&self) -> Option<&Gtin> {
+        None
+    }
+
+    /// The catalogue product this lot is stock of, if any.
+    fn sku(&self) -> Option<&Sku> {
+        self.article().sku()
+    }
+
+    fn unit_weight_grams(&self) -> Option<u32> {
+        None
+    }
+
+    fn unit_volume_ml(&self) -> Option<u32> {
+        None
+    }
+
+    fn can_merge_with(&self, other: &Self) -> bool {
+        // Two items can share a lot when they only differ in quantity and uuid.
+        // The shop also checks that both sell at the same current price.
+        self.article().is_same_as(other.article())
+            && self.expiration_date() == other.expiration_date()
+            && self.gtin() == other.gtin()
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ExpirableItem {
-    pub name: String,
+    #[serde(flatten)]
+    pub article: Article,
     pub quantity: u32,
     pub uuid: Uuid,
-    pub price: OrderedFloat<f32>,
     pub expiration_date: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub dimensions: Option<Dimensions>,
+    #[serde(default)]
+    pub gtin: Option<Gtin>,
 }
 impl Item for ExpirableItem {
-    fn name(&self) -> &str {
-        &self.name
+    fn article(&self) -> &Article {
+        &self.article
+    }
+
+    fn article_mut(&mut self) -> &mut Article {
+        &mut self.article
     }
 
     fn quantity(&self) -> u32 {
         self.quantity
     }
 
+    fn set_quantity(&mut self, quantity: u32) {
+        self.quantity = quantity;
+    }
+
     fn uuid(&self) -> Uuid {
         self.uuid
     }
 
-    fn price(&self) -> OrderedFloat<f32> {
-        self.price
+    fn expiration_date(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        Some(self.expiration_date)
+    }
+
+    fn gtin(&self) -> Option<&Gtin> {
+        self.gtin.as_ref()
+    }
+
+    fn unit_weight_grams(&self) -> Option<u32> {
+        self.dimensions.as_ref().map(|d| d.weight_grams)
+    }
+
+    fn unit_volume_ml(&self) -> Option<u32> {
+        self.dimensions.as_ref().map(|d| d.volume_ml)
     }
 }
 
+/// Weight and volume of a single unit of an item.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub weight_grams: u32,
+    pub volume_ml: u32,
+}
+
 fn main() {
-    todo!()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => cli::run_interactive(cli::DEFAULT_STORE),
+        [flag, store] if flag == "--store" => cli::run_interactive(store),
+        _ => {
+            if let Err(err) = cli::run_command(&args) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
 mod tests {
     use super::*;
-    use ordered_float::OrderedFloat;
 
     #[test]
     fn test_max_capacity() {
@@ -333,11 +792,12 @@ mod tests {
 
         // This should work because the rack is not full
         let item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         if let Ok(_) = shop.add_item(item.clone(), &location) {
             println!("Item added successfully");
@@ -348,11 +808,12 @@ mod tests {
 
         // This should work because the rack is not full
         let item2 = ExpirableItem {
-            name: "Bread".to_string(),
+            article: Article::loose("Bread", Money::eur(150)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(1.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let location2 = Location {
             row_id: row,
@@ -368,11 +829,12 @@ mod tests {
 
         // This should fail because the rack is full
         let item3 = ExpirableItem {
-            name: "Eggs".to_string(),
+            article: Article::loose("Eggs", Money::eur(300)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(3.0),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let location3 = Location {
             row_id: row,
@@ -399,7 +861,7 @@ mod tests {
             zone_id: 3,
         };
 
-        if let Some(item) = shop.get_item(&location) {
+        if let Some(item) = shop.get_items(&location).first() {
             println!("Item found: {:#?}", item);
             assert!(false);
         } else {
@@ -414,11 +876,12 @@ mod tests {
         shop.initialize();
 
         let item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
 
         let location = Location {
@@ -434,7 +897,7 @@ mod tests {
             println!("Failed to add item");
             // assert!(false);
         }
-        let received_item = shop.get_item(&location);
+        let received_item = shop.get_item(&location, &item.uuid);
         assert_eq!(received_item, Some(&item));
         println!("Item in location: {:#?}", received_item);
 
@@ -447,11 +910,12 @@ mod tests {
         shop.initialize();
 
         let item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
 
         let location = Location {
@@ -466,11 +930,11 @@ mod tests {
             println!("Failed to add item");
         }
 
-        if let Err(err) = shop.remove_item(&location) {
+        if let Err(err) = shop.remove_item(&location, &item.uuid) {
             println!("Failed to remove item: {}", err);
             assert!(false);
         }
-        assert_eq!(shop.get_item(&location), None);
+        assert_eq!(shop.get_item(&location, &item.uuid), None);
     }
 
     #[test]
@@ -479,11 +943,12 @@ mod tests {
         shop.initialize();
 
         let item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
 
         let from_location = Location {
@@ -504,14 +969,14 @@ mod tests {
             assert!(false);
         }
 
-        if let Ok(_) = shop.move_item(from_location, &to_location) {
+        if let Ok(_) = shop.move_item(from_location, &item.uuid, &to_location) {
             println!("Item moved successfully");
         } else {
             println!("Failed to move item");
             assert!(false);
         }
 
-        assert_eq!(shop.get_item(&to_location), Some(&item));
+        assert_eq!(shop.get_item(&to_location, &item.uuid), Some(&item));
     }
 
     #[test]
@@ -521,11 +986,12 @@ mod tests {
         let mut shop = GroceryShop::<ExpirableItem>::new();
         shop.initialize();
         let mut item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let location = Location {
             row_id: 1,
@@ -538,16 +1004,16 @@ mod tests {
             println!("Failed to add item");
         }
 
-        item.price = OrderedFloat(3.5);
-        item.name = "Milk 2".to_string();
-        if let Some(item_ref) = shop.get_item_mut(&location) {
+        item.article = Article::loose("Milk 2", Money::eur(350));
+        if let Some(mut item_ref) = shop.get_item_mut(&location, &item.uuid) {
             *item_ref = item.clone();
+            item_ref.commit().unwrap();
             println!("Item edited successfully");
         } else {
             println!("Failed to edit item");
         }
-        assert_eq!(shop.get_item(&location), Some(&item));
-        println!("Item in location: {:#?}", shop.get_item(&location));
+        assert_eq!(shop.get_item(&location, &item.uuid), Some(&item));
+        println!("Item in location: {:#?}", shop.get_items(&location));
     }
 
     #[test]
@@ -556,11 +1022,12 @@ mod tests {
         let mut shop = GroceryShop::<ExpirableItem>::new();
         shop.initialize();
         let mut item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let location = Location {
             row_id: 1,
@@ -574,13 +1041,14 @@ mod tests {
         }
 
         item.quantity -= 1;
-        if let Some(item_ref) = shop.get_item_mut(&location) {
+        if let Some(mut item_ref) = shop.get_item_mut(&location, &item.uuid) {
             *item_ref = item.clone();
+            item_ref.commit().unwrap();
             println!("Item edited successfully");
         } else {
             println!("Failed to edit item");
         }
-        assert_eq!(shop.get_item(&location), Some(&item));
+        assert_eq!(shop.get_item(&location, &item.uuid), Some(&item));
     }
 
     #[test]
@@ -589,11 +1057,12 @@ mod tests {
         let mut shop = GroceryShop::<ExpirableItem>::new();
         shop.initialize();
         let mut item = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let location = Location {
             row_id: 1,
@@ -607,13 +1076,14 @@ mod tests {
         }
 
         item.quantity += 1;
-        if let Some(item_ref) = shop.get_item_mut(&location) {
+        if let Some(mut item_ref) = shop.get_item_mut(&location, &item.uuid) {
             *item_ref = item.clone();
+            item_ref.commit().unwrap();
             println!("Item edited successfully");
         } else {
             println!("Failed to edit item");
         }
-        assert_eq!(shop.get_item(&location), Some(&item));
+        assert_eq!(shop.get_item(&location, &item.uuid), Some(&item));
     }
 
     #[test]
@@ -622,18 +1092,20 @@ mod tests {
         shop.initialize();
 
         let item1 = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(250)),
             quantity: 5,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(2.5),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
         let item2 = ExpirableItem {
-            name: "Milk".to_string(),
+            article: Article::loose("Milk", Money::eur(300)),
             quantity: 10,
             uuid: Uuid::new_v4(),
-            price: OrderedFloat(3.0),
             expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
         };
 
         let location1 = Location {
@@ -663,4 +1135,400 @@ mod tests {
         assert_eq!(items[0], &item1);
         assert_eq!(items[1], &item2);
     }
+
+    #[test]
+    fn test_errors_identify_the_failure() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let item = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let uuid = item.uuid;
+        let missing = Location {
+            row_id: 7,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        let empty = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 0,
+        };
+
+        assert_eq!(
+            shop.add_item(item, &missing),
+            Err(ShopError::LocationNotFound(missing.clone()))
+        );
+        assert_eq!(
+            shop.move_item(empty.clone(), &uuid, &missing),
+            Err(ShopError::ItemNotFound {
+                location: empty,
+                uuid
+            })
+        );
+
+        let row = shop.rows.get_mut(&0).unwrap();
+        assert_eq!(
+            row.add_rack(5, 2),
+            Err(ShopError::RowFull {
+                row_id: 0,
+                rack_id: 5,
+                max_capacity: 2
+            })
+        );
+        let rack = row.racks.get_mut(&0).unwrap();
+        assert_eq!(
+            rack.add_zone(5),
+            Err(ShopError::RackFull {
+                row_id: 0,
+                rack_id: 0,
+                zone_id: 5,
+                max_capacity: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_failed_move_keeps_item_in_place() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let item = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let from_location = Location {
+            row_id: 1,
+            rack_id: 1,
+            zone_id: 1,
+        };
+        let to_location = Location {
+            row_id: 1,
+            rack_id: 1,
+            zone_id: 9,
+        };
+        shop.add_item(item.clone(), &from_location).unwrap();
+
+        assert_eq!(
+            shop.move_item(from_location.clone(), &item.uuid, &to_location),
+            Err(ShopError::LocationNotFound(to_location))
+        );
+        assert_eq!(shop.get_item(&from_location, &item.uuid), Some(&item));
+        assert_eq!(shop.get_items_by_name("Milk"), vec![&item]);
+    }
+
+    #[test]
+    fn test_add_item_refuses_full_zone() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let milk = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let bread = ExpirableItem {
+            article: Article::loose("Bread", Money::eur(150)),
+            quantity: 3,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let location = Location {
+            row_id: 0,
+            rack_id: 1,
+            zone_id: 0,
+        };
+        shop.add_item(milk.clone(), &location).unwrap();
+
+        assert_eq!(
+            shop.add_item(bread.clone(), &location),
+            Err(ShopError::ZoneFull {
+                location: location.clone(),
+                max_lots: 1,
+            })
+        );
+        // Merging a different product is refused as well
+        assert!(shop
+            .add_item_with_mode(bread, &location, AddMode::Merge)
+            .is_err());
+
+        assert_eq!(shop.get_items(&location), vec![milk.clone()]);
+        assert_eq!(shop.get_items_by_name("Milk"), vec![&milk]);
+        assert!(shop.get_items_by_name("Bread").is_empty());
+    }
+
+    #[test]
+    fn test_add_item_merges_same_lot() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let expiration_date = chrono::Utc::now();
+        let milk = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date,
+            dimensions: None,
+            gtin: None,
+        };
+        let more_milk = ExpirableItem {
+            quantity: 7,
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+        let older_milk = ExpirableItem {
+            uuid: Uuid::new_v4(),
+            expiration_date: expiration_date - chrono::Duration::days(2),
+            ..milk.clone()
+        };
+        let location = Location {
+            row_id: 2,
+            rack_id: 0,
+            zone_id: 1,
+        };
+
+        shop.add_item(milk.clone(), &location).unwrap();
+        shop.add_item_with_mode(more_milk, &location, AddMode::Merge)
+            .unwrap();
+        assert!(shop
+            .add_item_with_mode(older_milk, &location, AddMode::Merge)
+            .is_err());
+
+        let stored = shop.get_item(&location, &milk.uuid).unwrap();
+        assert_eq!(stored.quantity, 12);
+        assert_eq!(shop.get_items(&location).len(), 1);
+        assert_eq!(
+            shop.name_index.get("Milk"),
+            Some(&vec![LotLocation {
+                location,
+                uuid: milk.uuid,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_uuid_index_follows_items() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let item = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let from_location = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 1,
+        };
+        let to_location = Location {
+            row_id: 2,
+            rack_id: 1,
+            zone_id: 0,
+        };
+
+        shop.add_item(item.clone(), &from_location).unwrap();
+        assert_eq!(shop.get_location_by_uuid(&item.uuid), Some(&from_location));
+        assert_eq!(
+            shop.add_item(item.clone(), &to_location),
+            Err(ShopError::DuplicateUuid {
+                uuid: item.uuid,
+                location: from_location.clone(),
+            })
+        );
+
+        shop.move_item(from_location, &item.uuid, &to_location)
+            .unwrap();
+        assert_eq!(shop.get_location_by_uuid(&item.uuid), Some(&to_location));
+        assert_eq!(shop.get_item_by_uuid(&item.uuid), Some(&item));
+        assert_eq!(shop.verify_indexes(), Ok(()));
+
+        assert_eq!(shop.remove_item(&to_location, &item.uuid), Ok(item.clone()));
+        assert_eq!(shop.get_location_by_uuid(&item.uuid), None);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_edits_through_get_item_mut_update_indexes() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let item = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let location = Location {
+            row_id: 1,
+            rack_id: 0,
+            zone_id: 1,
+        };
+        shop.add_item(item.clone(), &location).unwrap();
+
+        let new_uuid = Uuid::new_v4();
+        if let Some(mut item_ref) = shop.get_item_mut(&location, &item.uuid) {
+            item_ref.article = Article::loose("Oat Milk", Money::eur(250));
+            item_ref.uuid = new_uuid;
+            item_ref.commit().unwrap();
+        }
+
+        assert!(shop.get_items_by_name("Milk").is_empty());
+        assert_eq!(shop.get_items_by_name("Oat Milk").len(), 1);
+        assert_eq!(shop.get_location_by_uuid(&item.uuid), None);
+        assert_eq!(shop.get_location_by_uuid(&new_uuid), Some(&location));
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_indexes_reports_stale_entries() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let location = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        shop.name_index.insert(
+            "Ghost".to_string(),
+            vec![LotLocation {
+                location: location.clone(),
+                uuid: Uuid::new_v4(),
+            }],
+        );
+        shop.uuid_index.insert(Uuid::new_v4(), location);
+
+        assert_eq!(shop.verify_indexes().unwrap_err().len(), 2);
+        shop.rebuild_indexes();
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_indexes_reports_duplicate_uuids() {
+        let mut shop = GroceryShop::<ExpirableItem>::new();
+        shop.initialize();
+
+        let item = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date: chrono::Utc::now(),
+            dimensions: None,
+            gtin: None,
+        };
+        let first = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        let second = Location {
+            row_id: 1,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        shop.add_item(item.clone(), &first).unwrap();
+        shop.get_zone_mut(&second).unwrap().lots.push(item.clone());
+        shop.rebuild_indexes();
+
+        let problems = shop.verify_indexes().unwrap_err();
+        assert!(problems.contains(&format!(
+            "Lot {} is stored both at {} and at {}",
+            item.uuid, first, second
+        )));
+    }
+
+    #[test]
+    fn test_zone_holds_several_lots() {
+        let mut shop: GroceryShop<ExpirableItem> = LayoutBuilder::new()
+            .rows(1)
+            .racks_per_row(1)
+            .zones_per_rack(2)
+            .lots_per_zone(2)
+            .build()
+            .unwrap();
+
+        let expiration_date = chrono::Utc::now();
+        let milk = ExpirableItem {
+            article: Article::loose("Milk", Money::eur(250)),
+            quantity: 5,
+            uuid: Uuid::new_v4(),
+            expiration_date,
+            dimensions: None,
+            gtin: None,
+        };
+        let older_milk = ExpirableItem {
+            quantity: 2,
+            uuid: Uuid::new_v4(),
+            expiration_date: expiration_date - chrono::Duration::days(3),
+            ..milk.clone()
+        };
+        let bread = ExpirableItem {
+            article: Article::loose("Bread", Money::eur(200)),
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+        let location = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        let other = Location {
+            zone_id: 1,
+            ..location.clone()
+        };
+
+        shop.add_item(milk.clone(), &location).unwrap();
+        shop.add_item(older_milk.clone(), &location).unwrap();
+        assert_eq!(
+            shop.add_item(bread.clone(), &location),
+            Err(ShopError::ZoneFull {
+                location: location.clone(),
+                max_lots: 2,
+            })
+        );
+        assert_eq!(
+            shop.get_items(&location),
+            &[milk.clone(), older_milk.clone()]
+        );
+        assert_eq!(shop.get_items_by_name("Milk").len(), 2);
+
+        // Moving a lot to its own zone keeps the order of the zone
+        shop.move_item(location.clone(), &milk.uuid, &location)
+            .unwrap();
+        assert_eq!(
+            shop.get_items(&location),
+            &[milk.clone(), older_milk.clone()]
+        );
+
+        // Each lot is addressed by its uuid
+        assert_eq!(shop.consume(&location, &older_milk.uuid, 2), Ok(0));
+        assert_eq!(shop.get_item(&location, &milk.uuid).unwrap().quantity, 5);
+        shop.move_item(location.clone(), &milk.uuid, &other)
+            .unwrap();
+        assert_eq!(shop.get_items(&location).len(), 1);
+        assert_eq!(shop.get_location_by_uuid(&milk.uuid), Some(&other));
+        shop.add_item(bread, &location).unwrap();
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
 }
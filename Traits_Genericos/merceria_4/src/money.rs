@@ -0,0 +1,342 @@
+// Exact money amounts.
+//
+// A Money value is a whole number of minor units (cents) plus an ISO 4217
+// currency code. Every supported currency is assumed to have two decimal
+// places. Arithmetic is checked: it fails on overflow and when mixing
+// currencies instead of silently producing a wrong total.
+//
+// Amounts are written the Portuguese way, "2,50 €", and parsed from that or
+// from "2.50", "2.5 EUR", "€2,50", "-1,05 USD" and the like. An amount without
+// a currency is taken to be in euros.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A three-letter currency code, e.g. `EUR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+    pub const USD: Currency = Currency(*b"USD");
+    pub const GBP: Currency = Currency(*b"GBP");
+
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    fn symbol(&self) -> &str {
+        match *self {
+            Currency::EUR => "€",
+            Currency::USD => "$",
+            Currency::GBP => "£",
+            _ => self.code(),
+        }
+    }
+
+    fn from_symbol(symbol: &str) -> Option<Currency> {
+        match symbol {
+            "€" => Some(Currency::EUR),
+            "$" => Some(Currency::USD),
+            "£" => Some(Currency::GBP),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, MoneyError> {
+        let bytes = code.as_bytes();
+        if bytes.len() == 3 && bytes.iter().all(u8::is_ascii_alphabetic) {
+            Ok(Currency([
+                bytes[0].to_ascii_uppercase(),
+                bytes[1].to_ascii_uppercase(),
+                bytes[2].to_ascii_uppercase(),
+            ]))
+        } else {
+            Err(MoneyError::Invalid(format!(
+                "'{}' is not a currency code",
+                code
+            )))
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = MoneyError;
+
+    fn try_from(code: String) -> Result<Self, MoneyError> {
+        code.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> String {
+        currency.code().to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    /// The two amounts are in different currencies.
+    CurrencyMismatch { left: Currency, right: Currency },
+    /// The result does not fit in 64-bit minor units.
+    Overflow,
+    /// The text is not a valid amount.
+    Invalid(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch { left, right } => {
+                write!(f, "Cannot combine {} and {} amounts", left, right)
+            }
+            MoneyError::Overflow => write!(f, "Money amount overflow"),
+            MoneyError::Invalid(message) => write!(f, "Invalid amount: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Money {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub fn eur(cents: i64) -> Self {
+        Money::new(cents, Currency::EUR)
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(0, currency)
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch {
+                left: self.currency,
+                right: other.currency,
+            })
+        }
+    }
+
+    pub fn checked_add(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let minor_units = self
+            .minor_units
+            .checked_add(other.minor_units)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_sub(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        let minor_units = self
+            .minor_units
+            .checked_sub(other.minor_units)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_mul(&self, factor: i64) -> Result<Money, MoneyError> {
+        let minor_units = self
+            .minor_units
+            .checked_mul(factor)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_mul_ratio(&self, numerator: i64, denominator: i64) -> Result<Money, MoneyError> {
+        // Scale by numerator / denominator, rounding half away from zero to the
+        // nearest minor unit. A zero denominator counts as an overflow.
+        let scaled = i128::from(self.minor_units) * i128::from(numerator);
+        let denominator = i128::from(denominator);
+        let quotient = scaled
+            .checked_div(denominator)
+            .ok_or(MoneyError::Overflow)?;
+        let remainder = scaled % denominator;
+        let rounded = if 2 * remainder.abs() >= denominator.abs() {
+            quotient + scaled.signum() * denominator.signum()
+        } else {
+            quotient
+        };
+        let minor_units = i64::try_from(rounded).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn sum<I: IntoIterator<Item = Money>>(
+        currency: Currency,
+        amounts: I,
+    ) -> Result<Money, MoneyError> {
+        // Add up the amounts, all of which must be in `currency`
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), |total, amount| {
+                total.checked_add(amount)
+            })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 250 EUR -> "2,50 €"
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let minor_units = self.minor_units.unsigned_abs();
+        write!(
+            f,
+            "{}{},{:02} {}",
+            sign,
+            minor_units / 100,
+            minor_units % 100,
+            self.currency.symbol()
+        )
+    }
+}
+
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(text: &str) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::Invalid(format!("'{}'", text));
+
+        // Split the currency (symbol or code, before or after) from the number
+        let text = text.trim();
+        let is_number_char = |c: char| c.is_ascii_digit() || c == ',' || c == '.' || c == '-';
+        let start = text.find(is_number_char).ok_or_else(invalid)?;
+        let end = text.rfind(is_number_char).ok_or_else(invalid)? + 1;
+        let number = &text[start..end];
+        let marker = format!("{}{}", &text[..start], &text[end..]);
+        let marker = marker.trim();
+        let currency = if marker.is_empty() {
+            Currency::EUR
+        } else {
+            match Currency::from_symbol(marker) {
+                Some(currency) => currency,
+                None => marker.parse().map_err(|_| invalid())?,
+            }
+        };
+
+        let (negative, number) = match number.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, number),
+        };
+        let (units, fraction) = match number.find([',', '.']) {
+            Some(index) => (&number[..index], &number[index + 1..]),
+            None => (number, ""),
+        };
+        let all_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if units.is_empty() || !all_digits(units) || !all_digits(fraction) || fraction.len() > 2 {
+            return Err(invalid());
+        }
+
+        let units: i64 = units.parse().map_err(|_| MoneyError::Overflow)?;
+        let cents: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+        let minor_units = units
+            .checked_mul(100)
+            .and_then(|minor| minor.checked_add(cents))
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(
+            if negative { -minor_units } else { minor_units },
+            currency,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!("2,50 €".parse(), Ok(Money::eur(250)));
+        assert_eq!("2.5".parse(), Ok(Money::eur(250)));
+        assert_eq!("€0,07".parse(), Ok(Money::eur(7)));
+        assert_eq!("12 usd".parse(), Ok(Money::new(1200, Currency::USD)));
+        assert_eq!("-1,05 GBP".parse(), Ok(Money::new(-105, Currency::GBP)));
+
+        for text in ["", "€", "2,505", "2,5,0", "1.2 euros", "abc", "2 -"] {
+            assert!(text.parse::<Money>().is_err(), "{}", text);
+        }
+
+        assert_eq!(Money::eur(250).to_string(), "2,50 €");
+        assert_eq!(Money::new(-5, Currency::USD).to_string(), "-0,05 $");
+        assert_eq!(
+            Money::new(100, "CHF".parse().unwrap()).to_string(),
+            "1,00 CHF"
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let price = Money::eur(115);
+        assert_eq!(price.checked_mul(3), Ok(Money::eur(345)));
+        assert_eq!(price.checked_add(Money::eur(5)), Ok(Money::eur(120)));
+        assert_eq!(price.checked_sub(Money::eur(200)), Ok(Money::eur(-85)));
+        assert_eq!(
+            price.checked_add(Money::new(1, Currency::USD)),
+            Err(MoneyError::CurrencyMismatch {
+                left: Currency::EUR,
+                right: Currency::USD,
+            })
+        );
+        assert_eq!(
+            Money::eur(i64::MAX).checked_add(Money::eur(1)),
+            Err(MoneyError::Overflow)
+        );
+        // 10% of 1,15 € is 0,115 €, 6% VAT included in 1,06 € is 0,06 €
+        assert_eq!(price.checked_mul_ratio(10, 100), Ok(Money::eur(12)));
+        assert_eq!(
+            Money::eur(-115).checked_mul_ratio(10, 100),
+            Ok(Money::eur(-12))
+        );
+        assert_eq!(Money::eur(106).checked_mul_ratio(6, 106), Ok(Money::eur(6)));
+        assert_eq!(Money::eur(1).checked_mul_ratio(1, 3), Ok(Money::eur(0)));
+        assert_eq!(price.checked_mul_ratio(1, 0), Err(MoneyError::Overflow));
+        assert_eq!(
+            Money::sum(Currency::EUR, [Money::eur(10), Money::eur(20)]),
+            Ok(Money::eur(30))
+        );
+    }
+
+    #[test]
+    fn test_serde_format() {
+        let json = serde_json::to_string(&Money::eur(250)).unwrap();
+        assert_eq!(json, r#"{"minor_units":250,"currency":"EUR"}"#);
+        assert_eq!(
+            serde_json::from_str::<Money>(&json).unwrap(),
+            Money::eur(250)
+        );
+        assert!(serde_json::from_str::<Money>(r#"{"minor_units":1,"currency":"E"}"#).is_err());
+    }
+}
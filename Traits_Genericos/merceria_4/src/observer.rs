@@ -0,0 +1,300 @@
+// Observers notified of every change to the lots of a shop.
+//
+// A ShopObserver registered with `add_observer` is called after each lot is
+// added, removed, moved or edited. Edits cover changes committed through
+// `get_item_mut`, stock adjustments, price changes and merges into an existing
+// lot.
+//
+// Observers are shared (`Arc`), `Send + Sync` and take `&self`, so one that
+// keeps state (an audit log, a POS connection) uses a Mutex or atomics, and a
+// shop with observers can still move to another thread. Inside a transaction
+// the events are held back and only delivered once it commits.
+
+use crate::{GroceryShop, Item, Location};
+use std::fmt;
+use std::sync::Arc;
+
+pub trait ShopObserver<T: Item>: Send + Sync {
+    fn on_added(&self, _item: &T, _location: &Location) {}
+
+    fn on_removed(&self, _item: &T, _location: &Location) {}
+
+    fn on_moved(&self, _item: &T, _from: &Location, _to: &Location) {}
+
+    fn on_edited(&self, _before: &T, _after: &T, _location: &Location) {}
+}
+
+/// A change to a lot, as delivered to the observers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShopEvent<T: Item> {
+    Added {
+        item: T,
+        location: Location,
+    },
+    Removed {
+        item: T,
+        location: Location,
+    },
+    Moved {
+        item: T,
+        from: Location,
+        to: Location,
+    },
+    Edited {
+        before: T,
+        after: T,
+        location: Location,
+    },
+}
+
+impl<T: Item> ShopEvent<T> {
+    fn deliver(&self, observer: &dyn ShopObserver<T>) {
+        match self {
+            ShopEvent::Added { item, location } => observer.on_added(item, location),
+            ShopEvent::Removed { item, location } => observer.on_removed(item, location),
+            ShopEvent::Moved { item, from, to } => observer.on_moved(item, from, to),
+            ShopEvent::Edited {
+                before,
+                after,
+                location,
+            } => observer.on_edited(before, after, location),
+        }
+    }
+}
+
+/// The observers of a shop. Clones share the observers.
+#[derive(Clone)]
+pub struct Observers<T: Item> {
+    observers: Vec<Arc<dyn ShopObserver<T>>>,
+    deferred: Option<Vec<ShopEvent<T>>>,
+}
+
+impl<T: Item> Observers<T> {
+    pub(crate) fn notify(&mut self, event: ShopEvent<T>) {
+        // Deliver the event now, or keep it for later while deferring
+        match &mut self.deferred {
+            Some(deferred) => deferred.push(event),
+            None => {
+                for observer in &self.observers {
+                    event.deliver(observer.as_ref());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn defer(&mut self) {
+        self.deferred = Some(Vec::new());
+    }
+
+    pub(crate) fn take_deferred(&mut self) -> Vec<ShopEvent<T>> {
+        self.deferred.take().unwrap_or_default()
+    }
+}
+
+impl<T: Item> Default for Observers<T> {
+    fn default() -> Self {
+        Observers {
+            observers: Vec::new(),
+            deferred: None,
+        }
+    }
+}
+
+impl<T: Item> fmt::Debug for Observers<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("observers", &self.observers.len())
+            .field("deferred", &self.deferred)
+            .finish()
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn add_observer(&mut self, observer: Arc<dyn ShopObserver<T>>) {
+        self.observers.observers.push(observer);
+    }
+
+    pub fn remove_observer(&mut self, observer: &Arc<dyn ShopObserver<T>>) {
+        // Forget an observer added earlier, compared by identity
+        self.observers
+            .observers
+            .retain(|other| !Arc::ptr_eq(other, observer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::{Article, ExpirableItem, Money};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    /// Keeps every event it is told about, as text.
+    #[derive(Default)]
+    struct AuditLog {
+        entries: Mutex<Vec<String>>,
+    }
+
+    fn name(item: &ExpirableItem) -> &str {
+        // An observer only sees the lot, so a product goes by its SKU
+        match &item.article {
+            Article::Loose { name, .. } => name,
+            Article::Product { sku } => sku.as_str(),
+        }
+    }
+
+    fn describe(item: &ExpirableItem) -> String {
+        // "Leite x3 @ 1,00 €" for a loose lot
+        match &item.article {
+            Article::Loose { name, price } => format!("{} x{} @ {}", name, item.quantity, price),
+            Article::Product { sku } => format!("{} x{}", sku, item.quantity),
+        }
+    }
+
+    impl ShopObserver<ExpirableItem> for AuditLog {
+        fn on_added(&self, item: &ExpirableItem, location: &Location) {
+            self.entries.lock().unwrap().push(format!(
+                "added {} x{} at {}",
+                name(item),
+                item.quantity,
+                location
+            ));
+        }
+
+        fn on_removed(&self, item: &ExpirableItem, location: &Location) {
+            self.entries
+                .lock()
+                .unwrap()
+                .push(format!("removed {} at {}", name(item), location));
+        }
+
+        fn on_moved(&self, item: &ExpirableItem, from: &Location, to: &Location) {
+            self.entries.lock().unwrap().push(format!(
+                "moved {} from {} to {}",
+                name(item),
+                from,
+                to
+            ));
+        }
+
+        fn on_edited(&self, before: &ExpirableItem, after: &ExpirableItem, _: &Location) {
+            self.entries.lock().unwrap().push(format!(
+                "edited {} -> {}",
+                describe(before),
+                describe(after)
+            ));
+        }
+    }
+
+    fn shop() -> (GroceryShop<ExpirableItem>, Arc<AuditLog>) {
+        let mut shop = shop_with(vec![]);
+        let log = Arc::new(AuditLog::default());
+        shop.add_observer(log.clone());
+        (shop, log)
+    }
+
+    #[test]
+    fn test_observers_see_every_change() {
+        let (mut shop, log) = shop();
+        let milk = item("Leite", 3);
+
+        shop.add_item(milk.clone(), &location(0, 0, 0)).unwrap();
+        shop.move_item(location(0, 0, 0), &milk.uuid, &location(1, 0, 0))
+            .unwrap();
+        if let Some(mut lot) = shop.get_item_mut(&location(1, 0, 0), &milk.uuid) {
+            lot.article = Article::loose("Leite Gordo", Money::eur(100));
+            lot.commit().unwrap();
+        }
+        // A guard that changes nothing, or is dropped without a commit,
+        // reports nothing
+        shop.get_item_mut(&location(1, 0, 0), &milk.uuid)
+            .unwrap()
+            .commit()
+            .unwrap();
+        if let Some(mut lot) = shop.get_item_mut(&location(1, 0, 0), &milk.uuid) {
+            lot.quantity = 0;
+        }
+        // So does moving a lot to where it already is
+        shop.move_item(location(1, 0, 0), &milk.uuid, &location(1, 0, 0))
+            .unwrap();
+        shop.consume(&location(1, 0, 0), &milk.uuid, 1).unwrap();
+        shop.set_price(&milk.uuid, Money::eur(90), "promo").unwrap();
+        shop.remove_item(&location(1, 0, 0), &milk.uuid).unwrap();
+        // Failed operations report nothing
+        assert!(shop.remove_item(&location(1, 0, 0), &milk.uuid).is_err());
+
+        assert_eq!(
+            *log.entries.lock().unwrap(),
+            vec![
+                "added Leite x3 at row 0 / rack 0 / zone 0",
+                "moved Leite from row 0 / rack 0 / zone 0 to row 1 / rack 0 / zone 0",
+                "edited Leite x3 @ 1,00 € -> Leite Gordo x3 @ 1,00 €",
+                "edited Leite Gordo x3 @ 1,00 € -> Leite Gordo x2 @ 1,00 €",
+                "edited Leite Gordo x2 @ 1,00 € -> Leite Gordo x2 @ 0,90 €",
+                "removed Leite Gordo at row 1 / rack 0 / zone 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merges_are_edits() {
+        let (mut shop, log) = shop();
+        let milk = item("Leite", 3);
+        let more = ExpirableItem {
+            quantity: 2,
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+
+        shop.add_item(milk, &location(0, 0, 0)).unwrap();
+        shop.add_item_with_mode(more, &location(0, 0, 0), crate::AddMode::Merge)
+            .unwrap();
+
+        assert_eq!(
+            log.entries.lock().unwrap()[1],
+            "edited Leite x3 @ 1,00 € -> Leite x5 @ 1,00 €"
+        );
+    }
+
+    #[test]
+    fn test_events_wait_for_the_transaction_to_commit() {
+        let (mut shop, log) = shop();
+
+        let result: Result<(), crate::ShopError> = shop.transaction(|tx| {
+            tx.add_item(item("Leite", 1), &location(0, 0, 0))?;
+            tx.add_item(item("Pão", 1), &location(9, 0, 0))?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(log.entries.lock().unwrap().is_empty());
+
+        shop.transaction(|tx| {
+            tx.add_item(item("Leite", 1), &location(0, 0, 0))?;
+            assert!(log.entries.lock().unwrap().is_empty());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(log.entries.lock().unwrap().len(), 1);
+
+        let observer: Arc<dyn ShopObserver<ExpirableItem>> = log.clone();
+        shop.remove_observer(&observer);
+        shop.add_item(item("Pão", 1), &location(0, 0, 1)).unwrap();
+        assert_eq!(log.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_a_shop_with_observers_moves_across_threads() {
+        let (mut shop, log) = shop();
+        shop.subscribe_low_stock(|_| {});
+
+        let shop = std::thread::spawn(move || {
+            shop.add_item(item("Leite", 1), &location(0, 0, 0)).unwrap();
+            shop
+        })
+        .join()
+        .unwrap();
+        assert_eq!(shop.stock_of("Leite"), 1);
+        assert_eq!(log.entries.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,774 @@
+// Saving and loading a GroceryShop to/from a versioned JSON snapshot.
+//
+// The snapshot stores the full layout (rows, racks, zones and their max
+// capacities) together with every item, the price book, the reorder policies
+// (by SKU, or by name for loose articles),
+// the sales made and the ids of the voided ones, the suppliers, the purchase orders, the open count
+// sessions, the count history and the product catalogue. The name and uuid
+// indexes are never written to disk: they are rebuilt from the zones when a
+// snapshot is loaded.
+//
+// A snapshot is first written to a temporary file next to the store and then
+// renamed over it, so a failed save never leaves a half-written store behind.
+
+use crate::{
+    Catalogue, CountHistory, CountSession, GroceryShop, Item, Location, PriceBook, ProductKey,
+    PurchaseOrder, ReorderPolicy, Row, Sale, ShopError, Supplier,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Version written by `save_to`. Bump it whenever the snapshot layout changes
+/// and teach `upgrade_snapshot` how to migrate the previous version.
+pub const SNAPSHOT_VERSION: u32 = 12;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a, T: Item> {
+    version: u32,
+    rows: &'a BTreeMap<u32, Row<T>>,
+    prices: &'a PriceBook,
+    reorder_policies: &'a BTreeMap<ProductKey, ReorderPolicy>,
+    sales: &'a BTreeMap<Uuid, Sale>,
+    voided_sales: &'a BTreeSet<Uuid>,
+    suppliers: &'a BTreeMap<Uuid, Supplier>,
+    purchase_orders: &'a BTreeMap<Uuid, PurchaseOrder>,
+    count_sessions: &'a BTreeMap<Uuid, CountSession>,
+    count_history: &'a CountHistory,
+    catalogue: &'a Catalogue,
+}
+
+#[derive(Deserialize)]
+struct Snapshot<T: Item> {
+    version: u32,
+    rows: BTreeMap<u32, Row<T>>,
+    #[serde(default)]
+    prices: PriceBook,
+    #[serde(default)]
+    reorder_policies: BTreeMap<ProductKey, ReorderPolicy>,
+    #[serde(default)]
+    sales: BTreeMap<Uuid, Sale>,
+    #[serde(default)]
+    voided_sales: BTreeSet<Uuid>,
+    #[serde(default)]
+    suppliers: BTreeMap<Uuid, Supplier>,
+    #[serde(default)]
+    purchase_orders: BTreeMap<Uuid, PurchaseOrder>,
+    #[serde(default)]
+    count_sessions: BTreeMap<Uuid, CountSession>,
+    #[serde(default)]
+    count_history: CountHistory,
+    #[serde(default)]
+    catalogue: Catalogue,
+}
+
+impl<T: Item + Serialize + DeserializeOwned> GroceryShop<T> {
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<(), ShopError> {
+        // Serialize the whole layout and replace the file at the given path
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            rows: &self.rows,
+            prices: &self.prices,
+            reorder_policies: &self.reorder_policies,
+            sales: &self.sales,
+            voided_sales: &self.voided_sales,
+            suppliers: &self.suppliers,
+            purchase_orders: &self.purchase_orders,
+            count_sessions: &self.count_sessions,
+            count_history: &self.count_history,
+            catalogue: &self.catalogue,
+        };
+        let json = serde_json::to_string_pretty(&snapshot)
+            .map_err(|err| ShopError::InvalidSnapshot(err.to_string()))?;
+        let path = path.as_ref();
+        let temp_path = temp_path_for(path);
+        fs::write(&temp_path, json)
+            .and_then(|()| fs::rename(&temp_path, path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&temp_path);
+                ShopError::Io(format!("Could not write {}: {}", path.display(), err))
+            })
+    }
+
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, ShopError> {
+        // Read a snapshot, migrate it to the current version and rebuild the indexes
+        let json = fs::read_to_string(path.as_ref()).map_err(|err| {
+            ShopError::Io(format!(
+                "Could not read {}: {}",
+                path.as_ref().display(),
+                err
+            ))
+        })?;
+        let value: Value = serde_json::from_str(&json)
+            .map_err(|err| ShopError::InvalidSnapshot(err.to_string()))?;
+        let value = upgrade_snapshot(value)?;
+        let snapshot: Snapshot<T> = serde_json::from_value(value)
+            .map_err(|err| ShopError::InvalidSnapshot(err.to_string()))?;
+        debug_assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+
+        let mut stored: HashMap<Uuid, Location> = HashMap::new();
+        for (row_id, row) in &snapshot.rows {
+            if row.racks.len() as u32 > row.max_capacity {
+                return Err(ShopError::InvalidSnapshot(format!(
+                    "Row {} has {} racks but a max capacity of {}",
+                    row_id,
+                    row.racks.len(),
+                    row.max_capacity
+                )));
+            }
+            for (rack_id, rack) in &row.racks {
+                if rack.zones.len() as u32 > rack.max_capacity {
+                    return Err(ShopError::InvalidSnapshot(format!(
+                        "Rack {} in row {} has {} zones but a max capacity of {}",
+                        rack_id,
+                        row_id,
+                        rack.zones.len(),
+                        rack.max_capacity
+                    )));
+                }
+                for (zone_id, zone) in &rack.zones {
+                    if zone.lots.len() as u32 > zone.max_lots {
+                        return Err(ShopError::InvalidSnapshot(format!(
+                            "Zone {} in rack {} of row {} has {} lots but holds at most {}",
+                            zone_id,
+                            rack_id,
+                            row_id,
+                            zone.lots.len(),
+                            zone.max_lots
+                        )));
+                    }
+                    for lot in &zone.lots {
+                        let here = Location {
+                            row_id: *row_id,
+                            rack_id: *rack_id,
+                            zone_id: *zone_id,
+                        };
+                        if let Some(first) = stored.insert(lot.uuid(), here.clone()) {
+                            return Err(ShopError::InvalidSnapshot(format!(
+                                "Lot {} is stored both at {} and at {}",
+                                lot.uuid(),
+                                first,
+                                here
+                            )));
+                        }
+                        if let Some(sku) = lot.sku() {
+                            if snapshot.catalogue.get(sku).is_none() {
+                                return Err(ShopError::InvalidSnapshot(format!(
+                                    "Lot {} at {} refers to unknown SKU {}",
+                                    lot.uuid(),
+                                    here,
+                                    sku
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut shop = GroceryShop::new();
+        shop.rows = snapshot.rows;
+        shop.prices = snapshot.prices;
+        shop.reorder_policies = snapshot.reorder_policies;
+        shop.sales = snapshot.sales;
+        shop.voided_sales = snapshot.voided_sales;
+        shop.suppliers = snapshot.suppliers;
+        shop.purchase_orders = snapshot.purchase_orders;
+        shop.count_sessions = snapshot.count_sessions;
+        shop.count_history = snapshot.count_history;
+        shop.catalogue = snapshot.catalogue;
+        shop.rebuild_indexes();
+        Ok(shop)
+    }
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    // "store.json" is written as "store.json.tmp" in the same directory, so
+    // the rename that replaces the store stays on one file system
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+fn upgrade_snapshot(mut value: Value) -> Result<Value, ShopError> {
+    // Bring a snapshot of any supported version up to SNAPSHOT_VERSION, one
+    // version at a time
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| ShopError::InvalidSnapshot("missing version field".to_string()))?;
+    if version == 0 || version > u64::from(SNAPSHOT_VERSION) {
+        return Err(ShopError::UnsupportedSnapshotVersion {
+            found: version,
+            supported: SNAPSHOT_VERSION,
+        });
+    }
+
+    if version < 2 {
+        for_each_zone(&mut value, upgrade_v1_zone)?;
+    }
+    if version < 3 {
+        for_each_zone(&mut value, upgrade_v2_prices)?;
+    }
+    // Versions 4 to 9 added the price book, the reorder policies, the voided
+    // sales, the purchasing records, the cycle counts and the catalogue, which
+    // older snapshots load as empty
+    if version < 10 {
+        for_each_zone(&mut value, upgrade_v9_lots)?;
+    }
+    // Version 11 added the sales made, which older snapshots load as empty
+    if version < 12 {
+        upgrade_v11_products(&mut value)?;
+    }
+    value["version"] = Value::from(SNAPSHOT_VERSION);
+    Ok(value)
+}
+
+fn invalid(what: &str) -> ShopError {
+    ShopError::InvalidSnapshot(format!("{} is not an object", what))
+}
+
+fn for_each_zone<F>(value: &mut Value, mut upgrade: F) -> Result<(), ShopError>
+where
+    F: FnMut(&mut Map<String, Value>) -> Result<(), ShopError>,
+{
+    // Run `upgrade` on every zone object of a raw snapshot
+    let rows = value
+        .get_mut("rows")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| invalid("rows"))?;
+    for row in rows.values_mut() {
+        let racks = row
+            .get_mut("racks")
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| invalid("racks"))?;
+        for rack in racks.values_mut() {
+            let zones = rack
+                .get_mut("zones")
+                .and_then(Value::as_object_mut)
+                .ok_or_else(|| invalid("zones"))?;
+            for zone in zones.values_mut() {
+                upgrade(zone.as_object_mut().ok_or_else(|| invalid("zone"))?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_v1_zone(zone: &mut Map<String, Value>) -> Result<(), ShopError> {
+    // Version 1 zones held a single optional `item`, version 2 zones hold `lots`
+    let lots = match zone.remove("item") {
+        Some(Value::Null) | None => vec![],
+        Some(item) => vec![item],
+    };
+    zone.insert("lots".to_string(), Value::from(lots));
+    zone.insert("max_lots".to_string(), Value::from(1));
+    Ok(())
+}
+
+fn upgrade_v2_prices(zone: &mut Map<String, Value>) -> Result<(), ShopError> {
+    // Version 2 prices were f32 euros, version 3 prices are Money in cents
+    let lots = zone
+        .get_mut("lots")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| ShopError::InvalidSnapshot("lots is not an array".to_string()))?;
+    for lot in lots {
+        let price = lot
+            .get_mut("price")
+            .ok_or_else(|| ShopError::InvalidSnapshot("lot without a price".to_string()))?;
+        if let Some(euros) = price.as_f64() {
+            *price = json!({
+                "minor_units": (euros * 100.0).round() as i64,
+                "currency": "EUR",
+            });
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_v9_lots(zone: &mut Map<String, Value>) -> Result<(), ShopError> {
+    // Version 9 lots of a catalogue product also copied its name and price,
+    // version 10 lots keep only the sku
+    let lots = zone
+        .get_mut("lots")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| ShopError::InvalidSnapshot("lots is not an array".to_string()))?;
+    for lot in lots {
+        let lot = lot.as_object_mut().ok_or_else(|| invalid("lot"))?;
+        match lot.get("sku") {
+            Some(Value::Null) | None => {
+                lot.remove("sku");
+            }
+            Some(_) => {
+                lot.remove("name");
+                lot.remove("price");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_v11_products(value: &mut Value) -> Result<(), ShopError> {
+    // Version 11 reorder policies and order lines named their product,
+    // version 12 ones key it by SKU, or by name for a loose article
+    let mut skus_by_name: HashMap<String, Vec<String>> = HashMap::new();
+    if let Some(products) = value["catalogue"]["products"].as_object() {
+        for (sku, product) in products {
+            let name = product["name"]
+                .as_str()
+                .ok_or_else(|| ShopError::InvalidSnapshot("product without a name".to_string()))?;
+            skus_by_name
+                .entry(name.to_string())
+                .or_default()
+                .push(sku.clone());
+        }
+    }
+    let key_for = |name: &str| match skus_by_name.get(name) {
+        Some(skus) if skus.len() == 1 => ProductKey::sku(&skus[0]).to_string(),
+        _ => ProductKey::loose(name).to_string(),
+    };
+
+    if let Some(policies) = value.get_mut("reorder_policies") {
+        let old = policies
+            .as_object()
+            .ok_or_else(|| invalid("reorder_policies"))?;
+        let upgraded: Map<String, Value> = old
+            .iter()
+            .map(|(name, policy)| (key_for(name), policy.clone()))
+            .collect();
+        *policies = Value::Object(upgraded);
+    }
+    if let Some(orders) = value.get_mut("purchase_orders") {
+        let orders = orders
+            .as_object_mut()
+            .ok_or_else(|| invalid("purchase_orders"))?;
+        for order in orders.values_mut() {
+            let lines = order
+                .get_mut("lines")
+                .and_then(Value::as_array_mut)
+                .ok_or_else(|| ShopError::InvalidSnapshot("lines is not an array".to_string()))?;
+            for line in lines {
+                let line = line.as_object_mut().ok_or_else(|| invalid("order line"))?;
+                let name = line
+                    .remove("name")
+                    .and_then(|name| name.as_str().map(str::to_string))
+                    .ok_or_else(|| {
+                        ShopError::InvalidSnapshot("order line without a name".to_string())
+                    })?;
+                line.insert("product".to_string(), Value::from(key_for(&name)));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, location, priced_item, shop_with};
+    use crate::{ExpirableItem, LotLocation, Money};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("merceria_4_{}_{}.json", name, Uuid::new_v4()))
+    }
+
+    fn reload(shop: &GroceryShop<ExpirableItem>) -> GroceryShop<ExpirableItem> {
+        // Save the shop to a fresh file and load it back
+        let path = temp_path("roundtrip");
+        shop.save_to(&path).unwrap();
+        let loaded = GroceryShop::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn shop_with_milk() -> (GroceryShop<ExpirableItem>, ExpirableItem) {
+        let milk = priced_item("Milk", 5, Money::eur(250));
+        (shop_with(vec![(milk.clone(), location(2, 1, 0))]), milk)
+    }
+
+    #[test]
+    fn test_roundtrip_layout_and_lots() {
+        // Versions 1 to 3: the layout, the lots of every zone and their prices
+        let (shop, milk) = shop_with_milk();
+        let loaded = reload(&shop);
+
+        assert_eq!(loaded.get_items(&location(2, 1, 0)), vec![milk.clone()]);
+        assert_eq!(
+            loaded.name_index.get("Milk"),
+            Some(&vec![LotLocation {
+                location: location(2, 1, 0),
+                uuid: milk.uuid,
+            }])
+        );
+        assert_eq!(
+            loaded.get_location_by_uuid(&milk.uuid),
+            Some(&location(2, 1, 0))
+        );
+        assert_eq!(loaded.rows.len(), 3);
+        assert_eq!(loaded.rows[&0].max_capacity, 2);
+        assert_eq!(loaded.rows[&0].racks[&1].max_capacity, 2);
+        assert_eq!(loaded.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_roundtrip_price_book() {
+        // Version 4: price history and promotions
+        let (mut shop, milk) = shop_with_milk();
+        let now = chrono::Utc::now();
+        shop.add_promotion(
+            &milk.uuid,
+            Money::eur(199),
+            now,
+            now + chrono::Duration::days(7),
+            "week deal",
+        )
+        .unwrap();
+        let loaded = reload(&shop);
+
+        assert_eq!(
+            loaded.price_history(&milk.uuid),
+            shop.price_history(&milk.uuid)
+        );
+        assert_eq!(loaded.current_price(&milk.uuid), Some(Money::eur(199)));
+    }
+
+    #[test]
+    fn test_roundtrip_reorder_policies() {
+        // Versions 5 and 12: reorder policies, keyed by product
+        let (mut shop, _) = shop_with_milk();
+        shop.set_reorder_policy(ProductKey::loose("Milk"), 5, 20)
+            .unwrap();
+        let loaded = reload(&shop);
+
+        assert_eq!(loaded.reorder_policies, shop.reorder_policies);
+        assert_eq!(loaded.low_stock().len(), 1);
+    }
+
+    #[test]
+    fn test_roundtrip_sales() {
+        // Versions 6 and 11: the sales made and the ids of the voided ones
+        let (mut shop, _) = shop_with_milk();
+        let voided = crate::Checkout::new()
+            .add("Milk", 1)
+            .commit(&mut shop)
+            .unwrap();
+        let kept = crate::Checkout::new()
+            .add("Milk", 2)
+            .commit(&mut shop)
+            .unwrap();
+        shop.void_sale(&voided.id).unwrap();
+        let mut loaded = reload(&shop);
+
+        assert_eq!(loaded.sale(&kept.id), Some(&kept));
+        assert_eq!(
+            loaded.void_sale(&voided.id),
+            Err(ShopError::SaleAlreadyVoided(voided.id))
+        );
+        loaded.void_sale(&kept.id).unwrap();
+        assert_eq!(loaded.stock_of("Milk"), 5);
+    }
+
+    #[test]
+    fn test_roundtrip_purchasing() {
+        // Versions 7 and 12: suppliers and purchase orders, keyed by product
+        let (mut shop, _) = shop_with_milk();
+        let supplier = shop.add_supplier("Lactogal");
+        let order = shop
+            .place_order(
+                crate::PurchaseOrder::new(supplier).line(crate::OrderLine::new(
+                    ProductKey::loose("Milk"),
+                    12,
+                    Money::eur(60),
+                )),
+            )
+            .unwrap();
+        let loaded = reload(&shop);
+
+        assert_eq!(loaded.supplier(&supplier), shop.supplier(&supplier));
+        assert_eq!(loaded.purchase_order(&order), shop.purchase_order(&order));
+        assert_eq!(loaded.on_order(&ProductKey::loose("Milk")), 12);
+    }
+
+    #[test]
+    fn test_roundtrip_counts() {
+        // Version 8: open count sessions and the count history
+        let (mut shop, _) = shop_with_milk();
+        let count = shop.start_count(crate::CountScope::Row(2)).unwrap();
+        shop.record_count(&count, &location(2, 1, 0), "Milk", 4)
+            .unwrap();
+        shop.approve_count(&count, "DAMAGED").unwrap();
+        let open_count = shop.start_count(crate::CountScope::Row(2)).unwrap();
+        let loaded = reload(&shop);
+
+        assert_eq!(
+            loaded.count_history(&location(2, 1, 0)),
+            shop.count_history(&location(2, 1, 0))
+        );
+        assert_eq!(loaded.count_history(&location(2, 1, 0))[0].counted, 4);
+        assert_eq!(
+            loaded.count_session(&open_count),
+            shop.count_session(&open_count)
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_catalogue() {
+        // Versions 9 and 10: the catalogue and lots that only record a SKU
+        let (mut shop, _) = shop_with_milk();
+        shop.add_product(crate::Product::new("LEI-001", "Leite", Money::eur(95)))
+            .unwrap();
+        let sku = crate::Sku::new("LEI-001");
+        let product_lot = test_support::product_lot("LEI-001", 5);
+        shop.add_item(product_lot.clone(), &location(2, 1, 1))
+            .unwrap();
+        let loaded = reload(&shop);
+
+        assert_eq!(loaded.product(&sku), shop.product(&sku));
+        assert_eq!(loaded.stock_of("Leite"), 5);
+        assert_eq!(loaded.regular_price_of(&product_lot), Ok(Money::eur(95)));
+    }
+
+    #[test]
+    fn test_save_replaces_the_store() {
+        let (mut shop, milk) = shop_with_milk();
+        let path = temp_path("replace");
+        shop.save_to(&path).unwrap();
+        shop.restock(&location(2, 1, 0), &milk.uuid, 3).unwrap();
+        shop.save_to(&path).unwrap();
+        let loaded = GroceryShop::<ExpirableItem>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.stock_of("Milk"), 8);
+        assert!(!temp_path_for(&path).exists());
+
+        // A save that cannot be written leaves nothing behind
+        let missing = std::env::temp_dir()
+            .join(Uuid::new_v4().to_string())
+            .join("store.json");
+        assert!(matches!(shop.save_to(&missing), Err(ShopError::Io(_))));
+        assert!(!temp_path_for(&missing).exists());
+    }
+
+    #[test]
+    fn test_load_rejects_duplicate_lot_uuids() {
+        let path = temp_path("duplicate");
+        let uuid = Uuid::new_v4();
+        let lot = format!(
+            r#"{{"name": "Milk", "quantity": 1, "uuid": "{}",
+                "price": {{"minor_units": 100, "currency": "EUR"}},
+                "expiration_date": "2030-01-01T00:00:00Z"}}"#,
+            uuid
+        );
+        let snapshot = format!(
+            r#"{{"version": {}, "rows": {{"0": {{"max_capacity": 1, "racks": {{"0": {{
+                "max_capacity": 2,
+                "zones": {{
+                    "0": {{"max_lots": 1, "lots": [{}]}},
+                    "1": {{"max_lots": 1, "lots": [{}]}}
+                }}
+            }}}}}}}}}}"#,
+            SNAPSHOT_VERSION, lot, lot
+        );
+        fs::write(&path, snapshot).unwrap();
+        let result = GroceryShop::<ExpirableItem>::load_from(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap_err(),
+            ShopError::InvalidSnapshot(format!(
+                "Lot {} is stored both at {} and at {}",
+                uuid,
+                location(0, 0, 0),
+                location(0, 0, 1)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_lots_of_unknown_skus() {
+        let path = temp_path("unknown_sku");
+        let uuid = Uuid::new_v4();
+        let snapshot = format!(
+            r#"{{"version": {}, "rows": {{"0": {{"max_capacity": 1, "racks": {{"0": {{
+                "max_capacity": 1,
+                "zones": {{"0": {{"max_lots": 1, "lots": [
+                    {{"sku": "LEI-001", "quantity": 1, "uuid": "{}",
+                        "expiration_date": "2030-01-01T00:00:00Z"}}
+                ]}}}}
+            }}}}}}}}}}"#,
+            SNAPSHOT_VERSION, uuid
+        );
+        fs::write(&path, snapshot).unwrap();
+        let result = GroceryShop::<ExpirableItem>::load_from(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap_err(),
+            ShopError::InvalidSnapshot(format!(
+                "Lot {} at {} refers to unknown SKU LEI-001",
+                uuid,
+                location(0, 0, 0)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_load_migrates_version_1() {
+        let path = temp_path("v1");
+        let uuid = Uuid::new_v4();
+        let v1 = format!(
+            r#"{{"version": 1, "rows": {{"0": {{"max_capacity": 1, "racks": {{"0": {{
+                "max_capacity": 2,
+                "zones": {{
+                    "0": {{"item": {{"name": "Milk", "quantity": 3, "uuid": "{}",
+                        "price": 1.2, "expiration_date": "2030-01-01T00:00:00Z"}}}},
+                    "1": {{"item": null}}
+                }}
+            }}}}}}}}}}"#,
+            uuid
+        );
+        fs::write(&path, v1).unwrap();
+        let loaded = GroceryShop::<ExpirableItem>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let location = Location {
+            row_id: 0,
+            rack_id: 0,
+            zone_id: 0,
+        };
+        let zone = loaded.get_zone(&location).unwrap();
+        assert_eq!(zone.max_lots, 1);
+        assert_eq!(zone.lots[0].quantity, 3);
+        assert_eq!(loaded.regular_price_of(&zone.lots[0]), Ok(Money::eur(120)));
+        assert_eq!(loaded.get_location_by_uuid(&uuid), Some(&location));
+        assert!(loaded
+            .get_items(&Location {
+                zone_id: 1,
+                ..location
+            })
+            .is_empty());
+    }
+
+    #[test]
+    fn test_load_migrates_version_2_prices() {
+        let path = temp_path("v2");
+        let v2 = format!(
+            r#"{{"version": 2, "rows": {{"0": {{"max_capacity": 1, "racks": {{"0": {{
+                "max_capacity": 1,
+                "zones": {{"0": {{"max_lots": 2, "lots": [
+                    {{"name": "Milk", "quantity": 3, "uuid": "{}",
+                        "price": 0.1, "expiration_date": "2030-01-01T00:00:00Z"}},
+                    {{"name": "Bread", "quantity": 1, "uuid": "{}",
+                        "price": 1234.57, "expiration_date": "2030-01-01T00:00:00Z"}}
+                ]}}}}
+            }}}}}}}}}}"#,
+            Uuid::new_v4(),
+            Uuid::new_v4()
+        );
+        fs::write(&path, v2).unwrap();
+        let loaded = GroceryShop::<ExpirableItem>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let prices: Vec<Result<Money, ShopError>> = loaded
+            .iter()
+            .map(|(_, item)| loaded.regular_price_of(item))
+            .collect();
+        assert_eq!(prices, vec![Ok(Money::eur(10)), Ok(Money::eur(123457))]);
+    }
+
+    #[test]
+    fn test_load_migrates_version_9_lots() {
+        let path = temp_path("v9");
+        let (leite, milk) = (Uuid::new_v4(), Uuid::new_v4());
+        let v9 = format!(
+            r#"{{"version": 9, "rows": {{"0": {{"max_capacity": 1, "racks": {{"0": {{
+                "max_capacity": 1,
+                "zones": {{"0": {{"max_lots": 2, "lots": [
+                    {{"sku": "LEI-001", "name": "Leite", "quantity": 3, "uuid": "{}",
+                        "price": {{"minor_units": 80, "currency": "EUR"}},
+                        "expiration_date": "2030-01-01T00:00:00Z"}},
+                    {{"sku": null, "name": "Milk", "quantity": 1, "uuid": "{}",
+                        "price": {{"minor_units": 120, "currency": "EUR"}},
+                        "expiration_date": "2030-01-01T00:00:00Z"}}
+                ]}}}}
+            }}}}}}}},
+            "catalogue": {{"products": {{"LEI-001": {{"sku": "LEI-001", "name": "Leite",
+                "category": "", "unit": "Piece",
+                "price": {{"minor_units": 95, "currency": "EUR"}}}}}}}}}}"#,
+            leite, milk
+        );
+        fs::write(&path, v9).unwrap();
+        let loaded = GroceryShop::<ExpirableItem>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // The product lot takes its price from the catalogue, not its old copy
+        let product_lot = loaded.get_item_by_uuid(&leite).unwrap();
+        assert_eq!(product_lot.article, crate::Article::product("LEI-001"));
+        assert_eq!(loaded.regular_price_of(product_lot), Ok(Money::eur(95)));
+        let loose_lot = loaded.get_item_by_uuid(&milk).unwrap();
+        assert_eq!(
+            loose_lot.article,
+            crate::Article::loose("Milk", Money::eur(120))
+        );
+    }
+
+    #[test]
+    fn test_load_migrates_version_11_products() {
+        let path = temp_path("v11");
+        let (order, supplier) = (Uuid::new_v4(), Uuid::new_v4());
+        let v11 = format!(
+            r#"{{"version": 11, "rows": {{}},
+            "reorder_policies": {{
+                "Leite": {{"reorder_point": 5, "target_level": 20}},
+                "Milk": {{"reorder_point": 2, "target_level": 8}}
+            }},
+            "purchase_orders": {{"{}": {{"id": "{}", "supplier_id": "{}",
+                "created_at": "2030-01-01T00:00:00Z", "expected_at": null,
+                "status": "Open", "lines": [
+                    {{"name": "Leite", "quantity": 12, "received": 0,
+                        "unit_cost": {{"minor_units": 60, "currency": "EUR"}},
+                        "min_expiration": null}},
+                    {{"name": "Milk", "quantity": 4, "received": 0,
+                        "unit_cost": {{"minor_units": 50, "currency": "EUR"}},
+                        "min_expiration": null}}
+                ]}}}},
+            "catalogue": {{"products": {{"LEI-001": {{"sku": "LEI-001", "name": "Leite",
+                "category": "", "unit": "Piece",
+                "price": {{"minor_units": 95, "currency": "EUR"}}}}}}}}}}"#,
+            order, order, supplier
+        );
+        fs::write(&path, v11).unwrap();
+        let loaded = GroceryShop::<ExpirableItem>::load_from(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // A name the catalogue knows becomes its SKU, any other stays loose
+        let keys: Vec<&ProductKey> = loaded.reorder_policies.keys().collect();
+        assert_eq!(
+            keys,
+            vec![&ProductKey::sku("LEI-001"), &ProductKey::loose("Milk")]
+        );
+        assert_eq!(loaded.on_order(&ProductKey::sku("LEI-001")), 12);
+        assert_eq!(loaded.on_order(&ProductKey::loose("Milk")), 4);
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_version() {
+        let path = temp_path("version");
+        fs::write(&path, r#"{"version": 999, "rows": {}}"#).unwrap();
+        let result = GroceryShop::<ExpirableItem>::load_from(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap_err(),
+            ShopError::UnsupportedSnapshotVersion {
+                found: 999,
+                supported: SNAPSHOT_VERSION
+            }
+        );
+    }
+}
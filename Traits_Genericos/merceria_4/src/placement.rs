@@ -0,0 +1,254 @@
+// Automatic placement of incoming items.
+//
+// `place` looks at every zone that can take another lot, orders them by the
+// chosen strategy and stores the item in the first one whose capacity limits
+// allow it. Row 0, rack 0, zone 0 is taken to be the front of the shop.
+
+use crate::{GroceryShop, Item, Location, ShopError};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlacementStrategy {
+    /// The first zone with room, in location order.
+    #[default]
+    FirstFree,
+    /// As close as possible to existing stock with the same name.
+    NearSameName,
+    /// In the row currently holding the fewest units.
+    BalanceRows,
+    /// Items expiring sooner than the rest of the stock go nearer the front.
+    ExpiringFront,
+}
+
+fn distance(a: &Location, b: &Location) -> (u32, u32, u32) {
+    // How far apart two locations are: other rows first, then racks, then zones
+    (
+        a.row_id.abs_diff(b.row_id),
+        a.rack_id.abs_diff(b.rack_id),
+        a.zone_id.abs_diff(b.zone_id),
+    )
+}
+
+impl FromStr for PlacementStrategy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "first-free" => Ok(PlacementStrategy::FirstFree),
+            "near-same-name" => Ok(PlacementStrategy::NearSameName),
+            "balance-rows" => Ok(PlacementStrategy::BalanceRows),
+            "expiring-front" => Ok(PlacementStrategy::ExpiringFront),
+            _ => Err(format!("Unknown placement strategy '{}'", name)),
+        }
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    fn placement_order(&self, item: &T, strategy: PlacementStrategy) -> Vec<Location> {
+        // Zones with room for another lot, best first for the strategy
+        let mut candidates: Vec<Location> = self.zones_with_room().collect();
+        match strategy {
+            PlacementStrategy::FirstFree => {}
+            PlacementStrategy::NearSameName => {
+                let stock: Vec<&Location> = self
+                    .name_index
+                    .get(self.name_of(item))
+                    .into_iter()
+                    .flatten()
+                    .map(|lot| &lot.location)
+                    .collect();
+                // Without stock every key is `None` and the location order stays
+                candidates.sort_by_key(|candidate| {
+                    stock
+                        .iter()
+                        .map(|location| distance(candidate, location))
+                        .min()
+                });
+            }
+            PlacementStrategy::BalanceRows => {
+                let mut row_units: BTreeMap<u32, u64> = BTreeMap::new();
+                for (location, zone) in self.zones() {
+                    *row_units.entry(location.row_id).or_default() += zone.units();
+                }
+                candidates.sort_by_key(|candidate| row_units[&candidate.row_id]);
+            }
+            PlacementStrategy::ExpiringFront => {
+                // Share of the stock expiring before the item decides how far
+                // back it goes. Items that never expire go to the back.
+                let stock: Vec<_> = self.iter().map(|(_, lot)| lot.expiration_date()).collect();
+                let ahead = match item.expiration_date() {
+                    Some(date) => stock
+                        .iter()
+                        .filter(|other| other.is_some_and(|other| other < date))
+                        .count(),
+                    None => stock.len(),
+                };
+                let target = (ahead * candidates.len())
+                    .checked_div(stock.len())
+                    .unwrap_or(0);
+                let mut ranked: Vec<(usize, Location)> =
+                    candidates.into_iter().enumerate().collect();
+                ranked.sort_by_key(|(index, _)| index.abs_diff(target));
+                candidates = ranked.into_iter().map(|(_, location)| location).collect();
+            }
+        }
+        candidates
+    }
+
+    pub fn place(&mut self, item: T, strategy: PlacementStrategy) -> Result<Location, ShopError> {
+        // Store the item in the best zone for the strategy and return where it went
+        if let Some(location) = self.uuid_index.get(&item.uuid()) {
+            return Err(ShopError::DuplicateUuid {
+                uuid: item.uuid(),
+                location: location.clone(),
+            });
+        }
+
+        let units = u64::from(item.quantity());
+        let chosen = self
+            .placement_order(&item, strategy)
+            .into_iter()
+            .find(|location| {
+                let zone_units = self.get_zone(location).map_or(0, |zone| zone.units());
+                self.check_capacity(location, &item, zone_units + units, units)
+                    .is_ok()
+            })
+            .ok_or(ShopError::ShopFull)?;
+        self.add_item(item, &chosen)?;
+        Ok(chosen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expiring_item, location};
+    use crate::{ExpirableItem, LayoutBuilder};
+
+    fn shop() -> GroceryShop<ExpirableItem> {
+        // 3 rows x 2 racks x 2 zones, 10 units per zone
+        LayoutBuilder::new()
+            .rows(3)
+            .racks_per_row(2)
+            .zones_per_rack(2)
+            .zone_unit_capacity(10)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_first_free_and_shop_full() {
+        let mut shop = shop();
+        shop.add_item(expiring_item("Leite", 1, 5), &location(0, 0, 0))
+            .unwrap();
+
+        assert_eq!(
+            shop.place(expiring_item("Pão", 1, 5), PlacementStrategy::FirstFree),
+            Ok(location(0, 0, 1))
+        );
+        // Too many units for any zone
+        assert_eq!(
+            shop.place(expiring_item("Arroz", 11, 5), PlacementStrategy::FirstFree),
+            Err(ShopError::ShopFull)
+        );
+        for _ in 0..10 {
+            shop.place(expiring_item("Ovos", 1, 5), PlacementStrategy::FirstFree)
+                .unwrap();
+        }
+        assert_eq!(
+            shop.place(expiring_item("Ovos", 1, 5), PlacementStrategy::FirstFree),
+            Err(ShopError::ShopFull)
+        );
+    }
+
+    #[test]
+    fn test_near_same_name() {
+        let mut shop = shop();
+        shop.add_item(expiring_item("Leite", 1, 5), &location(2, 1, 0))
+            .unwrap();
+        shop.add_item(expiring_item("Pão", 1, 5), &location(0, 0, 0))
+            .unwrap();
+
+        assert_eq!(
+            shop.place(
+                expiring_item("Leite", 2, 9),
+                PlacementStrategy::NearSameName
+            ),
+            Ok(location(2, 1, 1))
+        );
+        assert_eq!(
+            shop.place(
+                expiring_item("Leite", 2, 9),
+                PlacementStrategy::NearSameName
+            ),
+            Ok(location(2, 0, 0))
+        );
+        // Without stock of the same name it behaves like FirstFree
+        assert_eq!(
+            shop.place(
+                expiring_item("Queijo", 1, 9),
+                PlacementStrategy::NearSameName
+            ),
+            Ok(location(0, 0, 1))
+        );
+    }
+
+    #[test]
+    fn test_balance_rows() {
+        let mut shop = shop();
+        shop.add_item(expiring_item("Leite", 8, 5), &location(0, 0, 0))
+            .unwrap();
+        shop.add_item(expiring_item("Pão", 3, 5), &location(1, 0, 0))
+            .unwrap();
+
+        assert_eq!(
+            shop.place(
+                expiring_item("Queijo", 2, 5),
+                PlacementStrategy::BalanceRows
+            ),
+            Ok(location(2, 0, 0))
+        );
+        assert_eq!(
+            shop.place(
+                expiring_item("Queijo", 2, 5),
+                PlacementStrategy::BalanceRows
+            ),
+            Ok(location(2, 0, 1))
+        );
+        // Row 2 now holds 4 units and row 1 holds 3
+        assert_eq!(
+            shop.place(
+                expiring_item("Queijo", 2, 5),
+                PlacementStrategy::BalanceRows
+            ),
+            Ok(location(1, 0, 1))
+        );
+    }
+
+    #[test]
+    fn test_expiring_front() {
+        let mut shop = shop();
+        shop.add_item(expiring_item("Leite", 1, 10), &location(1, 0, 0))
+            .unwrap();
+        shop.add_item(expiring_item("Leite", 1, 20), &location(1, 1, 0))
+            .unwrap();
+
+        // Expires before everything else: goes to the front
+        assert_eq!(
+            shop.place(
+                expiring_item("Iogurte", 1, 1),
+                PlacementStrategy::ExpiringFront
+            ),
+            Ok(location(0, 0, 0))
+        );
+        // Expires after everything else: goes to the back
+        assert_eq!(
+            shop.place(
+                expiring_item("Arroz", 1, 300),
+                PlacementStrategy::ExpiringFront
+            ),
+            Ok(location(2, 1, 1))
+        );
+    }
+}
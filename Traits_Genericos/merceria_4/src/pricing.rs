@@ -0,0 +1,406 @@
+// Price history and scheduled price changes.
+//
+// Every price change of a lot is kept in the shop's PriceBook, keyed by the
+// lot uuid, with the time it was recorded, the time it takes effect and a
+// reason. A change without an end date is a regular price change; one with
+// an end date is a promotion, which wins over regular prices while it runs.
+//
+// A loose article carries its regular price as of its last immediate change,
+// so a scheduled change that has come due since is only found in the book.
+// A lot of a catalogue product is priced by its product, so only promotions
+// are recorded for it. The changes of a lot are dropped when it is removed
+// from the shop; a lot that is moved keeps them. `price_at`, `current_price` and `current_price_of`
+// resolve what the lot actually costs at a given time, honouring scheduled
+// changes and active promotions; stock values, price queries and merges all
+// go through them.
+
+use crate::{Article, GroceryShop, Item, Money, ShopError, ShopEvent};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// A price that applies from `from` until `until`, open-ended when `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceChange {
+    pub recorded_at: DateTime<Utc>,
+    pub from: DateTime<Utc>,
+    pub until: Option<DateTime<Utc>>,
+    pub price: Money,
+    pub reason: String,
+}
+
+impl PriceChange {
+    pub fn is_promotion(&self) -> bool {
+        self.until.is_some()
+    }
+
+    fn applies_at(&self, at: DateTime<Utc>) -> bool {
+        self.from <= at && self.until.is_none_or(|until| at < until)
+    }
+}
+
+/// Price changes of every lot, oldest recorded first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceBook {
+    changes: BTreeMap<Uuid, Vec<PriceChange>>,
+    /// While a transaction runs, the changes of every lot it touched as they
+    /// were before it started (`None` for a lot that had none).
+    #[serde(skip)]
+    saved: Option<BTreeMap<Uuid, Option<Vec<PriceChange>>>>,
+}
+
+impl PriceBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, uuid: Uuid, listed: Option<Money>, change: PriceChange) {
+        // The first change of a lot also records the price it was listed at,
+        // if it carries one, so earlier times still resolve to it
+        self.save(uuid);
+        let changes = self.changes.entry(uuid).or_default();
+        if let (true, Some(listed)) = (changes.is_empty(), listed) {
+            changes.push(PriceChange {
+                recorded_at: change.recorded_at,
+                from: DateTime::<Utc>::UNIX_EPOCH,
+                until: None,
+                price: listed,
+                reason: "listed".to_string(),
+            });
+        }
+        changes.push(change);
+    }
+
+    fn save(&mut self, uuid: Uuid) {
+        // Keep the changes of a lot as they were before the running
+        // transaction first touched them
+        if let Some(saved) = &mut self.saved {
+            saved
+                .entry(uuid)
+                .or_insert_with(|| self.changes.get(&uuid).cloned());
+        }
+    }
+
+    pub(crate) fn forget(&mut self, uuid: &Uuid) {
+        // Drop the changes of a lot that has left the shop
+        self.save(*uuid);
+        self.changes.remove(uuid);
+    }
+
+    pub(crate) fn begin(&mut self) {
+        self.saved = Some(BTreeMap::new());
+    }
+
+    pub(crate) fn commit(&mut self) {
+        self.saved = None;
+    }
+
+    pub(crate) fn roll_back(&mut self) {
+        // Put back the changes of every lot the transaction touched
+        for (uuid, changes) in self.saved.take().unwrap_or_default() {
+            match changes {
+                Some(changes) => self.changes.insert(uuid, changes),
+                None => self.changes.remove(&uuid),
+            };
+        }
+    }
+
+    pub fn history(&self, uuid: &Uuid) -> &[PriceChange] {
+        match self.changes.get(uuid) {
+            Some(changes) => changes,
+            None => &[],
+        }
+    }
+
+    pub fn price_at(&self, uuid: &Uuid, at: DateTime<Utc>) -> Option<Money> {
+        // Promotions before regular prices, then the latest to take effect,
+        // then the latest recorded
+        self.history(uuid)
+            .iter()
+            .enumerate()
+            .filter(|(_, change)| change.applies_at(at))
+            .max_by_key(|(index, change)| (change.is_promotion(), change.from, *index))
+            .map(|(_, change)| change.price)
+    }
+
+    pub fn regular_price_at(&self, uuid: &Uuid, at: DateTime<Utc>) -> Option<Money> {
+        // Like `price_at`, leaving promotions out
+        self.history(uuid)
+            .iter()
+            .enumerate()
+            .filter(|(_, change)| !change.is_promotion() && change.applies_at(at))
+            .max_by_key(|(index, change)| (change.from, *index))
+            .map(|(_, change)| change.price)
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    fn record_price(
+        &mut self,
+        uuid: &Uuid,
+        price: Money,
+        from: DateTime<Utc>,
+        until: Option<DateTime<Utc>>,
+        reason: &str,
+    ) -> Result<(), ShopError> {
+        // Log a price change of a stored lot, updating its regular price when
+        // the change is permanent and already in effect. Only promotions are
+        // recorded for a lot of a catalogue product.
+        let now = Utc::now();
+        if until.is_some_and(|until| until <= from) {
+            return Err(ShopError::InvalidPriceChange(format!(
+                "a price change must end after it starts ({})",
+                from.format("%Y-%m-%d %H:%M")
+            )));
+        }
+        let location = self
+            .get_location_by_uuid(uuid)
+            .cloned()
+            .ok_or(ShopError::UnknownUuid(*uuid))?;
+        let zone = self.get_zone_mut(&location).expect("indexed zone exists");
+        let item = zone
+            .lots
+            .iter_mut()
+            .find(|lot| lot.uuid() == *uuid)
+            .expect("indexed lot exists");
+        let before = item.clone();
+        let listed = match item.article_mut() {
+            Article::Product { sku } if until.is_none() => {
+                return Err(ShopError::CatalogueProduct {
+                    uuid: *uuid,
+                    sku: sku.clone(),
+                });
+            }
+            Article::Product { .. } => None,
+            Article::Loose { price: regular, .. } => {
+                let listed = *regular;
+                if until.is_none() && from <= now {
+                    *regular = price;
+                }
+                Some(listed)
+            }
+        };
+        if *item != before {
+            let after = item.clone();
+            self.observers.notify(ShopEvent::Edited {
+                before,
+                after,
+                location,
+            });
+        }
+
+        self.prices.record(
+            *uuid,
+            listed,
+            PriceChange {
+                recorded_at: now,
+                from,
+                until,
+                price,
+                reason: reason.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn set_price(&mut self, uuid: &Uuid, price: Money, reason: &str) -> Result<(), ShopError> {
+        // Change the regular price of a lot from now on
+        self.record_price(uuid, price, Utc::now(), None, reason)
+    }
+
+    pub fn schedule_price(
+        &mut self,
+        uuid: &Uuid,
+        price: Money,
+        from: DateTime<Utc>,
+        reason: &str,
+    ) -> Result<(), ShopError> {
+        // Change the regular price of a lot from a given time on
+        self.record_price(uuid, price, from, None, reason)
+    }
+
+    pub fn add_promotion(
+        &mut self,
+        uuid: &Uuid,
+        price: Money,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        reason: &str,
+    ) -> Result<(), ShopError> {
+        // Sell a lot at `price` from `from` until (not including) `until`
+        self.record_price(uuid, price, from, Some(until), reason)
+    }
+
+    pub fn price_at(&self, uuid: &Uuid, at: DateTime<Utc>) -> Option<Money> {
+        // Price of a lot at any time: its own price changes first, then its
+        // regular price. None for a lot the shop cannot price.
+        self.prices
+            .price_at(uuid, at)
+            .or_else(|| self.regular_price_of(self.get_item_by_uuid(uuid)?).ok())
+    }
+
+    pub fn current_price(&self, uuid: &Uuid) -> Option<Money> {
+        // Price of a lot right now, promotions included
+        self.price_at(uuid, Utc::now())
+    }
+
+    pub fn current_price_of(&self, item: &T) -> Result<Money, ShopError> {
+        // Price a lot sells at right now, stored in the shop or about to be
+        match self.prices.price_at(&item.uuid(), Utc::now()) {
+            Some(price) => Ok(price),
+            None => self.regular_price_of(item),
+        }
+    }
+
+    pub fn price_history(&self, uuid: &Uuid) -> &[PriceChange] {
+        self.prices.history(uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{location, priced_item, shop_with};
+    use crate::ExpirableItem;
+    use chrono::Duration;
+
+    #[test]
+    fn test_price_changes_are_recorded() {
+        let milk = priced_item("Leite", 4, Money::eur(100));
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+        let before = Utc::now();
+
+        assert_eq!(shop.current_price(&milk.uuid), Some(Money::eur(100)));
+        assert!(shop.price_history(&milk.uuid).is_empty());
+
+        shop.set_price(&milk.uuid, Money::eur(120), "supplier increase")
+            .unwrap();
+        let history = shop.price_history(&milk.uuid);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].price, Money::eur(100));
+        assert_eq!(history[1].price, Money::eur(120));
+        assert_eq!(history[1].reason, "supplier increase");
+        assert!(history[1].recorded_at >= before);
+
+        assert_eq!(
+            shop.get_item_by_uuid(&milk.uuid).unwrap().article,
+            Article::loose("Leite", Money::eur(120))
+        );
+        assert_eq!(
+            shop.price_at(&milk.uuid, before - Duration::hours(1)),
+            Some(Money::eur(100))
+        );
+        assert_eq!(shop.current_price(&milk.uuid), Some(Money::eur(120)));
+        assert_eq!(shop.price_at(&Uuid::new_v4(), before), None);
+        assert_eq!(
+            shop.set_price(&Uuid::nil(), Money::eur(1), "typo"),
+            Err(ShopError::UnknownUuid(Uuid::nil()))
+        );
+
+        // Moving keeps the history, removing drops it
+        shop.move_item(location(0, 0, 0), &milk.uuid, &location(1, 0, 0))
+            .unwrap();
+        assert_eq!(shop.price_history(&milk.uuid).len(), 2);
+        shop.remove_item(&location(1, 0, 0), &milk.uuid).unwrap();
+        assert!(shop.price_history(&milk.uuid).is_empty());
+    }
+
+    #[test]
+    fn test_scheduled_changes_and_promotions() {
+        let milk = priced_item("Leite", 4, Money::eur(100));
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+        let now = Utc::now();
+        let day = |days: i64| now + Duration::days(days);
+
+        shop.schedule_price(&milk.uuid, Money::eur(110), day(10), "new season")
+            .unwrap();
+        shop.add_promotion(&milk.uuid, Money::eur(80), day(2), day(5), "weekend")
+            .unwrap();
+        shop.add_promotion(&milk.uuid, Money::eur(70), day(4), day(12), "clearance")
+            .unwrap();
+
+        // The regular price only changes once the scheduled change is due
+        assert_eq!(
+            shop.get_item_by_uuid(&milk.uuid).unwrap().article,
+            milk.article
+        );
+        assert_eq!(shop.current_price(&milk.uuid), Some(Money::eur(100)));
+        assert_eq!(shop.price_at(&milk.uuid, day(3)), Some(Money::eur(80)));
+        // The promotion that started last wins
+        assert_eq!(shop.price_at(&milk.uuid, day(4)), Some(Money::eur(70)));
+        assert_eq!(shop.price_at(&milk.uuid, day(11)), Some(Money::eur(70)));
+        assert_eq!(shop.price_at(&milk.uuid, day(12)), Some(Money::eur(110)));
+
+        assert!(matches!(
+            shop.add_promotion(&milk.uuid, Money::eur(50), day(5), day(5), "none"),
+            Err(ShopError::InvalidPriceChange(_))
+        ));
+        assert_eq!(shop.price_history(&milk.uuid).len(), 4);
+    }
+
+    #[test]
+    fn test_due_scheduled_changes_reach_every_price_read() {
+        let milk = priced_item("Leite", 4, Money::eur(100));
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+        let now = Utc::now();
+
+        // A change scheduled yesterday for an hour ago: the lot itself still
+        // carries the price it was listed at
+        shop.prices.record(
+            milk.uuid,
+            Some(Money::eur(100)),
+            PriceChange {
+                recorded_at: now - Duration::days(1),
+                from: now - Duration::hours(1),
+                until: None,
+                price: Money::eur(120),
+                reason: "new season".to_string(),
+            },
+        );
+        let stored = shop.get_item_by_uuid(&milk.uuid).unwrap();
+        assert_eq!(stored.article, milk.article);
+        assert_eq!(shop.regular_price_of(stored), Ok(Money::eur(120)));
+        assert_eq!(shop.total_value(), Ok(Money::eur(480)));
+        let query: crate::Query = "price > 1.10".parse().unwrap();
+        assert_eq!(shop.query(&query).count(), 1);
+
+        // Only a lot at the new price merges into it
+        let stale = ExpirableItem {
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+        assert!(shop
+            .add_item_with_mode(stale, &location(0, 0, 0), crate::AddMode::Merge)
+            .is_err());
+        let repriced = ExpirableItem {
+            article: Article::loose("Leite", Money::eur(120)),
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+        shop.add_item_with_mode(repriced, &location(0, 0, 0), crate::AddMode::Merge)
+            .unwrap();
+        assert_eq!(shop.get_item_by_uuid(&milk.uuid).unwrap().quantity, 8);
+    }
+
+    #[test]
+    fn test_edits_through_get_item_mut_are_recorded() {
+        let milk = priced_item("Leite", 4, Money::eur(100));
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+
+        if let Some(mut lot) = shop.get_item_mut(&location(0, 0, 0), &milk.uuid) {
+            lot.article = Article::loose("Leite", Money::eur(95));
+            lot.commit().unwrap();
+        }
+        if let Some(mut lot) = shop.get_item_mut(&location(0, 0, 0), &milk.uuid) {
+            lot.quantity = 1;
+            lot.commit().unwrap();
+        }
+
+        let history = shop.price_history(&milk.uuid);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].price, Money::eur(95));
+        assert_eq!(history[1].reason, "edited");
+        assert_eq!(shop.current_price(&milk.uuid), Some(Money::eur(95)));
+    }
+}
@@ -0,0 +1,593 @@
+// Suppliers, purchase orders and the receiving of deliveries.
+//
+// A PurchaseOrder lists what the shop ordered from a Supplier: products (by
+// SKU, or by name for loose articles), quantities, unit costs and,
+// optionally, the earliest expiration date the shop accepts for each line. Receiving a delivery against an open order
+// compares every delivered lot with the order, flags the discrepancies and
+// puts the accepted lots away through `add_item`, all in one transaction.
+//
+// Lots of products the order does not list, or that expire before the order
+// allows, are rejected. A product delivered above what is still outstanding
+// is accepted only up to the outstanding quantity. Units still outstanding on
+// open orders count as on order when suggesting purchases.
+
+use crate::{GroceryShop, Item, Location, Money, ProductKey, ShopError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Supplier {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// `quantity` units of `product`, of which `received` have arrived.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderLine {
+    pub product: ProductKey,
+    pub quantity: u32,
+    pub unit_cost: Money,
+    /// Delivered lots must not expire before this date.
+    pub min_expiration: Option<DateTime<Utc>>,
+    pub received: u32,
+}
+
+impl OrderLine {
+    pub fn new(product: ProductKey, quantity: u32, unit_cost: Money) -> Self {
+        OrderLine {
+            product,
+            quantity,
+            unit_cost,
+            min_expiration: None,
+            received: 0,
+        }
+    }
+
+    pub fn min_expiration(mut self, date: DateTime<Utc>) -> Self {
+        self.min_expiration = Some(date);
+        self
+    }
+
+    pub fn outstanding(&self) -> u32 {
+        self.quantity.saturating_sub(self.received)
+    }
+
+    fn accepts<T: Item>(&self, item: &T) -> bool {
+        // Lots without an expiration date never expire too early
+        match (self.min_expiration, item.expiration_date()) {
+            (Some(min), Some(expires)) => expires >= min,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    Open,
+    PartiallyReceived,
+    Received,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PurchaseOrder {
+    pub id: Uuid,
+    pub supplier_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expected_at: Option<DateTime<Utc>>,
+    pub lines: Vec<OrderLine>,
+    pub status: OrderStatus,
+}
+
+impl PurchaseOrder {
+    pub fn new(supplier_id: Uuid) -> Self {
+        PurchaseOrder {
+            id: Uuid::new_v4(),
+            supplier_id,
+            created_at: Utc::now(),
+            expected_at: None,
+            lines: Vec::new(),
+            status: OrderStatus::Open,
+        }
+    }
+
+    pub fn expected_at(mut self, date: DateTime<Utc>) -> Self {
+        self.expected_at = Some(date);
+        self
+    }
+
+    pub fn line(mut self, line: OrderLine) -> Self {
+        self.lines.push(line);
+        self
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self.status,
+            OrderStatus::Open | OrderStatus::PartiallyReceived
+        )
+    }
+
+    pub fn outstanding(&self, product: &ProductKey) -> u64 {
+        // Units of a product ordered but not received yet
+        self.lines
+            .iter()
+            .filter(|line| line.product == *product)
+            .map(|line| u64::from(line.outstanding()))
+            .sum()
+    }
+
+    fn products(&self) -> Vec<&ProductKey> {
+        // Ordered products, each once, in order line order
+        let mut products: Vec<&ProductKey> = Vec::new();
+        for line in &self.lines {
+            if !products.contains(&&line.product) {
+                products.push(&line.product);
+            }
+        }
+        products
+    }
+}
+
+/// A lot that arrived in a delivery, with the zone it goes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveredLot<T: Item> {
+    pub item: T,
+    pub location: Location,
+}
+
+/// Something in a delivery that does not match its purchase order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Fewer units of the product arrived than were outstanding.
+    Short {
+        name: String,
+        outstanding: u64,
+        delivered: u64,
+    },
+    /// More units of the product arrived than were outstanding; the excess is rejected.
+    Over {
+        name: String,
+        outstanding: u64,
+        delivered: u64,
+    },
+    /// A lot of a product the order does not list.
+    NotOrdered { name: String, uuid: Uuid },
+    /// A lot that expires before the order allows.
+    ShortDated {
+        name: String,
+        uuid: Uuid,
+        expires: Option<DateTime<Utc>>,
+        min_expiration: Option<DateTime<Utc>>,
+    },
+}
+
+/// The outcome of checking a delivery against its purchase order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivingReport<T: Item> {
+    pub order_id: Uuid,
+    /// Lots to put away, cut down to what was outstanding.
+    pub accepted: Vec<DeliveredLot<T>>,
+    /// Lots, or the part of a lot, sent back to the supplier.
+    pub rejected: Vec<DeliveredLot<T>>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl<T: Item> ReceivingReport<T> {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn add_supplier(&mut self, name: &str) -> Uuid {
+        // Register a supplier and return its id
+        let supplier = Supplier {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+        };
+        let id = supplier.id;
+        self.suppliers.insert(id, supplier);
+        id
+    }
+
+    pub fn supplier(&self, id: &Uuid) -> Option<&Supplier> {
+        self.suppliers.get(id)
+    }
+
+    pub fn place_order(&mut self, order: PurchaseOrder) -> Result<Uuid, ShopError> {
+        // Record an order to a known supplier, returning its id
+        if !self.suppliers.contains_key(&order.supplier_id) {
+            return Err(ShopError::UnknownSupplier(order.supplier_id));
+        }
+        if order.lines.is_empty() {
+            return Err(ShopError::InvalidPurchaseOrder(
+                "the order has no lines".to_string(),
+            ));
+        }
+        if let Some(line) = order.lines.iter().find(|line| line.quantity == 0) {
+            return Err(ShopError::InvalidPurchaseOrder(format!(
+                "the line for {} orders no units",
+                self.product_name(&line.product)
+            )));
+        }
+        for line in &order.lines {
+            self.check_product(&line.product)?;
+        }
+        let id = order.id;
+        self.purchase_orders.insert(id, order);
+        Ok(id)
+    }
+
+    pub fn purchase_order(&self, id: &Uuid) -> Option<&PurchaseOrder> {
+        self.purchase_orders.get(id)
+    }
+
+    pub fn cancel_order(&mut self, id: &Uuid) -> Result<(), ShopError> {
+        // Stop waiting for what is still outstanding on an order
+        let order = self.open_order_mut(id)?;
+        order.status = OrderStatus::Cancelled;
+        Ok(())
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &PurchaseOrder> {
+        // Orders still waiting for goods, oldest first
+        let mut orders: Vec<&PurchaseOrder> = self
+            .purchase_orders
+            .values()
+            .filter(|order| order.is_open())
+            .collect();
+        orders.sort_by_key(|order| (order.created_at, order.id));
+        orders.into_iter()
+    }
+
+    pub fn on_order(&self, product: &ProductKey) -> u64 {
+        // Units of a product still outstanding over every open order
+        self.open_orders()
+            .map(|order| order.outstanding(product))
+            .sum()
+    }
+
+    fn open_order_mut(&mut self, id: &Uuid) -> Result<&mut PurchaseOrder, ShopError> {
+        let order = self
+            .purchase_orders
+            .get_mut(id)
+            .ok_or(ShopError::UnknownPurchaseOrder(*id))?;
+        if !order.is_open() {
+            return Err(ShopError::PurchaseOrderClosed(*id));
+        }
+        Ok(order)
+    }
+
+    fn match_delivery(
+        &self,
+        order_id: &Uuid,
+        delivery: Vec<DeliveredLot<T>>,
+    ) -> Result<(ReceivingReport<T>, Vec<u32>), ShopError> {
+        // Split a delivery into accepted and rejected lots, along with the
+        // units each order line receives
+        let order = self
+            .purchase_orders
+            .get(order_id)
+            .ok_or(ShopError::UnknownPurchaseOrder(*order_id))?;
+        if !order.is_open() {
+            return Err(ShopError::PurchaseOrderClosed(*order_id));
+        }
+        let mut report = ReceivingReport {
+            order_id: *order_id,
+            accepted: Vec::new(),
+            rejected: Vec::new(),
+            discrepancies: Vec::new(),
+        };
+        let mut received = vec![0u32; order.lines.len()];
+        let mut delivered: Vec<(ProductKey, u64)> = Vec::new();
+
+        for lot in delivery {
+            let product = lot.item.article().key();
+            let name = self.name_of(&lot.item).to_string();
+            let uuid = lot.item.uuid();
+            let lines: Vec<usize> = (0..order.lines.len())
+                .filter(|&index| order.lines[index].product == product)
+                .collect();
+            if lines.is_empty() {
+                report
+                    .discrepancies
+                    .push(Discrepancy::NotOrdered { name, uuid });
+                report.rejected.push(lot);
+                continue;
+            }
+            if !lines
+                .iter()
+                .any(|&index| order.lines[index].accepts(&lot.item))
+            {
+                report.discrepancies.push(Discrepancy::ShortDated {
+                    name,
+                    uuid,
+                    expires: lot.item.expiration_date(),
+                    min_expiration: order.lines[lines[0]].min_expiration,
+                });
+                report.rejected.push(lot);
+                continue;
+            }
+            match delivered.iter_mut().find(|(other, _)| *other == product) {
+                Some((_, units)) => *units += u64::from(lot.item.quantity()),
+                None => delivered.push((product, u64::from(lot.item.quantity()))),
+            }
+
+            // Fill the lines the lot is good for, in order
+            let mut left = lot.item.quantity();
+            for &index in &lines {
+                let line = &order.lines[index];
+                if line.accepts(&lot.item) {
+                    let units = left.min(line.outstanding() - received[index]);
+                    received[index] += units;
+                    left -= units;
+                }
+            }
+            let taken = lot.item.quantity() - left;
+            if taken > 0 {
+                let mut item = lot.item.clone();
+                item.set_quantity(taken);
+                report.accepted.push(DeliveredLot {
+                    item,
+                    location: lot.location.clone(),
+                });
+            }
+            if left > 0 {
+                let mut item = lot.item;
+                item.set_quantity(left);
+                report.rejected.push(DeliveredLot {
+                    item,
+                    location: lot.location,
+                });
+            }
+        }
+
+        for product in order.products() {
+            let outstanding = order.outstanding(product);
+            let delivered = delivered
+                .iter()
+                .find(|(other, _)| other == product)
+                .map_or(0, |(_, units)| *units);
+            let name = self.product_name(product).to_string();
+            if delivered < outstanding {
+                report.discrepancies.push(Discrepancy::Short {
+                    name,
+                    outstanding,
+                    delivered,
+                });
+            } else if delivered > outstanding {
+                report.discrepancies.push(Discrepancy::Over {
+                    name,
+                    outstanding,
+                    delivered,
+                });
+            }
+        }
+        Ok((report, received))
+    }
+
+    pub fn inspect_delivery(
+        &self,
+        order_id: &Uuid,
+        delivery: Vec<DeliveredLot<T>>,
+    ) -> Result<ReceivingReport<T>, ShopError> {
+        // What receiving the delivery would accept, reject and flag, without
+        // changing anything
+        self.match_delivery(order_id, delivery)
+            .map(|(report, _)| report)
+    }
+
+    pub fn receive(
+        &mut self,
+        order_id: &Uuid,
+        delivery: Vec<DeliveredLot<T>>,
+    ) -> Result<ReceivingReport<T>, ShopError> {
+        // Put the accepted lots away and mark them received on the order, or
+        // change nothing if one of them does not fit
+        let (report, received) = self.match_delivery(order_id, delivery)?;
+        self.transaction(|tx| {
+            for lot in &report.accepted {
+                tx.add_item(lot.item.clone(), &lot.location)?;
+            }
+            Ok(())
+        })?;
+
+        let order = self.open_order_mut(order_id)?;
+        for (line, units) in order.lines.iter_mut().zip(received) {
+            line.received += units;
+        }
+        order.status = if order.lines.iter().all(|line| line.outstanding() == 0) {
+            OrderStatus::Received
+        } else if order.lines.iter().any(|line| line.received > 0) {
+            OrderStatus::PartiallyReceived
+        } else {
+            OrderStatus::Open
+        };
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expiring_item, location, shop_with};
+    use crate::ExpirableItem;
+    use chrono::Duration;
+
+    fn lot(name: &str, quantity: u32, days: i64, row_id: u32) -> DeliveredLot<ExpirableItem> {
+        DeliveredLot {
+            item: expiring_item(name, quantity, days),
+            location: location(row_id, 0, 0),
+        }
+    }
+
+    fn shop_with_order() -> (GroceryShop<ExpirableItem>, Uuid) {
+        let mut shop = shop_with(vec![]);
+        let supplier = shop.add_supplier("Lactogal");
+        let order = PurchaseOrder::new(supplier)
+            .line(
+                OrderLine::new(ProductKey::loose("Leite"), 12, Money::eur(60))
+                    .min_expiration(Utc::now() + Duration::days(7)),
+            )
+            .line(OrderLine::new(
+                ProductKey::loose("Manteiga"),
+                4,
+                Money::eur(180),
+            ));
+        let id = shop.place_order(order).unwrap();
+        (shop, id)
+    }
+
+    #[test]
+    fn test_orders_are_validated() {
+        let (mut shop, id) = shop_with_order();
+
+        assert_eq!(
+            shop.place_order(PurchaseOrder::new(Uuid::nil())),
+            Err(ShopError::UnknownSupplier(Uuid::nil()))
+        );
+        let supplier = shop.purchase_order(&id).unwrap().supplier_id;
+        assert_eq!(shop.supplier(&supplier).unwrap().name, "Lactogal");
+        assert!(matches!(
+            shop.place_order(PurchaseOrder::new(supplier)),
+            Err(ShopError::InvalidPurchaseOrder(_))
+        ));
+        assert!(matches!(
+            shop.place_order(PurchaseOrder::new(supplier).line(OrderLine::new(
+                ProductKey::loose("Leite"),
+                0,
+                Money::eur(60)
+            ))),
+            Err(ShopError::InvalidPurchaseOrder(_))
+        ));
+        assert_eq!(
+            shop.place_order(PurchaseOrder::new(supplier).line(OrderLine::new(
+                ProductKey::sku("NAT-002"),
+                6,
+                Money::eur(90)
+            ))),
+            Err(ShopError::UnknownSku(crate::Sku::new("NAT-002")))
+        );
+
+        assert_eq!(shop.on_order(&ProductKey::loose("Leite")), 12);
+        shop.cancel_order(&id).unwrap();
+        assert_eq!(shop.on_order(&ProductKey::loose("Leite")), 0);
+        assert_eq!(shop.open_orders().count(), 0);
+        assert_eq!(
+            shop.cancel_order(&id),
+            Err(ShopError::PurchaseOrderClosed(id))
+        );
+    }
+
+    #[test]
+    fn test_receiving_flags_discrepancies() {
+        let (mut shop, id) = shop_with_order();
+        let fresh = lot("Leite", 10, 30, 0);
+        let short_dated = lot("Leite", 6, 2, 1);
+        let unordered = lot("Iogurte", 8, 30, 1);
+        let butter = lot("Manteiga", 6, 60, 1);
+
+        let report = shop
+            .receive(
+                &id,
+                vec![
+                    fresh.clone(),
+                    short_dated.clone(),
+                    unordered.clone(),
+                    butter.clone(),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(
+            report.discrepancies,
+            vec![
+                Discrepancy::ShortDated {
+                    name: "Leite".to_string(),
+                    uuid: short_dated.item.uuid,
+                    expires: Some(short_dated.item.expiration_date),
+                    min_expiration: shop.purchase_order(&id).unwrap().lines[0].min_expiration,
+                },
+                Discrepancy::NotOrdered {
+                    name: "Iogurte".to_string(),
+                    uuid: unordered.item.uuid,
+                },
+                Discrepancy::Short {
+                    name: "Leite".to_string(),
+                    outstanding: 12,
+                    delivered: 10,
+                },
+                Discrepancy::Over {
+                    name: "Manteiga".to_string(),
+                    outstanding: 4,
+                    delivered: 6,
+                },
+            ]
+        );
+        // Only the outstanding butter is accepted, the rest goes back
+        assert_eq!(report.accepted.len(), 2);
+        assert_eq!(report.accepted[1].item.quantity, 4);
+        assert_eq!(report.rejected.len(), 3);
+        assert_eq!(report.rejected[2].item.quantity, 2);
+
+        assert_eq!(shop.stock_of("Leite"), 10);
+        assert_eq!(shop.stock_of("Manteiga"), 4);
+        assert_eq!(shop.stock_of("Iogurte"), 0);
+        let order = shop.purchase_order(&id).unwrap();
+        assert_eq!(order.status, OrderStatus::PartiallyReceived);
+        assert_eq!(shop.on_order(&ProductKey::loose("Leite")), 2);
+        assert_eq!(shop.on_order(&ProductKey::loose("Manteiga")), 0);
+
+        let report = shop.receive(&id, vec![lot("Leite", 2, 30, 2)]).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(
+            shop.purchase_order(&id).unwrap().status,
+            OrderStatus::Received
+        );
+        assert_eq!(
+            shop.receive(&id, vec![lot("Leite", 1, 30, 0)]),
+            Err(ShopError::PurchaseOrderClosed(id))
+        );
+    }
+
+    #[test]
+    fn test_receiving_is_all_or_nothing() {
+        let (mut shop, id) = shop_with_order();
+        let mut misplaced = lot("Manteiga", 4, 60, 0);
+        misplaced.location.row_id = 9;
+
+        let inspected = shop
+            .inspect_delivery(&id, vec![lot("Leite", 12, 30, 0), misplaced.clone()])
+            .unwrap();
+        assert!(inspected.is_clean());
+        assert_eq!(
+            shop.receive(&id, vec![lot("Leite", 12, 30, 0), misplaced.clone()]),
+            Err(ShopError::LocationNotFound(misplaced.location))
+        );
+        assert_eq!(shop.stock_of("Leite"), 0);
+        assert_eq!(shop.on_order(&ProductKey::loose("Leite")), 12);
+        assert_eq!(shop.purchase_order(&id).unwrap().status, OrderStatus::Open);
+    }
+
+    #[test]
+    fn test_open_orders_reduce_purchase_suggestions() {
+        let (mut shop, _) = shop_with_order();
+        shop.set_reorder_policy(ProductKey::loose("Leite"), 5, 20)
+            .unwrap();
+        shop.set_reorder_policy(ProductKey::loose("Manteiga"), 2, 3)
+            .unwrap();
+
+        let low = shop.low_stock();
+        assert_eq!(low[0].on_order, 12);
+        assert_eq!(
+            shop.purchase_suggestions(),
+            vec![crate::PurchaseSuggestion {
+                product: ProductKey::loose("Leite"),
+                name: "Leite".to_string(),
+                quantity: 8,
+            }]
+        );
+    }
+}
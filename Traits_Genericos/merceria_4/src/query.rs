@@ -0,0 +1,584 @@
+// Composable queries over the items of a shop.
+//
+// A Query is a Filter (predicates on name, price, quantity and location
+// combined with and/or/not) plus an optional sort order and limit. Queries can
+// be built in code or parsed from a small text syntax, e.g.
+//
+//     price < 2.00 and row = 1 and quantity < 5 sort price desc limit 10
+//     name ~ leite or not (rack in 0..1) sort location
+//
+// `=` and `~` on names compare case- and accent-insensitively, `~` meaning
+// "contains". Strings with spaces are written between double quotes, and so
+// are prices with a decimal comma or a currency ("2,50 €"). Prices are the
+// ones lots sell at right now, and only match items priced in the same
+// currency. Parentheses and `not` nest at most MAX_QUERY_DEPTH deep.
+
+use crate::search::normalize;
+use crate::{GroceryShop, Item, Location, Money, MoneyError, ShopError};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// How deep parentheses and `not` may nest in a text query.
+pub const MAX_QUERY_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds<V: Ord>(self, value: V, other: V) -> bool {
+        let ordering = value.cmp(&other);
+        match self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Same name, ignoring case and accents.
+    Name(String),
+    /// Name contains the text, ignoring case and accents.
+    NameContains(String),
+    /// Only matches items priced in the same currency.
+    Price(Comparison, Money),
+    Quantity(Comparison, u32),
+    Row(Comparison, u32),
+    Rack(Comparison, u32),
+    Zone(Comparison, u32),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn and(self, other: Filter) -> Filter {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+
+    pub fn matches<T: Item>(&self, shop: &GroceryShop<T>, location: &Location, item: &T) -> bool {
+        // Check the filter against one item of the shop stored at the location,
+        // by the name and current price the shop resolves for it
+        match self {
+            Filter::Name(name) => normalize(shop.name_of(item)) == normalize(name),
+            Filter::NameContains(text) => normalize(shop.name_of(item)).contains(&normalize(text)),
+            Filter::Price(comparison, price) => shop.current_price_of(item).is_ok_and(|own| {
+                own.currency() == price.currency()
+                    && comparison.holds(own.minor_units(), price.minor_units())
+            }),
+            Filter::Quantity(comparison, quantity) => comparison.holds(item.quantity(), *quantity),
+            Filter::Row(comparison, row_id) => comparison.holds(location.row_id, *row_id),
+            Filter::Rack(comparison, rack_id) => comparison.holds(location.rack_id, *rack_id),
+            Filter::Zone(comparison, zone_id) => comparison.holds(location.zone_id, *zone_id),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(shop, location, item)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(shop, location, item)),
+            Filter::Not(filter) => !filter.matches(shop, location, item),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Price,
+    Quantity,
+    Expiration,
+    Location,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    filter: Option<Filter>,
+    sort: Vec<(SortKey, bool)>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep only the items matching `filter`, on top of any previous filter.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(match self.filter {
+            Some(previous) => previous.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    /// Sort by `key`. Later keys break ties of earlier ones.
+    pub fn sort_by(mut self, key: SortKey, descending: bool) -> Self {
+        self.sort.push((key, descending));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn compare<T: Item>(
+        &self,
+        shop: &GroceryShop<T>,
+        a: &(Location, &T),
+        b: &(Location, &T),
+    ) -> Ordering {
+        // Order two results by the sort keys, falling back to location and uuid
+        let mut ordering = Ordering::Equal;
+        for (key, descending) in &self.sort {
+            let by_key = match key {
+                SortKey::Name => shop.name_of(a.1).cmp(shop.name_of(b.1)),
+                SortKey::Price => shop
+                    .current_price_of(a.1)
+                    .ok()
+                    .cmp(&shop.current_price_of(b.1).ok()),
+                SortKey::Quantity => a.1.quantity().cmp(&b.1.quantity()),
+                SortKey::Expiration => a.1.expiration_date().cmp(&b.1.expiration_date()),
+                SortKey::Location => a.0.cmp(&b.0),
+            };
+            ordering = ordering.then(if *descending {
+                by_key.reverse()
+            } else {
+                by_key
+            });
+        }
+        ordering
+            .then_with(|| a.0.cmp(&b.0))
+            .then_with(|| a.1.uuid().cmp(&b.1.uuid()))
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn query<'a>(&'a self, query: &Query) -> impl Iterator<Item = (Location, &'a T)> {
+        // Items matching the query, sorted and limited as it asks
+        let mut results: Vec<(Location, &T)> = self
+            .uuid_index
+            .iter()
+            .filter_map(|(uuid, location)| Some((location.clone(), self.get_item(location, uuid)?)))
+            .filter(|(location, item)| {
+                query
+                    .filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(self, location, item))
+            })
+            .collect();
+        results.sort_by(|a, b| query.compare(self, a, b));
+        results.into_iter().take(query.limit.unwrap_or(usize::MAX))
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, ShopError> {
+    // Split the text into words, quoted strings, parentheses and operators
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' || c == '~' || c == ',' {
+            tokens.push(c.to_string());
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut quoted = String::from("\"");
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => quoted.push(c),
+                    None => return Err(invalid("unterminated string")),
+                }
+            }
+            tokens.push(quoted);
+        } else if "=!<>".contains(c) {
+            chars.next();
+            let mut operator = c.to_string();
+            if chars.peek() == Some(&'=') {
+                operator.push('=');
+                chars.next();
+            }
+            tokens.push(operator);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || "()~,\"=!<>".contains(c) {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        }
+    }
+    Ok(tokens)
+}
+
+fn invalid(message: &str) -> ShopError {
+    ShopError::InvalidQuery(message.to_string())
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Result<String, ShopError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| invalid("unexpected end of query"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn number<V: FromStr>(&mut self) -> Result<V, ShopError> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| ShopError::InvalidQuery(format!("'{}' is not a valid number", token)))
+    }
+
+    fn expression(&mut self) -> Result<Filter, ShopError> {
+        // expression := term { "or" term }
+        let mut filter = self.term()?;
+        while self.peek_keyword("or") {
+            self.position += 1;
+            filter = filter.or(self.term()?);
+        }
+        Ok(filter)
+    }
+
+    fn term(&mut self) -> Result<Filter, ShopError> {
+        // term := factor { "and" factor }
+        let mut filter = self.factor()?;
+        while self.peek_keyword("and") {
+            self.position += 1;
+            filter = filter.and(self.factor()?);
+        }
+        Ok(filter)
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Filter, ShopError>
+    where
+        F: FnOnce(&mut Self) -> Result<Filter, ShopError>,
+    {
+        // Parse one level deeper, refusing queries nested beyond MAX_QUERY_DEPTH
+        if self.depth == MAX_QUERY_DEPTH {
+            return Err(ShopError::InvalidQuery(format!(
+                "nested more than {} levels deep",
+                MAX_QUERY_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let filter = parse(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn factor(&mut self) -> Result<Filter, ShopError> {
+        // factor := "not" factor | "(" expression ")" | field operator value | field "in" a..b
+        if self.peek_keyword("not") {
+            self.position += 1;
+            return Ok(self.nested(Self::factor)?.not());
+        }
+        if self.peek() == Some("(") {
+            self.position += 1;
+            let filter = self.nested(Self::expression)?;
+            if self.next()? != ")" {
+                return Err(invalid("missing ')'"));
+            }
+            return Ok(filter);
+        }
+
+        let field = self.next()?.to_lowercase();
+        let operator = self.next()?;
+        if field == "name" {
+            let value = self.next()?;
+            let value = value.strip_prefix('"').unwrap_or(&value).to_string();
+            return match operator.as_str() {
+                "=" => Ok(Filter::Name(value)),
+                "!=" => Ok(Filter::Name(value).not()),
+                "~" => Ok(Filter::NameContains(value)),
+                _ => Err(ShopError::InvalidQuery(format!(
+                    "'{}' cannot be used on names",
+                    operator
+                ))),
+            };
+        }
+
+        let location_filter = match field.as_str() {
+            "row" => Filter::Row,
+            "rack" => Filter::Rack,
+            "zone" => Filter::Zone,
+            "price" => {
+                let comparison = comparison(&operator)?;
+                let price = self.next()?;
+                let price = price.strip_prefix('"').unwrap_or(&price);
+                return Ok(Filter::Price(
+                    comparison,
+                    price
+                        .parse()
+                        .map_err(|err: MoneyError| ShopError::InvalidQuery(err.to_string()))?,
+                ));
+            }
+            "quantity" => {
+                let comparison = comparison(&operator)?;
+                return Ok(Filter::Quantity(comparison, self.number()?));
+            }
+            _ => {
+                return Err(ShopError::InvalidQuery(format!(
+                    "unknown field '{}'",
+                    field
+                )))
+            }
+        };
+
+        if operator.eq_ignore_ascii_case("in") {
+            let range = self.next()?;
+            let (from, to) = range
+                .split_once("..")
+                .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+                .ok_or_else(|| {
+                    ShopError::InvalidQuery(format!("'{}' is not a range like 0..2", range))
+                })?;
+            return Ok(
+                location_filter(Comparison::Ge, from).and(location_filter(Comparison::Le, to))
+            );
+        }
+        Ok(location_filter(comparison(&operator)?, self.number()?))
+    }
+
+    fn sort_key(&mut self) -> Result<SortKey, ShopError> {
+        let key = self.next()?.to_lowercase();
+        match key.as_str() {
+            "name" => Ok(SortKey::Name),
+            "price" => Ok(SortKey::Price),
+            "quantity" => Ok(SortKey::Quantity),
+            "expiration" => Ok(SortKey::Expiration),
+            "location" => Ok(SortKey::Location),
+            _ => Err(ShopError::InvalidQuery(format!("cannot sort by '{}'", key))),
+        }
+    }
+}
+
+fn comparison(operator: &str) -> Result<Comparison, ShopError> {
+    match operator {
+        "=" => Ok(Comparison::Eq),
+        "!=" => Ok(Comparison::Ne),
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        ">" => Ok(Comparison::Gt),
+        ">=" => Ok(Comparison::Ge),
+        _ => Err(ShopError::InvalidQuery(format!(
+            "unknown operator '{}'",
+            operator
+        ))),
+    }
+}
+
+impl FromStr for Query {
+    type Err = ShopError;
+
+    fn from_str(text: &str) -> Result<Self, ShopError> {
+        // query := [expression] ["sort" key [asc|desc] {"," key [asc|desc]}] ["limit" n]
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            depth: 0,
+        };
+        let mut query = Query::new();
+
+        if parser.peek().is_some() && !parser.peek_keyword("sort") && !parser.peek_keyword("limit")
+        {
+            query = query.filter(parser.expression()?);
+        }
+        if parser.peek_keyword("sort") {
+            parser.position += 1;
+            loop {
+                let key = parser.sort_key()?;
+                let descending = parser.peek_keyword("desc");
+                if descending || parser.peek_keyword("asc") {
+                    parser.position += 1;
+                }
+                query = query.sort_by(key, descending);
+                if parser.peek() != Some(",") {
+                    break;
+                }
+                parser.position += 1;
+            }
+        }
+        if parser.peek_keyword("limit") {
+            parser.position += 1;
+            query = query.limit(parser.number()?);
+        }
+        match parser.peek() {
+            Some(token) => Err(ShopError::InvalidQuery(format!("unexpected '{}'", token))),
+            None => Ok(query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{location, priced_item, shop_with};
+    use crate::ExpirableItem;
+
+    fn shop() -> GroceryShop<ExpirableItem> {
+        shop_with(vec![
+            (priced_item("Leite", 3, Money::eur(90)), location(1, 0, 0)),
+            (priced_item("Pão", 10, Money::eur(120)), location(1, 0, 1)),
+            (priced_item("Queijo", 2, Money::eur(450)), location(1, 1, 0)),
+            (
+                priced_item("Leite Magro", 4, Money::eur(110)),
+                location(2, 0, 0),
+            ),
+        ])
+    }
+
+    fn names<'a>(
+        shop: &GroceryShop<ExpirableItem>,
+        results: impl Iterator<Item = (Location, &'a ExpirableItem)>,
+    ) -> Vec<String> {
+        results
+            .map(|(_, item)| shop.name_of(item).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_query_builder() {
+        let shop = shop();
+
+        let query = Query::new()
+            .filter(Filter::Price(Comparison::Lt, Money::eur(200)))
+            .filter(Filter::Row(Comparison::Eq, 1))
+            .filter(Filter::Quantity(Comparison::Lt, 5));
+        assert_eq!(names(&shop, shop.query(&query)), vec!["Leite"]);
+
+        let query = Query::new()
+            .filter(
+                Filter::NameContains("leite".to_string())
+                    .or(Filter::Name("pao".to_string()))
+                    .and(Filter::Rack(Comparison::Eq, 1).not()),
+            )
+            .sort_by(SortKey::Price, true)
+            .limit(2);
+        assert_eq!(names(&shop, shop.query(&query)), vec!["Pão", "Leite Magro"]);
+    }
+
+    #[test]
+    fn test_parse_text_queries() {
+        let shop = shop();
+
+        let query: Query = "price < 2.00 and row = 1 and quantity < 5".parse().unwrap();
+        assert_eq!(names(&shop, shop.query(&query)), vec!["Leite"]);
+
+        let query: Query = r#"name ~ "leite m" or not (row in 1..1) sort quantity desc"#
+            .parse()
+            .unwrap();
+        assert_eq!(names(&shop, shop.query(&query)), vec!["Leite Magro"]);
+
+        let query: Query = "sort name limit 3".parse().unwrap();
+        assert_eq!(
+            names(&shop, shop.query(&query)),
+            vec!["Leite", "Leite Magro", "Pão"]
+        );
+
+        let query: Query = "row >= 1 and (price > 4 or name = PAO) sort location"
+            .parse()
+            .unwrap();
+        assert_eq!(names(&shop, shop.query(&query)), vec!["Pão", "Queijo"]);
+
+        // Prices with a decimal comma or a currency are quoted
+        let query: Query = r#"price <= "1,10 €" sort price"#.parse().unwrap();
+        assert_eq!(
+            names(&shop, shop.query(&query)),
+            vec!["Leite", "Leite Magro"]
+        );
+        let query: Query = r#"price < "9 USD""#.parse().unwrap();
+        assert_eq!(shop.query(&query).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_queries() {
+        for text in [
+            "price <",
+            "price < cheap",
+            "colour = red",
+            "name < milk",
+            "row in 1-2",
+            "(quantity > 1",
+            "quantity > 1 sort weight",
+            "name = \"milk",
+            "quantity > 1 limit 2 extra",
+        ] {
+            assert!(
+                matches!(text.parse::<Query>(), Err(ShopError::InvalidQuery(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_limits_nesting() {
+        let nested =
+            |depth: usize| format!("{}quantity > 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(nested(MAX_QUERY_DEPTH).parse::<Query>().is_ok());
+        assert!(matches!(
+            nested(MAX_QUERY_DEPTH + 1).parse::<Query>(),
+            Err(ShopError::InvalidQuery(_))
+        ));
+        assert!(matches!(
+            "(".repeat(100_000).parse::<Query>(),
+            Err(ShopError::InvalidQuery(_))
+        ));
+        assert!(matches!(
+            "not ".repeat(100_000).parse::<Query>(),
+            Err(ShopError::InvalidQuery(_))
+        ));
+    }
+}
@@ -0,0 +1,322 @@
+// Reorder points, low-stock reports and purchase suggestions.
+//
+// A ReorderPolicy says, per product (its SKU, or the name of a loose article),
+// at how many units in stock it should be reordered and how many units an
+// order should bring it back up to. Stock is always the sum over every lot of
+// the product, found through the name index. Purchase suggestions leave out
+// the units already on order with suppliers.
+//
+// Subscribers registered with `subscribe_low_stock` are told when `consume`,
+// `pick`, `adjust` or `remove_item` takes a product from above its reorder
+// point to at or below it. Inside a transaction the alerts are held back and
+// only sent once it commits.
+
+use crate::{GroceryShop, Item, ProductKey, ShopError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Reorder a product once its stock falls to `reorder_point` units, up to `target_level`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReorderPolicy {
+    pub reorder_point: u32,
+    pub target_level: u32,
+}
+
+/// A product at or below its reorder point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LowStock {
+    pub product: ProductKey,
+    pub name: String,
+    pub on_hand: u64,
+    /// Units outstanding on open purchase orders.
+    pub on_order: u64,
+    pub policy: ReorderPolicy,
+}
+
+impl LowStock {
+    pub fn to_order(&self) -> u64 {
+        // Units still needed to reach the target level once the open orders arrive
+        u64::from(self.policy.target_level).saturating_sub(self.on_hand + self.on_order)
+    }
+}
+
+/// Order `quantity` units of `product`, named `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurchaseSuggestion {
+    pub product: ProductKey,
+    pub name: String,
+    pub quantity: u64,
+}
+
+/// Sent to subscribers when a product's stock crosses its reorder point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LowStockAlert {
+    pub product: ProductKey,
+    pub name: String,
+    pub on_hand: u64,
+    pub reorder_point: u32,
+}
+
+/// A callback told about low-stock alerts. Like the shop's observers it is
+/// `Send + Sync`, so the shop can be shared with other threads.
+pub type LowStockSubscriber = Arc<dyn Fn(&LowStockAlert) + Send + Sync>;
+
+/// The low-stock subscribers of a shop. Clones share the subscribers.
+#[derive(Clone, Default)]
+pub struct AlertSubscribers {
+    subscribers: Vec<LowStockSubscriber>,
+    deferred: Option<Vec<LowStockAlert>>,
+}
+
+impl AlertSubscribers {
+    pub(crate) fn notify(&mut self, alert: LowStockAlert) {
+        // Send the alert now, or keep it for later while deferring
+        match &mut self.deferred {
+            Some(deferred) => deferred.push(alert),
+            None => {
+                for subscriber in &self.subscribers {
+                    subscriber(&alert);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn defer(&mut self) {
+        self.deferred = Some(Vec::new());
+    }
+
+    pub(crate) fn take_deferred(&mut self) -> Vec<LowStockAlert> {
+        self.deferred.take().unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for AlertSubscribers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlertSubscribers")
+            .field("subscribers", &self.subscribers.len())
+            .field("deferred", &self.deferred)
+            .finish()
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn set_reorder_policy(
+        &mut self,
+        product: ProductKey,
+        reorder_point: u32,
+        target_level: u32,
+    ) -> Result<(), ShopError> {
+        // Reorder `product` at `reorder_point` units, up to `target_level` units
+        if target_level <= reorder_point {
+            return Err(ShopError::InvalidReorderPolicy {
+                reorder_point,
+                target_level,
+            });
+        }
+        self.check_product(&product)?;
+        self.reorder_policies.insert(
+            product,
+            ReorderPolicy {
+                reorder_point,
+                target_level,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove_reorder_policy(&mut self, product: &ProductKey) -> Option<ReorderPolicy> {
+        self.reorder_policies.remove(product)
+    }
+
+    pub fn subscribe_low_stock<F>(&mut self, subscriber: F)
+    where
+        F: Fn(&LowStockAlert) + Send + Sync + 'static,
+    {
+        self.low_stock_subscribers
+            .subscribers
+            .push(Arc::new(subscriber));
+    }
+
+    pub fn stock_of(&self, name: &str) -> u64 {
+        // Units of a product over all the lots with that name
+        self.get_items_by_name(name)
+            .iter()
+            .map(|item| u64::from(item.quantity()))
+            .sum()
+    }
+
+    pub fn low_stock(&self) -> Vec<LowStock> {
+        // Products with a policy whose stock is at or below the reorder point,
+        // by SKU and then loose articles by name
+        self.reorder_policies
+            .iter()
+            .map(|(product, policy)| LowStock {
+                product: product.clone(),
+                name: self.product_name(product).to_string(),
+                on_hand: self.units_of(product),
+                on_order: self.on_order(product),
+                policy: *policy,
+            })
+            .filter(|low| low.on_hand <= u64::from(low.policy.reorder_point))
+            .collect()
+    }
+
+    pub fn purchase_suggestions(&self) -> Vec<PurchaseSuggestion> {
+        // Enough units of every low product to bring it back to its target
+        // level, counting what is already on order
+        self.low_stock()
+            .into_iter()
+            .filter(|low| low.to_order() > 0)
+            .map(|low| PurchaseSuggestion {
+                quantity: low.to_order(),
+                product: low.product,
+                name: low.name,
+            })
+            .collect()
+    }
+
+    pub(crate) fn check_reorder_point(&mut self, product: &ProductKey, stock_before: u64) {
+        // Alert the subscribers if the product just went down to its reorder point
+        let Some(policy) = self.reorder_policies.get(product) else {
+            return;
+        };
+        let reorder_point = u64::from(policy.reorder_point);
+        let on_hand = self.units_of(product);
+        if stock_before > reorder_point && on_hand <= reorder_point {
+            let alert = LowStockAlert {
+                product: product.clone(),
+                name: self.product_name(product).to_string(),
+                on_hand,
+                reorder_point: policy.reorder_point,
+            };
+            self.low_stock_subscribers.notify(alert);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::ExpirableItem;
+    use std::sync::Mutex;
+
+    fn shop() -> (GroceryShop<ExpirableItem>, ExpirableItem, ExpirableItem) {
+        // Two lots of milk, which is reordered below 5 units, and some bread
+        let (first, second) = (item("Leite", 6), item("Leite", 4));
+        let mut shop = shop_with(vec![
+            (first.clone(), location(0, 0, 0)),
+            (second.clone(), location(1, 0, 0)),
+            (item("Pão", 2), location(2, 0, 0)),
+        ]);
+        shop.set_reorder_policy(ProductKey::loose("Leite"), 5, 20)
+            .unwrap();
+        (shop, first, second)
+    }
+
+    fn recorder(shop: &mut GroceryShop<ExpirableItem>) -> Arc<Mutex<Vec<LowStockAlert>>> {
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&alerts);
+        shop.subscribe_low_stock(move |alert| sink.lock().unwrap().push(alert.clone()));
+        alerts
+    }
+
+    #[test]
+    fn test_low_stock_and_suggestions() {
+        let (mut shop, first, _) = shop();
+
+        assert_eq!(shop.stock_of("Leite"), 10);
+        assert!(shop.low_stock().is_empty());
+        assert_eq!(
+            shop.set_reorder_policy(ProductKey::loose("Pão"), 3, 3),
+            Err(ShopError::InvalidReorderPolicy {
+                reorder_point: 3,
+                target_level: 3
+            })
+        );
+        shop.set_reorder_policy(ProductKey::loose("Pão"), 3, 12)
+            .unwrap();
+        shop.set_reorder_policy(ProductKey::loose("Ovos"), 0, 24)
+            .unwrap();
+        assert_eq!(
+            shop.set_reorder_policy(ProductKey::sku("NAT-002"), 1, 5),
+            Err(ShopError::UnknownSku(crate::Sku::new("NAT-002")))
+        );
+        shop.consume(&location(0, 0, 0), &first.uuid, 5).unwrap();
+
+        let low: Vec<(String, u64)> = shop
+            .low_stock()
+            .into_iter()
+            .map(|low| (low.name, low.on_hand))
+            .collect();
+        assert_eq!(
+            low,
+            vec![
+                ("Leite".to_string(), 5),
+                ("Ovos".to_string(), 0),
+                ("Pão".to_string(), 2)
+            ]
+        );
+        assert_eq!(
+            shop.purchase_suggestions()[0],
+            PurchaseSuggestion {
+                product: ProductKey::loose("Leite"),
+                name: "Leite".to_string(),
+                quantity: 15
+            }
+        );
+        assert_eq!(shop.purchase_suggestions()[2].quantity, 10);
+    }
+
+    #[test]
+    fn test_subscribers_are_told_when_stock_crosses() {
+        let (mut shop, first, second) = shop();
+        let alerts = recorder(&mut shop);
+
+        shop.consume(&location(0, 0, 0), &first.uuid, 4).unwrap();
+        assert!(alerts.lock().unwrap().is_empty());
+        // 6 units left, removing a lot of 4 goes down to 2
+        shop.remove_item(&location(1, 0, 0), &second.uuid).unwrap();
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec![LowStockAlert {
+                product: ProductKey::loose("Leite"),
+                name: "Leite".to_string(),
+                on_hand: 2,
+                reorder_point: 5,
+            }]
+        );
+        // Already below the reorder point: no new alert
+        shop.consume(&location(0, 0, 0), &first.uuid, 1).unwrap();
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+        // Moving a lot does not change the stock
+        shop.restock(&location(0, 0, 0), &first.uuid, 10).unwrap();
+        shop.move_item(location(0, 0, 0), &first.uuid, &location(0, 1, 0))
+            .unwrap();
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_alerts_wait_for_the_transaction_to_commit() {
+        let (mut shop, first, second) = shop();
+        let alerts = recorder(&mut shop);
+
+        let result: Result<(), ShopError> = shop.transaction(|tx| {
+            tx.consume(&location(0, 0, 0), &first.uuid, 6)?;
+            tx.consume(&location(1, 0, 0), &second.uuid, 5)?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(alerts.lock().unwrap().is_empty());
+
+        shop.transaction(|tx| {
+            tx.consume(&location(0, 0, 0), &first.uuid, 6)?;
+            assert!(alerts.lock().unwrap().is_empty());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(alerts.lock().unwrap().len(), 1);
+        assert_eq!(alerts.lock().unwrap()[0].on_hand, 4);
+    }
+}
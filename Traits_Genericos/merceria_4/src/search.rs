@@ -0,0 +1,173 @@
+// Forgiving product search over the name index.
+//
+// Names and queries are compared after folding case and stripping the accents
+// used in Portuguese ("Pão" matches "pao"). A name matches when it is equal to
+// the query, when it or one of its words starts with the query, or when it or
+// one of its words is within `max_distance` edits of the query.
+
+use crate::{GroceryShop, Item, Location};
+
+/// How a search result matched the query, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    /// Matched with `distance` single-character edits.
+    Fuzzy {
+        distance: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit<'a, T: Item> {
+    pub location: Location,
+    pub item: &'a T,
+    pub kind: MatchKind,
+}
+
+pub fn normalize(text: &str) -> String {
+    // Lowercase the text and replace accented letters by their base letter
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            c => c,
+        })
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    // Levenshtein distance between two strings, counted in chars
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn match_name(name: &str, query: &str, max_distance: usize) -> Option<MatchKind> {
+    // Best way a normalized name matches a normalized query
+    if name == query {
+        return Some(MatchKind::Exact);
+    }
+    let words = || {
+        name.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+    };
+    if name.starts_with(query) || words().any(|word| word.starts_with(query)) {
+        return Some(MatchKind::Prefix);
+    }
+    let distance = words()
+        .map(|word| edit_distance(word, query))
+        .chain(std::iter::once(edit_distance(name, query)))
+        .min()?;
+    (distance <= max_distance).then_some(MatchKind::Fuzzy { distance })
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn search(&self, query: &str, max_distance: usize) -> Vec<SearchHit<'_, T>> {
+        // Every lot whose name matches the query, best matches first
+        let query = normalize(query.trim());
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut hits = Vec::new();
+        for (name, lots) in &self.name_index {
+            let Some(kind) = match_name(&normalize(name), &query, max_distance) else {
+                continue;
+            };
+            for lot in lots {
+                if let Some(item) = self.get_item(&lot.location, &lot.uuid) {
+                    hits.push(SearchHit {
+                        location: lot.location.clone(),
+                        item,
+                        kind,
+                    });
+                }
+            }
+        }
+        hits.sort_by(|a, b| {
+            a.kind
+                .cmp(&b.kind)
+                .then_with(|| self.name_of(a.item).cmp(self.name_of(b.item)))
+                .then_with(|| a.location.cmp(&b.location))
+                .then_with(|| a.item.uuid().cmp(&b.item.uuid()))
+        });
+        hits
+    }
+
+    pub fn search_prefix(&self, prefix: &str) -> Vec<SearchHit<'_, T>> {
+        // Lots whose name, or a word of it, starts with the prefix. With no
+        // edits allowed every fuzzy match is already a prefix match.
+        self.search(prefix, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::ExpirableItem;
+
+    fn shop() -> GroceryShop<ExpirableItem> {
+        shop_with(vec![
+            (item("Leite", 1), location(0, 0, 0)),
+            (item("Leite Magro", 1), location(0, 0, 1)),
+            (item("Pão de Forma", 1), location(1, 0, 0)),
+            (item("Açúcar", 1), location(2, 1, 1)),
+        ])
+    }
+
+    #[test]
+    fn test_normalize_folds_case_and_accents() {
+        assert_eq!(normalize("Pão de Forma"), "pao de forma");
+        assert_eq!(normalize("AÇÚCAR"), "acucar");
+        assert_eq!(edit_distance("leite", "lete"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_search_ranks_exact_prefix_then_fuzzy() {
+        let shop = shop();
+
+        let hits = shop.search("leite", 1);
+        let names: Vec<&str> = hits.iter().map(|hit| shop.name_of(hit.item)).collect();
+        assert_eq!(names, vec!["Leite", "Leite Magro"]);
+        assert_eq!(hits[0].kind, MatchKind::Exact);
+        assert_eq!(hits[1].kind, MatchKind::Prefix);
+        assert_eq!(hits[1].location, location(0, 0, 1));
+
+        // Accents, case and a typo
+        assert_eq!(shop.search("acucar", 0)[0].location, location(2, 1, 1));
+        let hits = shop.search("pao de froma", 2);
+        assert_eq!(hits[0].kind, MatchKind::Fuzzy { distance: 2 });
+        assert!(shop.search("pao de froma", 1).is_empty());
+    }
+
+    #[test]
+    fn test_search_prefix() {
+        let shop = shop();
+
+        assert_eq!(shop.search_prefix("Lei").len(), 2);
+        assert_eq!(
+            shop.name_of(shop.search_prefix("mag")[0].item),
+            "Leite Magro"
+        );
+        assert_eq!(shop.search_prefix("forma")[0].location, location(1, 0, 0));
+        assert!(shop.search_prefix("Lte").is_empty());
+        assert!(shop.search_prefix("").is_empty());
+    }
+}
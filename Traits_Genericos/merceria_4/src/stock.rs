@@ -0,0 +1,280 @@
+// Quantity changes (restock, consume, adjust) and the ledger that records them.
+// Merging a lot into an existing one and editing a quantity through
+// `get_item_mut` are recorded too.
+
+use crate::{GroceryShop, Item, Location, ShopError, ShopEvent};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovementReason {
+    Restock,
+    Consumption,
+    Pick,
+    Adjustment(String),
+    /// Sold in the sale with that id.
+    Sale(Uuid),
+    /// Put back by voiding the sale with that id.
+    Void(Uuid),
+    /// The lot with that uuid was added to the zone and merged into this one.
+    Merge(Uuid),
+    /// Set directly through `get_item_mut`.
+    Edit,
+    /// Reconciled by the count session with that id, for the reason `code`.
+    Count {
+        session_id: Uuid,
+        code: String,
+    },
+}
+
+/// A single quantity change of the lot `uuid` stored at `location`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StockMovement {
+    pub timestamp: DateTime<Utc>,
+    pub uuid: Uuid,
+    pub location: Location,
+    pub delta: i64,
+    pub quantity_after: u32,
+    pub reason: MovementReason,
+}
+
+/// Append-only, in-memory list of stock movements.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    movements: Vec<StockMovement>,
+    deferred: Option<Vec<StockMovement>>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, movement: StockMovement) {
+        // Append the movement now, or keep it for later while deferring
+        match &mut self.deferred {
+            Some(deferred) => deferred.push(movement),
+            None => self.movements.push(movement),
+        }
+    }
+
+    pub(crate) fn defer(&mut self) {
+        self.deferred = Some(Vec::new());
+    }
+
+    pub(crate) fn take_deferred(&mut self) -> Vec<StockMovement> {
+        self.deferred.take().unwrap_or_default()
+    }
+
+    pub fn movements(&self) -> &[StockMovement] {
+        &self.movements
+    }
+
+    pub fn for_item(&self, uuid: &Uuid) -> Vec<&StockMovement> {
+        // Movements of a single lot, oldest first
+        self.movements.iter().filter(|m| &m.uuid == uuid).collect()
+    }
+
+    pub fn at_location(&self, location: &Location) -> Vec<&StockMovement> {
+        // Movements that happened at a location, oldest first
+        self.movements
+            .iter()
+            .filter(|m| &m.location == location)
+            .collect()
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn restock(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        quantity: u32,
+    ) -> Result<u32, ShopError> {
+        // Add units to the lot at the location, returning the new quantity
+        self.adjust(location, uuid, i64::from(quantity), MovementReason::Restock)
+    }
+
+    pub fn consume(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        quantity: u32,
+    ) -> Result<u32, ShopError> {
+        // Take units from the lot at the location, returning the new quantity
+        self.adjust(
+            location,
+            uuid,
+            -i64::from(quantity),
+            MovementReason::Consumption,
+        )
+    }
+
+    pub fn adjust(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        delta: i64,
+        reason: MovementReason,
+    ) -> Result<u32, ShopError> {
+        // Change the quantity of the lot at the location and record it in the ledger
+        let zone = self
+            .get_zone(location)
+            .ok_or_else(|| ShopError::LocationNotFound(location.clone()))?;
+        let index = zone
+            .lots
+            .iter()
+            .position(|lot| lot.uuid() == *uuid)
+            .ok_or_else(|| ShopError::ItemNotFound {
+                location: location.clone(),
+                uuid: *uuid,
+            })?;
+        let item = &zone.lots[index];
+
+        let product = item.article().key();
+        let stock_before = self.units_of(&product);
+        let new_quantity = i64::from(item.quantity())
+            .checked_add(delta)
+            .ok_or_else(|| ShopError::QuantityOverflow(location.clone()))?;
+        if new_quantity < 0 {
+            return Err(ShopError::QuantityUnderflow {
+                location: location.clone(),
+                available: item.quantity(),
+                requested: delta.unsigned_abs(),
+            });
+        }
+        let new_quantity = u32::try_from(new_quantity)
+            .map_err(|_| ShopError::QuantityOverflow(location.clone()))?;
+        if delta > 0 {
+            self.check_capacity(
+                location,
+                item,
+                zone.units() + delta.unsigned_abs(),
+                delta.unsigned_abs(),
+            )?;
+        }
+
+        let lot = &mut self.get_zone_mut(location).unwrap().lots[index];
+        let before = lot.clone();
+        lot.set_quantity(new_quantity);
+        let after = lot.clone();
+        self.observers.notify(ShopEvent::Edited {
+            before,
+            after,
+            location: location.clone(),
+        });
+
+        self.ledger.record(StockMovement {
+            timestamp: Utc::now(),
+            uuid: *uuid,
+            location: location.clone(),
+            delta,
+            quantity_after: new_quantity,
+            reason,
+        });
+        if delta < 0 {
+            self.check_reorder_point(&product, stock_before);
+        }
+        Ok(new_quantity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+    use crate::{AddMode, ExpirableItem};
+
+    #[test]
+    fn test_restock_consume_and_ledger() {
+        let item = item("Milk", 5);
+        let location = location(1, 1, 1);
+        let mut shop = shop_with(vec![(item.clone(), location.clone())]);
+
+        assert_eq!(shop.restock(&location, &item.uuid, 10), Ok(15));
+        assert_eq!(shop.consume(&location, &item.uuid, 4), Ok(11));
+        assert_eq!(
+            shop.adjust(
+                &location,
+                &item.uuid,
+                -1,
+                MovementReason::Adjustment("Broken bottle".to_string())
+            ),
+            Ok(10)
+        );
+        assert_eq!(shop.get_item(&location, &item.uuid).unwrap().quantity, 10);
+
+        let movements = shop.ledger.for_item(&item.uuid);
+        let deltas: Vec<i64> = movements.iter().map(|m| m.delta).collect();
+        assert_eq!(deltas, vec![10, -4, -1]);
+        assert_eq!(movements[2].quantity_after, 10);
+        assert_eq!(shop.ledger.at_location(&location).len(), 3);
+    }
+
+    #[test]
+    fn test_consume_refuses_underflow() {
+        let item = item("Milk", 2);
+        let location = location(0, 0, 0);
+        let uuid = item.uuid;
+        let mut shop = shop_with(vec![(item, location.clone())]);
+
+        assert_eq!(
+            shop.consume(&location, &uuid, 3),
+            Err(ShopError::QuantityUnderflow {
+                location: location.clone(),
+                available: 2,
+                requested: 3,
+            })
+        );
+        assert_eq!(shop.get_item(&location, &uuid).unwrap().quantity, 2);
+        assert!(shop.ledger.movements().is_empty());
+    }
+
+    #[test]
+    fn test_adjust_refuses_overflow() {
+        let item = item("Milk", 2);
+        let location = location(0, 0, 0);
+        let mut shop = shop_with(vec![(item.clone(), location.clone())]);
+
+        for delta in [i64::MAX, i64::from(u32::MAX) - 1] {
+            assert_eq!(
+                shop.adjust(&location, &item.uuid, delta, MovementReason::Restock),
+                Err(ShopError::QuantityOverflow(location.clone()))
+            );
+        }
+        assert_eq!(shop.get_item(&location, &item.uuid).unwrap().quantity, 2);
+        assert!(shop.ledger.movements().is_empty());
+    }
+
+    #[test]
+    fn test_merges_and_edits_are_recorded() {
+        let milk = item("Milk", 2);
+        let more = ExpirableItem {
+            quantity: 3,
+            uuid: Uuid::new_v4(),
+            ..milk.clone()
+        };
+        let location = location(0, 0, 0);
+        let mut shop = shop_with(vec![(milk.clone(), location.clone())]);
+        shop.add_item_with_mode(more.clone(), &location, AddMode::Merge)
+            .unwrap();
+        if let Some(mut lot) = shop.get_item_mut(&location, &milk.uuid) {
+            lot.quantity = 4;
+            lot.commit().unwrap();
+        }
+
+        let movements: Vec<(i64, u32, MovementReason)> = shop
+            .ledger
+            .for_item(&milk.uuid)
+            .iter()
+            .map(|m| (m.delta, m.quantity_after, m.reason.clone()))
+            .collect();
+        assert_eq!(
+            movements,
+            vec![
+                (3, 5, MovementReason::Merge(more.uuid)),
+                (-1, 4, MovementReason::Edit),
+            ]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// Factories shared by the unit tests of every module.
+//
+// These are plain functions rather than methods on ExpirableItem, so no
+// production type grows test-only behaviour. `item` builds a plain lot and a
+// new field on ExpirableItem only has to be added there; a test that cares
+// about another field sets it with struct update syntax.
+
+use crate::{Article, ExpirableItem, GroceryShop, Location, Money};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+pub fn location(row_id: u32, rack_id: u32, zone_id: u32) -> Location {
+    Location {
+        row_id,
+        rack_id,
+        zone_id,
+    }
+}
+
+/// The time `days` days from now, in the past for a negative count.
+pub fn in_days(days: i64) -> DateTime<Utc> {
+    Utc::now() + Duration::days(days)
+}
+
+/// A lot of `quantity` units of `name` at 1,00 € that expires in 30 days.
+pub fn item(name: &str, quantity: u32) -> ExpirableItem {
+    ExpirableItem {
+        article: Article::loose(name, Money::eur(100)),
+        quantity,
+        uuid: Uuid::new_v4(),
+        expiration_date: in_days(30),
+        dimensions: None,
+        gtin: None,
+    }
+}
+
+/// A lot of `quantity` units of `name` at `price` that expires in 30 days.
+pub fn priced_item(name: &str, quantity: u32, price: Money) -> ExpirableItem {
+    ExpirableItem {
+        article: Article::loose(name, price),
+        ..item(name, quantity)
+    }
+}
+
+/// A lot of `quantity` units of `name` at 1,00 € that expires in `days` days.
+pub fn expiring_item(name: &str, quantity: u32, days: i64) -> ExpirableItem {
+    ExpirableItem {
+        expiration_date: in_days(days),
+        ..item(name, quantity)
+    }
+}
+
+/// A lot of `quantity` units of the catalogue product `sku`, expiring in 30 days.
+pub fn product_lot(sku: &str, quantity: u32) -> ExpirableItem {
+    ExpirableItem {
+        article: Article::product(sku),
+        ..item("", quantity)
+    }
+}
+
+/// The default layout of `GroceryShop::initialize` holding `lots`.
+pub fn shop_with(lots: Vec<(ExpirableItem, Location)>) -> GroceryShop<ExpirableItem> {
+    let mut shop = GroceryShop::new();
+    shop.initialize();
+    for (lot, location) in lots {
+        shop.add_item(lot, &location).unwrap();
+    }
+    shop
+}
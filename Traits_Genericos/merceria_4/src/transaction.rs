@@ -0,0 +1,250 @@
+// All-or-nothing batches of shop operations.
+//
+// A transaction applies its operations to the shop itself, after copying the
+// hierarchy and the indexes, the only state they rewrite in place. When the
+// closure returns Err the copies are put back and the price book undoes the
+// changes it saved for the lots the transaction touched. History is only
+// appended on commit: stock movements, observer events and low-stock alerts
+// raised while the closure runs are held back until it returns Ok, and
+// dropped otherwise.
+
+use crate::expiry::PickLine;
+use crate::{AddMode, GroceryShop, Item, Location, Money, MovementReason, ShopError};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+pub struct Transaction<'a, T: Item> {
+    staged: &'a mut GroceryShop<T>,
+}
+
+impl<T: Item> Transaction<'_, T> {
+    pub fn shop(&self) -> &GroceryShop<T> {
+        // Read access to the shop as it looks with the staged operations applied
+        self.staged
+    }
+
+    pub fn add_item(&mut self, item: T, location: &Location) -> Result<(), ShopError> {
+        self.staged.add_item(item, location)
+    }
+
+    pub fn add_item_with_mode(
+        &mut self,
+        item: T,
+        location: &Location,
+        mode: AddMode,
+    ) -> Result<(), ShopError> {
+        self.staged.add_item_with_mode(item, location, mode)
+    }
+
+    pub fn remove_item(&mut self, location: &Location, uuid: &Uuid) -> Result<T, ShopError> {
+        self.staged.remove_item(location, uuid)
+    }
+
+    pub fn move_item(
+        &mut self,
+        from_location: Location,
+        uuid: &Uuid,
+        to_location: &Location,
+    ) -> Result<(), ShopError> {
+        self.staged.move_item(from_location, uuid, to_location)
+    }
+
+    pub fn restock(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        quantity: u32,
+    ) -> Result<u32, ShopError> {
+        self.staged.restock(location, uuid, quantity)
+    }
+
+    pub fn consume(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        quantity: u32,
+    ) -> Result<u32, ShopError> {
+        self.staged.consume(location, uuid, quantity)
+    }
+
+    pub fn adjust(
+        &mut self,
+        location: &Location,
+        uuid: &Uuid,
+        delta: i64,
+        reason: MovementReason,
+    ) -> Result<u32, ShopError> {
+        self.staged.adjust(location, uuid, delta, reason)
+    }
+
+    pub fn set_price(&mut self, uuid: &Uuid, price: Money, reason: &str) -> Result<(), ShopError> {
+        self.staged.set_price(uuid, price, reason)
+    }
+
+    pub fn schedule_price(
+        &mut self,
+        uuid: &Uuid,
+        price: Money,
+        from: DateTime<Utc>,
+        reason: &str,
+    ) -> Result<(), ShopError> {
+        self.staged.schedule_price(uuid, price, from, reason)
+    }
+
+    pub fn add_promotion(
+        &mut self,
+        uuid: &Uuid,
+        price: Money,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+        reason: &str,
+    ) -> Result<(), ShopError> {
+        self.staged.add_promotion(uuid, price, from, until, reason)
+    }
+
+    pub fn pick(&mut self, name: &str, quantity: u32) -> Result<Vec<PickLine>, ShopError> {
+        self.staged.pick(name, quantity)
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    pub fn transaction<R, F>(&mut self, operations: F) -> Result<R, ShopError>
+    where
+        F: FnOnce(&mut Transaction<'_, T>) -> Result<R, ShopError>,
+    {
+        // Apply every staged operation, or none of them if one fails
+        let rows = self.rows.clone();
+        let name_index = self.name_index.clone();
+        let uuid_index = self.uuid_index.clone();
+        let barcode_index = self.barcode_index.clone();
+        self.observers.defer();
+        self.low_stock_subscribers.defer();
+        self.ledger.defer();
+        self.prices.begin();
+        let result = operations(&mut Transaction { staged: self });
+
+        let events = self.observers.take_deferred();
+        let alerts = self.low_stock_subscribers.take_deferred();
+        let movements = self.ledger.take_deferred();
+        let result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.rows = rows;
+                self.name_index = name_index;
+                self.uuid_index = uuid_index;
+                self.barcode_index = barcode_index;
+                self.prices.roll_back();
+                return Err(err);
+            }
+        };
+
+        self.prices.commit();
+        for movement in movements {
+            self.ledger.record(movement);
+        }
+        for event in events {
+            self.observers.notify(event);
+        }
+        for alert in alerts {
+            self.low_stock_subscribers.notify(alert);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{item, location, shop_with};
+
+    #[test]
+    fn test_transaction_applies_everything() {
+        let milk = item("Milk", 5);
+        let bread = item("Bread", 5);
+        let mut shop = shop_with(vec![
+            (milk.clone(), location(0, 0, 0)),
+            (bread.clone(), location(0, 0, 1)),
+        ]);
+
+        // Move the whole rack 0 of row 0 to row 2
+        shop.transaction(|tx| {
+            tx.move_item(location(0, 0, 0), &milk.uuid, &location(2, 0, 0))?;
+            tx.move_item(location(0, 0, 1), &bread.uuid, &location(2, 0, 1))?;
+            tx.restock(&location(2, 0, 1), &bread.uuid, 3)?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(shop.get_items(&location(0, 0, 0)).is_empty());
+        assert_eq!(shop.get_items(&location(2, 0, 0)), vec![milk.clone()]);
+        assert_eq!(
+            shop.get_item(&location(2, 0, 1), &bread.uuid)
+                .unwrap()
+                .quantity,
+            8
+        );
+        assert_eq!(
+            shop.get_location_by_uuid(&bread.uuid),
+            Some(&location(2, 0, 1))
+        );
+        assert_eq!(shop.ledger.movements().len(), 1);
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_on_error() {
+        let milk = item("Milk", 5);
+        let mut shop = shop_with(vec![
+            (milk.clone(), location(0, 0, 0)),
+            (item("Bread", 5), location(1, 0, 0)),
+        ]);
+
+        let result = shop.transaction(|tx| {
+            tx.restock(&location(0, 0, 0), &milk.uuid, 10)?;
+            tx.move_item(location(0, 0, 0), &milk.uuid, &location(0, 1, 0))?;
+            // Row 1 rack 0 zone 0 is taken by the bread
+            tx.move_item(location(0, 1, 0), &milk.uuid, &location(1, 0, 0))?;
+            Ok(())
+        });
+
+        assert_eq!(
+            result,
+            Err(ShopError::ZoneFull {
+                location: location(1, 0, 0),
+                max_lots: 1,
+            })
+        );
+        assert_eq!(shop.get_items(&location(0, 0, 0)), vec![milk.clone()]);
+        assert!(shop.get_items(&location(0, 1, 0)).is_empty());
+        assert_eq!(
+            shop.get_location_by_uuid(&milk.uuid),
+            Some(&location(0, 0, 0))
+        );
+        assert!(shop.ledger.movements().is_empty());
+        assert_eq!(shop.verify_indexes(), Ok(()));
+    }
+
+    #[test]
+    fn test_rollback_undoes_price_changes() {
+        let milk = item("Milk", 5);
+        let mut shop = shop_with(vec![(milk.clone(), location(0, 0, 0))]);
+        shop.set_price(&milk.uuid, Money::eur(120), "supplier")
+            .unwrap();
+        let history = shop.price_history(&milk.uuid).to_vec();
+
+        let result: Result<(), ShopError> = shop.transaction(|tx| {
+            tx.set_price(&milk.uuid, Money::eur(150), "supplier")?;
+            tx.consume(&location(0, 0, 0), &milk.uuid, 2)?;
+            // The staged price is visible inside the transaction
+            assert_eq!(tx.shop().current_price(&milk.uuid), Some(Money::eur(150)));
+            tx.consume(&location(0, 0, 0), &milk.uuid, 9)?;
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(shop.price_history(&milk.uuid), history.as_slice());
+        assert_eq!(shop.current_price(&milk.uuid), Some(Money::eur(120)));
+        assert_eq!(shop.get_items(&location(0, 0, 0))[0].quantity, 5);
+        assert!(shop.ledger.movements().is_empty());
+    }
+}
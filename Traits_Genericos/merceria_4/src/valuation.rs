@@ -0,0 +1,318 @@
+// Stock valuation reports.
+//
+// Values are Money amounts: each lot is worth its exact current unit price
+// (promotions and due price changes included) times its quantity, and totals
+// are added with checked arithmetic. A report is in a single currency, that
+// of the first lot it values; a lot priced in another currency makes it fail
+// with `ShopError::Money`. Reports render as CSV or as a plain-text table.
+
+use crate::{Currency, GroceryShop, Item, Location, Money, ShopError};
+use chrono::{Duration, Utc};
+use std::collections::BTreeMap;
+
+/// One line of a report: `units` units worth `value` in total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValuationLine {
+    pub label: String,
+    pub units: u64,
+    pub value: Money,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValuationReport {
+    pub title: String,
+    pub lines: Vec<ValuationLine>,
+    total: Money,
+}
+
+fn decimal(money: &Money) -> String {
+    // 1234 minor units -> "12.34", for machine-readable output
+    let sign = if money.minor_units() < 0 { "-" } else { "" };
+    let minor_units = money.minor_units().unsigned_abs();
+    format!("{}{}.{:02}", sign, minor_units / 100, minor_units % 100)
+}
+
+fn csv_field(text: &str) -> String {
+    // Quote fields holding separators or quotes
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+impl ValuationReport {
+    fn from_groups(
+        title: &str,
+        currency: Currency,
+        groups: BTreeMap<String, (u64, Money)>,
+    ) -> Result<Self, ShopError> {
+        let lines: Vec<ValuationLine> = groups
+            .into_iter()
+            .map(|(label, (units, value))| ValuationLine {
+                label,
+                units,
+                value,
+            })
+            .collect();
+        Ok(ValuationReport {
+            title: title.to_string(),
+            total: Money::sum(currency, lines.iter().map(|line| line.value))?,
+            lines,
+        })
+    }
+
+    pub fn total_units(&self) -> u64 {
+        self.lines.iter().map(|line| line.units).sum()
+    }
+
+    pub fn total_value(&self) -> Money {
+        self.total
+    }
+
+    pub fn to_csv(&self) -> String {
+        // One line per group plus a final total line
+        let mut csv = String::from("label,units,value,currency\n");
+        for line in &self.lines {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_field(&line.label),
+                line.units,
+                decimal(&line.value),
+                line.value.currency()
+            ));
+        }
+        csv.push_str(&format!(
+            "Total,{},{},{}\n",
+            self.total_units(),
+            decimal(&self.total),
+            self.total.currency()
+        ));
+        csv
+    }
+
+    pub fn to_table(&self) -> String {
+        // Aligned columns with a separator above the total
+        let total = ValuationLine {
+            label: "Total".to_string(),
+            units: self.total_units(),
+            value: self.total,
+        };
+        let rows: Vec<(String, String, String)> = self
+            .lines
+            .iter()
+            .chain(std::iter::once(&total))
+            .map(|line| {
+                (
+                    line.label.clone(),
+                    line.units.to_string(),
+                    line.value.to_string(),
+                )
+            })
+            .collect();
+        let width = |column: fn(&(String, String, String)) -> &String, header: &str| {
+            rows.iter()
+                .map(|row| column(row).chars().count())
+                .chain(std::iter::once(header.len()))
+                .max()
+                .unwrap_or(0)
+        };
+        let label_width = width(|row| &row.0, "Label");
+        let units_width = width(|row| &row.1, "Units");
+        let value_width = width(|row| &row.2, "Value");
+
+        let format_row = |label: &str, units: &str, value: &str| {
+            let padding = label_width.saturating_sub(label.chars().count());
+            format!(
+                "{}{}  {:>units_width$}  {:>value_width$}\n",
+                label,
+                " ".repeat(padding),
+                units,
+                value
+            )
+        };
+        let mut table = format!("{}\n", self.title);
+        table.push_str(&format_row("Label", "Units", "Value"));
+        for (index, (label, units, value)) in rows.iter().enumerate() {
+            if index == rows.len() - 1 {
+                table.push_str(&format!(
+                    "{}\n",
+                    "-".repeat(label_width + units_width + value_width + 4)
+                ));
+            }
+            table.push_str(&format_row(label, units, value));
+        }
+        table
+    }
+}
+
+impl<T: Item> GroceryShop<T> {
+    fn lot_value(&self, item: &T) -> Result<Money, ShopError> {
+        // Current unit price times quantity
+        let price = self.current_price_of(item)?;
+        Ok(price.checked_mul(i64::from(item.quantity()))?)
+    }
+
+    fn report_currency(&self) -> Currency {
+        // Currency of the first priced lot, euros for an empty shop
+        self.iter()
+            .find_map(|(_, item)| self.current_price_of(item).ok())
+            .map_or(Currency::EUR, |price| price.currency())
+    }
+
+    fn group_value<F>(&self, title: &str, mut group: F) -> Result<ValuationReport, ShopError>
+    where
+        F: FnMut(&Location, &T) -> Option<String>,
+    {
+        // Add up units and value of every lot, grouped by the label `group` gives it
+        let currency = self.report_currency();
+        let mut groups: BTreeMap<String, (u64, Money)> = BTreeMap::new();
+        for (location, item) in self.iter() {
+            if let Some(label) = group(&location, item) {
+                let (units, value) = groups.entry(label).or_insert((0, Money::zero(currency)));
+                *units += u64::from(item.quantity());
+                *value = value.checked_add(self.lot_value(item)?)?;
+            }
+        }
+        ValuationReport::from_groups(title, currency, groups)
+    }
+
+    pub fn total_value(&self) -> Result<Money, ShopError> {
+        // Value of all the stock in the shop
+        let values = self
+            .iter()
+            .map(|(_, item)| self.lot_value(item))
+            .collect::<Result<Vec<Money>, ShopError>>()?;
+        Ok(Money::sum(self.report_currency(), values)?)
+    }
+
+    pub fn value_by_row(&self) -> Result<ValuationReport, ShopError> {
+        self.group_value("Stock value by row", |location, _| {
+            Some(format!("row {}", location.row_id))
+        })
+    }
+
+    pub fn value_by_rack(&self) -> Result<ValuationReport, ShopError> {
+        self.group_value("Stock value by rack", |location, _| {
+            Some(format!(
+                "row {} / rack {}",
+                location.row_id, location.rack_id
+            ))
+        })
+    }
+
+    pub fn value_by_name(&self) -> Result<ValuationReport, ShopError> {
+        self.group_value("Stock value by product", |_, item| {
+            Some(self.name_of(item).to_string())
+        })
+    }
+
+    pub fn value_at_risk(&self, days: i64) -> Result<ValuationReport, ShopError> {
+        // Value, by product, of the stock that has expired or will within `days` days
+        let until = Utc::now() + Duration::days(days);
+        self.group_value(
+            &format!("Stock value expiring within {} days", days),
+            |_, item| {
+                item.expiration_date()
+                    .filter(|date| *date <= until)
+                    .map(|_| self.name_of(item).to_string())
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{in_days, location, priced_item, shop_with};
+    use crate::{ExpirableItem, MoneyError};
+
+    fn shop() -> GroceryShop<ExpirableItem> {
+        shop_with(vec![
+            (
+                ExpirableItem {
+                    expiration_date: in_days(2),
+                    ..priced_item("Leite", 3, Money::eur(10))
+                },
+                location(0, 0, 0),
+            ),
+            (priced_item("Leite", 7, Money::eur(20)), location(0, 1, 0)),
+            (
+                ExpirableItem {
+                    expiration_date: in_days(-1),
+                    ..priced_item("Pão, caseiro", 2, Money::eur(115))
+                },
+                location(2, 0, 1),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_values_are_exact() {
+        let shop = shop();
+
+        // 3 x 0.10 + 7 x 0.20 + 2 x 1.15, which used to drift in f32
+        assert_eq!(shop.total_value(), Ok(Money::eur(400)));
+
+        let by_rack = shop.value_by_rack().unwrap();
+        assert_eq!(by_rack.lines.len(), 3);
+        assert_eq!(by_rack.lines[1].label, "row 0 / rack 1");
+        assert_eq!(by_rack.lines[1].value, Money::eur(140));
+
+        let by_row = shop.value_by_row().unwrap();
+        assert_eq!(by_row.lines[0].units, 10);
+        assert_eq!(by_row.total_value(), Money::eur(400));
+
+        let at_risk = shop.value_at_risk(7).unwrap();
+        assert_eq!(at_risk.total_value(), Money::eur(30 + 230));
+        assert_eq!(
+            shop.value_at_risk(60).unwrap().total_value(),
+            Money::eur(400)
+        );
+        assert_eq!(
+            GroceryShop::<ExpirableItem>::new().total_value(),
+            Ok(Money::eur(0))
+        );
+    }
+
+    #[test]
+    fn test_mixed_currencies_are_rejected() {
+        let mut shop = shop();
+        shop.add_item(
+            priced_item("Chá", 1, Money::new(300, Currency::GBP)),
+            &location(1, 0, 0),
+        )
+        .unwrap();
+
+        let mismatch = ShopError::Money(MoneyError::CurrencyMismatch {
+            left: Currency::EUR,
+            right: Currency::GBP,
+        });
+        assert_eq!(shop.total_value(), Err(mismatch.clone()));
+        assert_eq!(shop.value_by_name().unwrap_err(), mismatch);
+        // Reports that leave the other currency out still work
+        assert!(shop.value_at_risk(7).is_ok());
+    }
+
+    #[test]
+    fn test_csv_and_table_output() {
+        let report = shop().value_by_name().unwrap();
+
+        assert_eq!(
+            report.to_csv(),
+            "label,units,value,currency\n\
+             Leite,10,1.70,EUR\n\
+             \"Pão, caseiro\",2,2.30,EUR\n\
+             Total,12,4.00,EUR\n"
+        );
+        assert_eq!(
+            report.to_table(),
+            "Stock value by product\n\
+             Label         Units   Value\n\
+             Leite            10  1,70 €\n\
+             Pão, caseiro      2  2,30 €\n\
+             ---------------------------\n\
+             Total            12  4,00 €\n"
+        );
+    }
+}